@@ -1,9 +1,10 @@
 #![no_std]
 #![no_main]
 use embassy_executor::Spawner;
-use embassy_nrf::{config::Config, ipc::{self, Ipc, IpcChannel}, pac, peripherals};
+use embassy_nrf::{config::Config, ipc::{Ipc, IpcChannel}, pac, peripherals};
 use embassy_time::Delay;
-use icmsg::{IcMsg, Notifier, WaitForNotify};
+use icmsg::IcMsg;
+use icmsg_nrf::IpcWaiter;
 use rtt_target::rprintln;
 use {
     rtt_target::rtt_init_print,
@@ -26,18 +27,17 @@ mod icmsg_config {
     pub const ALIGN: usize = 4;
     pub fn get_icmsg_config() -> icmsg::MemoryConfig {
         unsafe {
-            let send_buffer_len = (&raw const __icmsg_tx_end)
-                .byte_offset_from(&raw const __icmsg_tx_start) as u32
-                - size_of::<icmsg::transport::SharedMemoryRegionHeader<ALIGN>>() as u32;
-            let recv_buffer_len = (&raw const __icmsg_rx_end)
-                .byte_offset_from(&raw const __icmsg_rx_start) as u32
-                - size_of::<icmsg::transport::SharedMemoryRegionHeader<ALIGN>>() as u32;
-            icmsg::MemoryConfig {
-                send_region: (&raw mut __icmsg_tx_start).cast(),
-                recv_region: (&raw mut __icmsg_rx_start).cast(),
-                send_buffer_len,
-                recv_buffer_len,
-            }
+            let send_region_len =
+                (&raw const __icmsg_tx_end).byte_offset_from(&raw const __icmsg_tx_start) as usize;
+            let recv_region_len =
+                (&raw const __icmsg_rx_end).byte_offset_from(&raw const __icmsg_rx_start) as usize;
+            icmsg::MemoryConfig::from_regions::<ALIGN>(
+                (&raw mut __icmsg_tx_start).cast(),
+                send_region_len,
+                (&raw mut __icmsg_rx_start).cast(),
+                recv_region_len,
+            )
+            .unwrap()
         }
     }
 }
@@ -54,17 +54,17 @@ async fn main(spawner: Spawner) {
 
     rprintln!("Hello, world!");
 
-    let mut ipc = Ipc::new(p.IPC, Irqs);
-    ipc.event0.configure_trigger([IpcChannel::Channel0]);
-    ipc.event0.configure_wait([IpcChannel::Channel1]);
+    let ipc = Ipc::new(p.IPC, Irqs);
+    let (notifier, waiter) =
+        icmsg_nrf::configure(ipc.event0, IpcChannel::Channel0, IpcChannel::Channel1);
 
     let icmsg_config = icmsg_config::get_icmsg_config();
     rprintln!("{:?}", icmsg_config);
     let icmsg = unsafe {
         IcMsg::<_, _, { icmsg_config::ALIGN }>::init(
             icmsg_config::get_icmsg_config(),
-            IpcNotify { trigger: ipc.event0.trigger_handle() },
-            IpcWait { event: ipc.event0 },
+            notifier,
+            waiter,
             Delay,
         ).await
     };
@@ -100,7 +100,7 @@ async fn main(spawner: Spawner) {
 }
 
 #[embassy_executor::task]
-async fn receive(mut recv: icmsg::Receiver<IpcWait<'static>, { icmsg_config::ALIGN }>) {
+async fn receive(mut recv: icmsg::Receiver<IpcWaiter<'static>, { icmsg_config::ALIGN }>) {
     let mut buf = [0; 128];
     loop {
         let n = match recv.recv(&mut buf).await {
@@ -113,23 +113,3 @@ async fn receive(mut recv: icmsg::Receiver<IpcWait<'static>, { icmsg_config::ALI
         rprintln!("Received {} bytes: {:x?}", n, &buf[..n]);
     }
 }
-
-struct IpcNotify<'d> {
-    trigger: ipc::EventTrigger<'d>,
-}
-
-struct IpcWait<'d> {
-    event: ipc::Event<'d>,
-}
-
-impl Notifier for IpcNotify<'_> {
-    fn notify(&mut self) {
-        self.trigger.trigger();
-    }
-}
-
-impl WaitForNotify for IpcWait<'_> {
-    fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
-        self.event.wait()
-    }
-}
@@ -3,21 +3,17 @@
 
 mod ble_bas_peripheral_bonding;
 mod init;
-mod transport;
-mod uninit_write_buf;
 
-use crate::transport::MyTransport;
 use bt_hci::controller::ExternalController;
-use defmt::Debug2Format;
 use embassy_embedded_hal::adapter::BlockingAsync;
 use embassy_executor::Spawner;
 use embassy_nrf::{
-    ipc::{self, Ipc, IpcChannel},
+    ipc::{Ipc, IpcChannel},
     peripherals,
 };
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_time::Delay;
-use icmsg::{IcMsg, Notifier, WaitForNotify};
+use icmsg::{IcMsg, hci::HciTransport};
 
 use {defmt_rtt as _, panic_probe as _};
 
@@ -36,18 +32,17 @@ mod icmsg_config {
     pub const ALIGN: usize = 4;
     pub fn get_icmsg_config() -> icmsg::MemoryConfig {
         unsafe {
-            let send_buffer_len =
-                (&raw const __icmsg_tx_end).byte_offset_from(&raw const __icmsg_tx_start) as u32
-                    - size_of::<icmsg::transport::SharedMemoryRegionHeader<ALIGN>>() as u32;
-            let recv_buffer_len =
-                (&raw const __icmsg_rx_end).byte_offset_from(&raw const __icmsg_rx_start) as u32
-                    - size_of::<icmsg::transport::SharedMemoryRegionHeader<ALIGN>>() as u32;
-            icmsg::MemoryConfig {
-                send_region: (&raw mut __icmsg_tx_start).cast(),
-                recv_region: (&raw mut __icmsg_rx_start).cast(),
-                send_buffer_len,
-                recv_buffer_len,
-            }
+            let send_region_len =
+                (&raw const __icmsg_tx_end).byte_offset_from(&raw const __icmsg_tx_start) as usize;
+            let recv_region_len =
+                (&raw const __icmsg_rx_end).byte_offset_from(&raw const __icmsg_rx_start) as usize;
+            icmsg::MemoryConfig::from_regions::<ALIGN>(
+                (&raw mut __icmsg_tx_start).cast(),
+                send_region_len,
+                (&raw mut __icmsg_rx_start).cast(),
+                recv_region_len,
+            )
+            .unwrap()
         }
     }
 }
@@ -115,26 +110,24 @@ async fn main(_spawner: Spawner) {
         grant_spu(Some(0));
     }
 
-    let mut ipc = Ipc::new(p.IPC, Irqs);
-    ipc.event0.configure_trigger([IpcChannel::Channel1]);
-    ipc.event0.configure_wait([IpcChannel::Channel0]);
+    let ipc = Ipc::new(p.IPC, Irqs);
+    let (notifier, waiter) =
+        icmsg_nrf::configure(ipc.event0, IpcChannel::Channel1, IpcChannel::Channel0);
 
     let icmsg_config = icmsg_config::get_icmsg_config();
-    defmt::info!("{:?}", Debug2Format(&icmsg_config));
+    defmt::info!("{}", icmsg_config);
     let icmsg = unsafe {
         IcMsg::<_, _, { icmsg_config::ALIGN }>::init(
             icmsg_config::get_icmsg_config(),
-            IpcNotify {
-                trigger: ipc.event0.trigger_handle(),
-            },
-            IpcWait { event: ipc.event0 },
+            notifier,
+            waiter,
             Delay,
         )
         .await
     };
     let icmsg = match icmsg {
         Err(e) => {
-            defmt::error!("error: {:?}", Debug2Format(&e));
+            defmt::error!("error: {}", e);
             return;
         }
         Ok(icmsg) => {
@@ -145,29 +138,10 @@ async fn main(_spawner: Spawner) {
 
     let (send, recv) = icmsg.split();
 
-    let driver: MyTransport<NoopRawMutex, _, _> = MyTransport::new(recv, send);
+    let driver: HciTransport<NoopRawMutex, _, _, { icmsg_config::ALIGN }, 1024> =
+        HciTransport::new(recv, send);
     let controller: ExternalController<_, 10> = ExternalController::new(driver);
 
     let mut nvmc = BlockingAsync::new(embassy_nrf::nvmc::Nvmc::new(p.NVMC));
     ble_bas_peripheral_bonding::run(controller, &mut nvmc).await
 }
-
-struct IpcNotify<'d> {
-    trigger: ipc::EventTrigger<'d>,
-}
-
-struct IpcWait<'d> {
-    event: ipc::Event<'d>,
-}
-
-impl Notifier for IpcNotify<'_> {
-    fn notify(&mut self) {
-        self.trigger.trigger();
-    }
-}
-
-impl WaitForNotify for IpcWait<'_> {
-    fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
-        self.event.wait()
-    }
-}
@@ -101,11 +101,11 @@ macro_rules! dispatch_cmd {
 }
 pub(crate) use dispatch_cmd;
 
-use crate::IpcNotify;
+use icmsg_nrf::IpcNotifier;
 use crate::icmsg_config::ALIGN;
 
 pub async fn exec_cmd_by_opcode<'d, E>(
-	send: &'static Mutex<NoopRawMutex, RefCell<icmsg::Sender<IpcNotify<'static>, ALIGN>>>,
+	send: &'static Mutex<NoopRawMutex, RefCell<icmsg::Sender<IpcNotifier<'static>, ALIGN>>>,
     ctrl: &crate::sdc::SoftdeviceController<'d>,
     opcode: bt_hci::cmd::Opcode,
     payload: &[u8],
@@ -10,18 +10,19 @@ use crate::{cmd_dispatch::exec_cmd_by_opcode, icmsg_config::ALIGN};
 use bt_hci::{
     cmd::{self, Opcode, OpcodeGroup}, data::{AclPacketHeader, IsoPacketHeader, SyncPacketHeader}, event::EventPacketHeader, param, FromHciBytes, FromHciBytesError, PacketKind
 };
-use defmt::{Debug2Format, unwrap};
+use defmt::unwrap;
 use embassy_executor::Spawner;
 use embassy_nrf::{
     config::Config,
-    ipc::{self, Ipc, IpcChannel},
+    ipc::{Ipc, IpcChannel},
     mode::Async,
     peripherals::{self, RNG},
     rng::{self, Rng},
 };
 use embassy_sync::{blocking_mutex::{raw::NoopRawMutex, Mutex}};
 use embassy_time::Delay;
-use icmsg::{IcMsg, Notifier, WaitForNotify};
+use icmsg::IcMsg;
+use icmsg_nrf::{IpcNotifier, IpcWaiter};
 use nrf_sdc::{self as sdc, mpsl};
 use sdc::mpsl::MultiprotocolServiceLayer;
 use static_cell::StaticCell;
@@ -49,18 +50,17 @@ mod icmsg_config {
     pub const ALIGN: usize = 4;
     pub fn get_icmsg_config() -> icmsg::MemoryConfig {
         unsafe {
-            let send_buffer_len =
-                (&raw const __icmsg_tx_end).byte_offset_from(&raw const __icmsg_tx_start) as u32
-                    - size_of::<icmsg::transport::SharedMemoryRegionHeader<ALIGN>>() as u32;
-            let recv_buffer_len =
-                (&raw const __icmsg_rx_end).byte_offset_from(&raw const __icmsg_rx_start) as u32
-                    - size_of::<icmsg::transport::SharedMemoryRegionHeader<ALIGN>>() as u32;
-            icmsg::MemoryConfig {
-                send_region: (&raw mut __icmsg_tx_start).cast(),
-                recv_region: (&raw mut __icmsg_rx_start).cast(),
-                send_buffer_len,
-                recv_buffer_len,
-            }
+            let send_region_len =
+                (&raw const __icmsg_tx_end).byte_offset_from(&raw const __icmsg_tx_start) as usize;
+            let recv_region_len =
+                (&raw const __icmsg_rx_end).byte_offset_from(&raw const __icmsg_rx_start) as usize;
+            icmsg::MemoryConfig::from_regions::<ALIGN>(
+                (&raw mut __icmsg_tx_start).cast(),
+                send_region_len,
+                (&raw mut __icmsg_rx_start).cast(),
+                recv_region_len,
+            )
+            .unwrap()
         }
     }
 }
@@ -103,26 +103,24 @@ async fn main(spawner: Spawner) {
     // give myself a second to attach without panic. uncomment for debug
     // embassy_time::Timer::after_secs(3).await;
 
-    let mut ipc = Ipc::new(p.IPC, Irqs);
-    ipc.event0.configure_trigger([IpcChannel::Channel0]);
-    ipc.event0.configure_wait([IpcChannel::Channel1]);
+    let ipc = Ipc::new(p.IPC, Irqs);
+    let (notifier, waiter) =
+        icmsg_nrf::configure(ipc.event0, IpcChannel::Channel0, IpcChannel::Channel1);
 
     let icmsg_config = icmsg_config::get_icmsg_config();
-    defmt::info!("{:?}", Debug2Format(&icmsg_config));
+    defmt::info!("{}", icmsg_config);
     let icmsg = unsafe {
         IcMsg::<_, _, { icmsg_config::ALIGN }>::init(
             icmsg_config::get_icmsg_config(),
-            IpcNotify {
-                trigger: ipc.event0.trigger_handle(),
-            },
-            IpcWait { event: ipc.event0 },
+            notifier,
+            waiter,
             Delay,
         )
         .await
     };
     let icmsg = match icmsg {
         Err(e) => {
-            defmt::error!("error: {:?}", Debug2Format(&e));
+            defmt::error!("error: {}", e);
             return;
         }
         Ok(icmsg) => {
@@ -163,25 +161,19 @@ async fn main(spawner: Spawner) {
 
     let (send, recv) = icmsg.split();
 
-    static SEND: StaticCell<Mutex<NoopRawMutex, RefCell<icmsg::Sender<IpcNotify<'static>, ALIGN>>>> = StaticCell::new();
+    static SEND: StaticCell<Mutex<NoopRawMutex, RefCell<icmsg::Sender<IpcNotifier<'static>, ALIGN>>>> = StaticCell::new();
     let send = SEND.init(Mutex::new(RefCell::new(send)));
     spawner.must_spawn(receive_task(send, recv, sdc));
 
-    const OUT_MAX: usize = nrf_sdc::raw::HCI_MSG_BUFFER_MAX_SIZE as usize + 1;
-    let mut buffer = [0u8; OUT_MAX];
+    let mut buffer = [0u8; nrf_sdc::raw::HCI_MSG_BUFFER_MAX_SIZE as usize];
 
     loop {
-        let pkt_data = &mut buffer[1..];
-        let kind = unwrap!(sdc.hci_get(pkt_data).await);
-        let used = unwrap!(packet_len(kind, pkt_data));
-
-        assert!(1 + used <= buffer.len());
+        let kind = unwrap!(sdc.hci_get(&mut buffer).await);
+        let used = unwrap!(packet_len(kind, &buffer));
+        let pkt_data = &buffer[..used];
 
-        buffer[0] = kind as u8;
-        let n = 1 + used;
-
-        defmt::trace!("send {}: {=[u8]:x}", n, &buffer[..n]);
-        send.lock(|x| x.borrow_mut().send(&buffer[..n]).unwrap());
+        defmt::trace!("send {}: {=u8:x} {=[u8]:x}", 1 + used, kind as u8, pkt_data);
+        send.lock(|x| x.borrow_mut().send_vectored(&[&[kind as u8], pkt_data]).unwrap());
     }
 }
 
@@ -229,7 +221,7 @@ macro_rules! io {
 }
 
 async fn exec_h4_to_sdc(
-	send: &'static Mutex<NoopRawMutex, RefCell<icmsg::Sender<IpcNotify<'static>, ALIGN>>>,
+	send: &'static Mutex<NoopRawMutex, RefCell<icmsg::Sender<IpcNotifier<'static>, ALIGN>>>,
     sdc: &sdc::SoftdeviceController<'static>,
     pkt: &[u8],
 ) -> Result<(), CmdErr> {
@@ -249,8 +241,8 @@ async fn exec_h4_to_sdc(
 
 #[embassy_executor::task]
 async fn receive_task(
-	send: &'static Mutex<NoopRawMutex, RefCell<icmsg::Sender<IpcNotify<'static>, ALIGN>>>,
-    mut recv: icmsg::Receiver<IpcWait<'static>, ALIGN>,
+	send: &'static Mutex<NoopRawMutex, RefCell<icmsg::Sender<IpcNotifier<'static>, ALIGN>>>,
+    mut recv: icmsg::Receiver<IpcWaiter<'static>, ALIGN>,
     sdc: &'static sdc::SoftdeviceController<'static>,
 ) {
     let mut buf = [0; nrf_sdc::raw::HCI_MSG_BUFFER_MAX_SIZE as usize];
@@ -258,7 +250,7 @@ async fn receive_task(
         let n = match recv.recv(&mut buf).await {
             Ok(n) => n,
             Err(e) => {
-                defmt::error!("Recv error: {:?}", defmt::Debug2Format(&e));
+                defmt::error!("Recv error: {}", e);
                 return;
             }
         };
@@ -269,23 +261,3 @@ async fn receive_task(
 		}
     }
 }
-
-struct IpcNotify<'d> {
-    trigger: ipc::EventTrigger<'d>,
-}
-
-struct IpcWait<'d> {
-    event: ipc::Event<'d>,
-}
-
-impl Notifier for IpcNotify<'_> {
-    fn notify(&mut self) {
-        self.trigger.trigger();
-    }
-}
-
-impl WaitForNotify for IpcWait<'_> {
-    fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
-        self.event.wait()
-    }
-}
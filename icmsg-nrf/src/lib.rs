@@ -0,0 +1,78 @@
+//! [`Notifier`] and [`WaitForNotify`] for the nRF5340's IPC peripheral, using `embassy-nrf`.
+//!
+//! `embassy-nrf` only has an IPC driver when one of its nRF5340 chip features is enabled. Select
+//! the core with the `nrf5340-app-s`, `nrf5340-app-ns` or `nrf5340-net` feature of this crate,
+//! or with the same feature of `embassy-nrf` in the application's own `Cargo.toml`. This crate is
+//! kept apart from `icmsg` so that `icmsg` builds with all of its features, which can't pick a
+//! core. It is written against `embassy-nrf` 0.8; the IPC API differs in other releases.
+//!
+//! ```ignore
+//! let ipc = Ipc::new(p.IPC, Irqs);
+//! let (notifier, waiter) =
+//!     icmsg_nrf::configure(ipc.event0, IpcChannel::Channel0, IpcChannel::Channel1);
+//! let icmsg = unsafe { IcMsg::<_, _, ALIGN>::init(config, notifier, waiter, Delay).await };
+//! ```
+
+#![no_std]
+
+use embassy_nrf::ipc::{Event, EventTrigger, IpcChannel};
+use icmsg::{Notifier, WaitForNotify};
+
+/// Configure `event` to trigger on channel `send` and to wait on channel `recv`, and return the
+/// [`Notifier`] and [`WaitForNotify`] for it.
+///
+/// The other core has to use the same channels the other way around.
+pub fn configure<'d>(
+    mut event: Event<'d>,
+    send: IpcChannel,
+    recv: IpcChannel,
+) -> (IpcNotifier<'d>, IpcWaiter<'d>) {
+    event.configure_trigger([send]);
+    event.configure_wait([recv]);
+    (
+        IpcNotifier::new(event.trigger_handle()),
+        IpcWaiter::new(event),
+    )
+}
+
+/// Notifies the other core by triggering an IPC event.
+pub struct IpcNotifier<'d> {
+    trigger: EventTrigger<'d>,
+}
+
+impl<'d> IpcNotifier<'d> {
+    pub fn new(trigger: EventTrigger<'d>) -> Self {
+        Self { trigger }
+    }
+
+    pub fn into_inner(self) -> EventTrigger<'d> {
+        self.trigger
+    }
+}
+
+impl Notifier for IpcNotifier<'_> {
+    fn notify(&mut self) {
+        self.trigger.trigger();
+    }
+}
+
+/// Waits for the other core to trigger an IPC event.
+pub struct IpcWaiter<'d> {
+    event: Event<'d>,
+}
+
+impl<'d> IpcWaiter<'d> {
+    pub fn new(event: Event<'d>) -> Self {
+        Self { event }
+    }
+
+    pub fn into_inner(self) -> Event<'d> {
+        self.event
+    }
+}
+
+impl WaitForNotify for IpcWaiter<'_> {
+    fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
+        self.event.wait()
+    }
+}
@@ -0,0 +1,168 @@
+//! Blocking send and receive for cores without an async executor, e.g. a bare-metal superloop.
+//!
+//! Instead of a [`WaitForNotify`], everything here takes an `idle` hook that is called between
+//! attempts, e.g. to execute `wfe` or to sleep for a while. Calling nothing at all spins at full
+//! speed.
+
+use core::task::Poll;
+
+use crate::{
+    Bonder, InitError, Notifier, Receiver, RecvState, Sender, WaitForNotify,
+    transport::{self, RecvError, SendError},
+};
+
+/// Drive a [`Bonder`] to completion and split the channel into its blocking halves.
+///
+/// `poll_notified` should return `true` once a notification from the other side has been received
+/// since the last call. `idle` is called between polls, and should take about
+/// [`retry_interval_ms`][crate::BondingConfig::retry_interval_ms] for the bonding timeout to be
+/// accurate.
+pub fn bond<M, const ALIGN: usize>(
+    mut bonder: Bonder<M, ALIGN>,
+    poll_notified: impl FnMut() -> bool,
+    idle: impl FnMut(),
+) -> Result<(BlockingSender<M, ALIGN>, BlockingReceiver<ALIGN>), InitError>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    let (sender, state) = drive(|notified| bonder.poll_split(notified), poll_notified, idle)?;
+    Ok((BlockingSender::new(sender), BlockingReceiver { state }))
+}
+
+/// Call `poll` with the result of `poll_notified` until it is ready, calling `idle` in between.
+pub(crate) fn drive<T>(
+    mut poll: impl FnMut(bool) -> Poll<Result<T, InitError>>,
+    mut poll_notified: impl FnMut() -> bool,
+    mut idle: impl FnMut(),
+) -> Result<T, InitError> {
+    loop {
+        match poll(poll_notified()) {
+            Poll::Pending => idle(),
+            Poll::Ready(r) => return r,
+        }
+    }
+}
+
+/// A [`Sender`] that waits for space in the ring.
+pub struct BlockingSender<M, const ALIGN: usize>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    sender: Sender<M, ALIGN>,
+}
+
+impl<M, const ALIGN: usize> BlockingSender<M, ALIGN>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn new(sender: Sender<M, ALIGN>) -> Self {
+        Self { sender }
+    }
+
+    /// Send a message, calling `idle` until there is enough space in the ring.
+    ///
+    /// Errors other than [`SendError::InsufficientCapacity`] are returned right away.
+    pub fn send(&mut self, msg: &[u8], mut idle: impl FnMut()) -> Result<(), SendError> {
+        loop {
+            match self.sender.send(msg) {
+                Err(SendError::InsufficientCapacity) => idle(),
+                r => return r,
+            }
+        }
+    }
+
+    /// Send a message that may be larger than the ring, split into fragments, calling `idle`
+    /// whenever the ring is full. See [`transport::Sender::send_fragmented`].
+    pub fn send_fragmented(&mut self, msg: &[u8], mut idle: impl FnMut()) -> Result<(), SendError> {
+        let mut sent = 0;
+        loop {
+            match self.sender.send_fragmented(msg, &mut sent) {
+                Err(SendError::InsufficientCapacity) => idle(),
+                r => return r,
+            }
+        }
+    }
+
+    /// The underlying [`Sender`], for non-blocking operations.
+    pub fn inner(&mut self) -> &mut Sender<M, ALIGN> {
+        &mut self.sender
+    }
+
+    pub fn into_inner(self) -> Sender<M, ALIGN> {
+        self.sender
+    }
+}
+
+/// A [`Receiver`] that waits for messages by calling an `idle` hook instead of a waiter.
+///
+/// Like [`Receiver`], it skips the other side's bonding messages and reports its teardown as
+/// [`RecvError::PeerClosed`] and its restart as [`RecvError::SessionLost`].
+pub struct BlockingReceiver<const ALIGN: usize>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    state: RecvState<ALIGN>,
+}
+
+impl<const ALIGN: usize> BlockingReceiver<ALIGN>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Wrap the receiving half of a transport that was not bonded with a session ID.
+    pub fn from_transport(receiver: transport::Receiver<ALIGN>) -> Self {
+        Self {
+            state: RecvState::new(receiver, None, &crate::MAGIC),
+        }
+    }
+
+    /// Try to receive a message without waiting. See [`Receiver::try_recv`].
+    pub fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, RecvError> {
+        self.state.try_recv(msg)
+    }
+
+    /// Receive a message, calling `idle` until one is available. On success, returns the size of
+    /// the message.
+    ///
+    /// Errors other than [`RecvError::Empty`] are returned right away.
+    pub fn recv(&mut self, msg: &mut [u8], mut idle: impl FnMut()) -> Result<usize, RecvError> {
+        loop {
+            match self.state.try_recv(msg) {
+                Err(RecvError::Empty) => idle(),
+                r => return r,
+            }
+        }
+    }
+
+    /// Receive a message sent with [`BlockingSender::send_fragmented`], calling `idle` until all of
+    /// its fragments have arrived. See [`transport::Receiver::try_recv_reassembled`].
+    pub fn recv_reassembled(
+        &mut self,
+        msg: &mut [u8],
+        mut idle: impl FnMut(),
+    ) -> Result<usize, RecvError> {
+        let mut received = 0;
+        loop {
+            match self.state.try_recv_reassembled(msg, &mut received) {
+                Err(RecvError::Empty) => idle(),
+                r => return r,
+            }
+        }
+    }
+
+    /// The underlying low-level receiver.
+    pub fn transport_mut(&mut self) -> &mut transport::Receiver<ALIGN> {
+        &mut self.state.transport
+    }
+
+    /// Turn this into an async [`Receiver`] that waits with `waiter`, e.g. once an executor is
+    /// running.
+    pub fn into_receiver<W: WaitForNotify>(self, waiter: W) -> Receiver<W, ALIGN> {
+        Receiver {
+            state: self.state,
+            waiter,
+        }
+    }
+}
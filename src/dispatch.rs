@@ -0,0 +1,137 @@
+//! Sharing one notification between several channels.
+//!
+//! Each [`Receiver`][crate::Receiver] owns its [`WaitForNotify`], so two channels can't wait on
+//! the same event directly. A [`NotifyDispatcher`] owns the event instead and hands out a
+//! [`DispatchedWaiter`] for each channel. Its runner future waits on the event and wakes every
+//! waiter on every notification, since it can't tell which channel has data. A channel that was
+//! woken for nothing just finds its ring empty and waits again.
+//!
+//! ```ignore
+//! let mut dispatcher = NotifyDispatcher::<_, 2>::new(waiter);
+//! let (mut runner, [hci_waiter, log_waiter]) = dispatcher.split();
+//! // Run `runner.run()` alongside the channels, e.g. with `join`.
+//! ```
+
+use core::{
+    future::poll_fn,
+    pin::pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+
+use atomic_waker::AtomicWaker;
+
+use crate::{PollWaitForNotify, WaitForNotify};
+
+/// Fans the notifications of one [`WaitForNotify`] out to `N` [`DispatchedWaiter`]s.
+pub struct NotifyDispatcher<W, const N: usize> {
+    waiter: W,
+    slots: [Slot; N],
+}
+
+impl<W: WaitForNotify, const N: usize> NotifyDispatcher<W, N> {
+    pub const fn new(waiter: W) -> Self {
+        Self {
+            waiter,
+            slots: [const { Slot::new() }; N],
+        }
+    }
+
+    /// Get the runner that waits on the underlying waiter, and the `N` waiters it wakes.
+    pub fn split(&mut self) -> (DispatchRunner<'_, W, N>, [DispatchedWaiter<'_>; N]) {
+        let runner = DispatchRunner {
+            waiter: &mut self.waiter,
+            slots: &self.slots,
+        };
+        (
+            runner,
+            self.slots.each_ref().map(|slot| DispatchedWaiter { slot }),
+        )
+    }
+
+    pub fn into_inner(self) -> W {
+        self.waiter
+    }
+}
+
+/// Waits for notifications and passes them on to the [`DispatchedWaiter`]s, see
+/// [`NotifyDispatcher::split`].
+pub struct DispatchRunner<'a, W, const N: usize> {
+    waiter: &'a mut W,
+    slots: &'a [Slot; N],
+}
+
+impl<W: WaitForNotify, const N: usize> DispatchRunner<'_, W, N> {
+    /// Wake every [`DispatchedWaiter`] on every notification. Never completes, so it has to run
+    /// alongside the channels, e.g. in its own task or with `join`.
+    pub async fn run(&mut self) {
+        let mut notified = false;
+        loop {
+            // Wait for the next notification before passing on the last one, so one that arrives
+            // while the channels handle the last one is not missed.
+            let mut wait_fut = pin!(self.waiter.wait_for_notify());
+            let r = crate::poll::poll(wait_fut.as_mut()).await;
+
+            if notified {
+                for slot in self.slots {
+                    slot.notify();
+                }
+            }
+            if r.is_pending() {
+                wait_fut.await;
+            }
+            notified = true;
+        }
+    }
+}
+
+/// One of the waiters of a [`NotifyDispatcher`]. Notifications latch: one that arrives while
+/// nobody is waiting completes the next wait immediately.
+pub struct DispatchedWaiter<'a> {
+    slot: &'a Slot,
+}
+
+impl WaitForNotify for DispatchedWaiter<'_> {
+    fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
+        let slot = self.slot;
+        poll_fn(move |cx| slot.poll_wait(cx))
+    }
+}
+
+impl PollWaitForNotify for DispatchedWaiter<'_> {
+    fn poll_wait_for_notify(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.slot.poll_wait(cx)
+    }
+}
+
+struct Slot {
+    waker: AtomicWaker,
+    notified: AtomicBool,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            notified: AtomicBool::new(false),
+        }
+    }
+
+    fn notify(&self) {
+        self.notified.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    fn poll_wait(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.notified.swap(false, Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        self.waker.register(cx.waker());
+        // Check again in case `notify` was called before the waker was registered.
+        if self.notified.swap(false, Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Flow control for setups with a separate notification channel in each direction.
+//!
+//! [`IcMsg`] uses one event to say "I sent you data". Since the other side does not notify when
+//! it reads messages, a full ring can only be waited on by polling. If the hardware has a second
+//! event, the receiver can use it to say "I made room", and the sender can wait on it instead.
+//!
+//! [`IcMsg::split_duplex`] splits a channel into a [`DuplexSender`] that waits for room when the
+//! ring is full, and a [`DuplexReceiver`] that notifies the other side after each message it
+//! receives. Both sides have to use them, or at least agree on what the second event means.
+
+use core::pin::pin;
+
+use crate::{
+    IcMsg, Notifier, Receiver, Sender, WaitForNotify,
+    transport::{RecvError, SendError},
+};
+
+impl<M, W, const ALIGN: usize> IcMsg<M, W, ALIGN>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Split into a sender that waits on `space` for the other side to make room, and a receiver
+    /// that notifies `drained` when it has made room.
+    pub fn split_duplex<S, D>(
+        self,
+        space: S,
+        drained: D,
+    ) -> (DuplexSender<M, S, ALIGN>, DuplexReceiver<W, D, ALIGN>)
+    where
+        S: WaitForNotify,
+        D: Notifier,
+    {
+        let (sender, receiver) = self.split();
+        (
+            DuplexSender::new(sender, space),
+            DuplexReceiver::new(receiver, drained),
+        )
+    }
+}
+
+/// A [`Sender`] that waits for the other side to make room instead of failing with
+/// [`SendError::InsufficientCapacity`].
+pub struct DuplexSender<M, S, const ALIGN: usize>
+where
+    M: Notifier,
+    S: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    sender: Sender<M, ALIGN>,
+    space: S,
+}
+
+impl<M, S, const ALIGN: usize> DuplexSender<M, S, ALIGN>
+where
+    M: Notifier,
+    S: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn new(sender: Sender<M, ALIGN>, space: S) -> Self {
+        Self { sender, space }
+    }
+
+    /// Send a message, waiting for room in the ring if it is full.
+    pub async fn send(&mut self, msg: &[u8]) -> Result<(), SendError> {
+        loop {
+            // Let the waiter register its waker before attempting to send
+            let mut wait_fut = pin!(self.space.wait_for_notify());
+            let r = crate::poll::poll(wait_fut.as_mut()).await;
+
+            match self.sender.send(msg) {
+                Err(SendError::InsufficientCapacity) => {
+                    if r.is_pending() {
+                        wait_fut.await;
+                    }
+                }
+                r => return r,
+            }
+        }
+    }
+
+    /// Send a message if there is room. See [`Sender::send`].
+    pub fn try_send(&mut self, msg: &[u8]) -> Result<(), SendError> {
+        self.sender.send(msg)
+    }
+
+    pub fn into_inner(self) -> (Sender<M, ALIGN>, S) {
+        (self.sender, self.space)
+    }
+}
+
+/// A [`Receiver`] that notifies the other side after each message it receives, so that a
+/// [`DuplexSender`] waiting for room wakes up.
+pub struct DuplexReceiver<W, D, const ALIGN: usize>
+where
+    W: WaitForNotify,
+    D: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    receiver: Receiver<W, ALIGN>,
+    drained: D,
+}
+
+impl<W, D, const ALIGN: usize> DuplexReceiver<W, D, ALIGN>
+where
+    W: WaitForNotify,
+    D: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn new(receiver: Receiver<W, ALIGN>, drained: D) -> Self {
+        Self { receiver, drained }
+    }
+
+    /// Try to receive a message. See [`Receiver::try_recv`].
+    pub fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, RecvError> {
+        let n = self.receiver.try_recv(msg)?;
+        self.drained.notify();
+        Ok(n)
+    }
+
+    /// Wait for and receive a message. See [`Receiver::recv`].
+    pub async fn recv(&mut self, msg: &mut [u8]) -> Result<usize, RecvError> {
+        let n = self.receiver.recv(msg).await?;
+        self.drained.notify();
+        Ok(n)
+    }
+
+    pub fn into_inner(self) -> (Receiver<W, ALIGN>, D) {
+        (self.receiver, self.drained)
+    }
+}
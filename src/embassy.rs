@@ -0,0 +1,36 @@
+//! [`Notifier`] and [`WaitForNotify`] for `embassy_sync`'s [`Signal`], for when the other side
+//! is another task on the same core, or in tests.
+//!
+//! A `&Signal<M, ()>` notifies by signaling and waits by taking the signal. Signals latch, so a
+//! notification that arrives before the wait starts is not lost.
+//!
+//! There is no [`WaitForNotify`] for `embassy_sync`'s `AtomicWaker`, since it only wakes a task
+//! that is already waiting and does not record the notification otherwise.
+
+use core::{
+    pin::pin,
+    task::{Context, Poll},
+};
+
+use embassy_sync::{blocking_mutex::raw::RawMutex, signal::Signal};
+
+use crate::{Notifier, PollWaitForNotify, WaitForNotify};
+
+impl<M: RawMutex> Notifier for &Signal<M, ()> {
+    fn notify(&mut self) {
+        self.signal(())
+    }
+}
+
+impl<M: RawMutex> WaitForNotify for &Signal<M, ()> {
+    fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
+        self.wait()
+    }
+}
+
+impl<M: RawMutex> PollWaitForNotify for &Signal<M, ()> {
+    fn poll_wait_for_notify(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        // The future holds no state of its own, so a new one can be polled every time.
+        pin!(self.wait()).poll(cx)
+    }
+}
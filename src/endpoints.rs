@@ -0,0 +1,248 @@
+//! Several logical channels, or endpoints, over one [`IcMsg`].
+//!
+//! Each message starts with a one byte endpoint ID. Receiving on one endpoint moves messages for
+//! the other endpoints out of the ring into their own queues, so that a slow endpoint does not
+//! hold up the others. If a queue is full, further messages for that endpoint are dropped and
+//! counted in [`Endpoints::dropped`].
+
+use crate::{
+    IcMsg, Notifier, RecvState, WaitForNotify,
+    transport::{self, RecvError, SendError},
+};
+
+/// `N` endpoints over one channel, each with a queue of up to `DEPTH` messages of up to `MAX`
+/// bytes.
+pub struct Endpoints<M, W, const ALIGN: usize, const N: usize, const MAX: usize, const DEPTH: usize>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    icmsg: IcMsg<M, W, ALIGN>,
+    queues: [Queue<MAX, DEPTH>; N],
+}
+
+impl<M, W, const ALIGN: usize, const N: usize, const MAX: usize, const DEPTH: usize>
+    Endpoints<M, W, ALIGN, N, MAX, DEPTH>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn new(icmsg: IcMsg<M, W, ALIGN>) -> Self {
+        const { assert!(N <= 256, "endpoint IDs are one byte") };
+        Self {
+            icmsg,
+            queues: core::array::from_fn(|_| Queue::new()),
+        }
+    }
+
+    /// A handle to endpoint `id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is not less than `N`.
+    pub fn endpoint(&mut self, id: u8) -> Endpoint<'_, M, W, ALIGN, N, MAX, DEPTH> {
+        assert!((id as usize) < N, "no such endpoint");
+        Endpoint {
+            endpoints: self,
+            id,
+        }
+    }
+
+    /// Send a message on endpoint `id`.
+    pub fn send(&mut self, id: u8, msg: &[u8]) -> Result<(), SendError> {
+        assert!((id as usize) < N, "no such endpoint");
+        self.icmsg.send_vectored(&[&[id], msg])
+    }
+
+    /// The size of the largest message that can be sent on an endpoint.
+    pub fn max_message_len(&self) -> usize {
+        self.icmsg.max_message_len() - 1
+    }
+
+    /// Try to receive a message on endpoint `id`. On success, returns the size of the message.
+    ///
+    /// Messages for other endpoints that are ahead of it in the ring are moved to their queues.
+    /// If the message doesn't fit in `msg`, this fails with [`RecvError::MessageTooBig`] and the
+    /// message is kept.
+    pub fn try_recv(&mut self, id: u8, msg: &mut [u8]) -> Result<usize, RecvError> {
+        try_recv(&mut self.queues, &mut self.icmsg.receiver.state, id, msg)
+    }
+
+    /// Wait for and receive a message on endpoint `id`. See [`try_recv`][Self::try_recv].
+    pub async fn recv(&mut self, id: u8, msg: &mut [u8]) -> Result<usize, RecvError> {
+        let receiver = &mut self.icmsg.receiver;
+        let queues = &mut self.queues;
+        transport::recv_with(&mut receiver.waiter, || {
+            try_recv(queues, &mut receiver.state, id, msg)
+        })
+        .await
+    }
+
+    /// The number of messages for endpoint `id` that were dropped because its queue was full,
+    /// or because they were bigger than `MAX`.
+    pub fn dropped(&self, id: u8) -> u32 {
+        self.queues[id as usize].dropped
+    }
+
+    pub fn into_inner(self) -> IcMsg<M, W, ALIGN> {
+        self.icmsg
+    }
+}
+
+fn try_recv<const ALIGN: usize, const MAX: usize, const DEPTH: usize>(
+    queues: &mut [Queue<MAX, DEPTH>],
+    state: &mut RecvState<ALIGN>,
+    id: u8,
+    msg: &mut [u8],
+) -> Result<usize, RecvError>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    assert!((id as usize) < queues.len(), "no such endpoint");
+    if let Some(r) = queues[id as usize].pop(msg) {
+        return r;
+    }
+    loop {
+        if let Some(n) = dispatch_next(queues, state, id, msg)? {
+            return Ok(n);
+        }
+    }
+}
+
+/// Receive the next message from the ring into `msg` if it is for endpoint `id`, or into its
+/// endpoint's queue otherwise. Messages without an endpoint ID, or for an endpoint that doesn't
+/// exist, are dropped.
+fn dispatch_next<const ALIGN: usize, const MAX: usize, const DEPTH: usize>(
+    queues: &mut [Queue<MAX, DEPTH>],
+    state: &mut RecvState<ALIGN>,
+    id: u8,
+    msg: &mut [u8],
+) -> Result<Option<usize>, RecvError>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    state.skip_control_messages()?;
+    let transport = &mut state.transport;
+    let packet = transport.next_packet()?;
+    let mut received = None;
+    if packet.len > 0 {
+        let mut target = [0];
+        transport.copy_packet(&packet, 0, &mut target);
+        let len = packet.len - 1;
+        if target[0] == id {
+            if len > msg.len() {
+                return Err(RecvError::MessageTooBig { required: len });
+            }
+            transport.copy_packet(&packet, 1, &mut msg[..len]);
+            received = Some(len);
+        } else if let Some(queue) = queues.get_mut(target[0] as usize) {
+            match queue.push(len) {
+                Some(slot) => transport.copy_packet(&packet, 1, slot),
+                None => queue.dropped = queue.dropped.wrapping_add(1),
+            }
+        }
+    }
+    transport.consume_packet(&packet);
+    state.read_offset = 0;
+    Ok(received)
+}
+
+/// A handle to one endpoint of [`Endpoints`].
+pub struct Endpoint<
+    'a,
+    M,
+    W,
+    const ALIGN: usize,
+    const N: usize,
+    const MAX: usize,
+    const DEPTH: usize,
+> where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    endpoints: &'a mut Endpoints<M, W, ALIGN, N, MAX, DEPTH>,
+    id: u8,
+}
+
+impl<M, W, const ALIGN: usize, const N: usize, const MAX: usize, const DEPTH: usize>
+    Endpoint<'_, M, W, ALIGN, N, MAX, DEPTH>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// See [`Endpoints::send`].
+    pub fn send(&mut self, msg: &[u8]) -> Result<(), SendError> {
+        self.endpoints.send(self.id, msg)
+    }
+
+    /// See [`Endpoints::try_recv`].
+    pub fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, RecvError> {
+        self.endpoints.try_recv(self.id, msg)
+    }
+
+    /// See [`Endpoints::recv`].
+    pub async fn recv(&mut self, msg: &mut [u8]) -> Result<usize, RecvError> {
+        self.endpoints.recv(self.id, msg).await
+    }
+
+    /// See [`Endpoints::dropped`].
+    pub fn dropped(&self) -> u32 {
+        self.endpoints.dropped(self.id)
+    }
+}
+
+/// A ring of up to `DEPTH` messages of up to `MAX` bytes.
+struct Queue<const MAX: usize, const DEPTH: usize> {
+    msgs: [[u8; MAX]; DEPTH],
+    lens: [usize; DEPTH],
+    head: usize,
+    len: usize,
+    dropped: u32,
+}
+
+impl<const MAX: usize, const DEPTH: usize> Queue<MAX, DEPTH> {
+    fn new() -> Self {
+        Self {
+            msgs: [[0; MAX]; DEPTH],
+            lens: [0; DEPTH],
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Add a message of `len` bytes and return the space to copy it to, or `None` if it doesn't
+    /// fit.
+    fn push(&mut self, len: usize) -> Option<&mut [u8]> {
+        if self.len == DEPTH || len > MAX {
+            return None;
+        }
+        let i = (self.head + self.len) % DEPTH;
+        self.len += 1;
+        self.lens[i] = len;
+        Some(&mut self.msgs[i][..len])
+    }
+
+    /// Copy the oldest message into `msg` and remove it, if there is one.
+    fn pop(&mut self, msg: &mut [u8]) -> Option<Result<usize, RecvError>> {
+        if self.len == 0 {
+            return None;
+        }
+        let len = self.lens[self.head];
+        if len > msg.len() {
+            return Some(Err(RecvError::MessageTooBig { required: len }));
+        }
+        msg[..len].copy_from_slice(&self.msgs[self.head][..len]);
+        self.head = (self.head + 1) % DEPTH;
+        self.len -= 1;
+        Some(Ok(len))
+    }
+}
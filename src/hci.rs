@@ -0,0 +1,156 @@
+//! A [`bt_hci_transport::Transport`] over ICMsg, for running a Bluetooth host on one core and the
+//! controller on the other.
+//!
+//! Each HCI packet is sent as one message, starting with the packet indicator byte as in the UART
+//! transport.
+
+use core::convert::Infallible;
+
+use bt_hci_transport::{
+    PacketKind, PacketToController, PacketToHost, ReadHciError, Transport, WithIndicator,
+};
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+use embedded_io_async::BufRead;
+
+use crate::{
+    Notifier, Receiver, Sender, WaitForNotify,
+    reader::ReceiverReader,
+    transport::{RecvError, SendError},
+};
+
+/// An HCI transport built from the two halves of an [`IcMsg`][crate::IcMsg] channel.
+///
+/// Packets are staged in buffers of `BUF` bytes, one for each direction, so `BUF` has to fit the
+/// largest packet plus its indicator byte. Received packets are taken out of the ring by a
+/// [`ReceiverReader`]. Bigger packets fail with [`Error::PacketTooBig`] when sending, and with
+/// [`RecvError::MessageTooBig`] when receiving, in which case the packet is dropped so that the
+/// next read can continue with the following one.
+pub struct HciTransport<M, W, N, const ALIGN: usize, const BUF: usize>
+where
+    M: RawMutex,
+    W: WaitForNotify,
+    N: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    reader: Mutex<M, ReceiverReader<W, ALIGN, BUF>>,
+    writer: Mutex<M, (Sender<N, ALIGN>, [u8; BUF])>,
+}
+
+impl<M, W, N, const ALIGN: usize, const BUF: usize> HciTransport<M, W, N, ALIGN, BUF>
+where
+    M: RawMutex,
+    W: WaitForNotify,
+    N: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn new(receiver: Receiver<W, ALIGN>, sender: Sender<N, ALIGN>) -> Self {
+        Self {
+            reader: Mutex::new(ReceiverReader::new(receiver)),
+            writer: Mutex::new((sender, [0; BUF])),
+        }
+    }
+
+    pub fn into_inner(self) -> (Receiver<W, ALIGN>, Sender<N, ALIGN>) {
+        (
+            self.reader.into_inner().into_inner(),
+            self.writer.into_inner().0,
+        )
+    }
+}
+
+impl<M, W, N, const ALIGN: usize, const BUF: usize> embedded_io::ErrorType
+    for HciTransport<M, W, N, ALIGN, BUF>
+where
+    M: RawMutex,
+    W: WaitForNotify,
+    N: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    type Error = Error;
+}
+
+impl<M, W, N, const ALIGN: usize, const BUF: usize> Transport for HciTransport<M, W, N, ALIGN, BUF>
+where
+    M: RawMutex,
+    W: WaitForNotify,
+    N: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    async fn read<'a, P: PacketToHost<'a>>(&self, rx: &'a mut [u8]) -> Result<P, Self::Error> {
+        let mut reader = self.reader.lock().await;
+        // Each message is one packet, which is parsed in place and then consumed as a whole.
+        let mut packet = reader.fill_buf().await?;
+        let len = packet.len();
+        let r = PacketKind::read(&mut packet).and_then(|kind| P::read_hci(kind, &mut packet, rx));
+        reader.consume(len);
+        Ok(r?)
+    }
+
+    async fn write<P: PacketToController>(&self, tx: &P) -> Result<(), Self::Error> {
+        let packet = WithIndicator::new(tx);
+        let len = packet.size();
+        let mut writer = self.writer.lock().await;
+        let (sender, buf) = &mut *writer;
+        let Some(mut dst) = buf.get_mut(..len) else {
+            return Err(Error::PacketTooBig);
+        };
+        packet
+            .write_hci(&mut dst)
+            .map_err(|_| Error::PacketTooBig)?;
+        Ok(sender.send(&buf[..len])?)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    Send(SendError),
+    Recv(RecvError),
+    /// A packet to send does not fit in the transport's buffer.
+    PacketTooBig,
+    /// A received message is not a valid HCI packet, or does not fit in the buffer passed to
+    /// [`Transport::read`].
+    InvalidPacket(ReadHciError<Infallible>),
+}
+
+impl From<SendError> for Error {
+    fn from(e: SendError) -> Self {
+        Self::Send(e)
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(e: RecvError) -> Self {
+        Self::Recv(e)
+    }
+}
+
+impl From<ReadHciError<Infallible>> for Error {
+    fn from(e: ReadHciError<Infallible>) -> Self {
+        Self::InvalidPacket(e)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Send(e) => write!(f, "send error: {e}"),
+            Error::Recv(e) => write!(f, "receive error: {e}"),
+            Error::PacketTooBig => write!(f, "packet too big"),
+            Error::InvalidPacket(e) => write!(f, "invalid packet: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Self::Send(e) => e.kind(),
+            Self::Recv(e) => e.kind(),
+            Self::PacketTooBig => embedded_io::ErrorKind::InvalidInput,
+            Self::InvalidPacket(e) => e.kind(),
+        }
+    }
+}
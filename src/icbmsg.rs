@@ -0,0 +1,445 @@
+//! A block-based backend in the style of [ICBMsg][1], for large messages.
+//!
+//! Message data is written to fixed-size blocks in a separate area of shared memory, and only a
+//! short descriptor of the blocks goes through the ICMsg ring. The receiver can read the data in
+//! place, and hands the blocks back with a release message once it is done with them.
+//!
+//! This is not wire compatible with Zephyr's ICBMsg yet: the descriptors and release messages
+//! are this crate's own, and there are no endpoints.
+//!
+//! [1]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icbmsg.html
+
+use core::{ops::Deref, pin::pin};
+
+use crate::{
+    IcMsg, Notifier, Receiver, RecvState, Sender, WaitForNotify,
+    transport::{RecvError, SendError},
+};
+
+/// `[DATA, first block, len as u16 LE]`: a message in the sender's blocks.
+const DATA: u8 = 0;
+/// `[RELEASE, bitmap of blocks as u32 LE]`: blocks the receiver is done with.
+const RELEASE: u8 = 1;
+
+/// An ICMsg channel with `N` blocks of `BLOCK` bytes in each direction.
+///
+/// A message takes as many consecutive blocks as it needs, so messages of up to `N * BLOCK`
+/// bytes can be sent no matter how small the ICMsg ring is. Blocks are freed when the other side
+/// releases them, which is noticed while sending and receiving.
+pub struct IcbMsg<M, W, const ALIGN: usize, const BLOCK: usize, const N: usize>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    sender: Sender<M, ALIGN>,
+    receiver: Receiver<W, ALIGN>,
+    blocks: Blocks<BLOCK, N>,
+}
+
+/// The state of the blocks in both directions, kept apart from the channel so that it can be
+/// borrowed separately from the waiter.
+struct Blocks<const BLOCK: usize, const N: usize> {
+    tx: *mut u8,
+    rx: *const u8,
+    // our blocks that hold a message the other side has not released yet
+    tx_allocated: u32,
+    // the other side's blocks that hold a message we have received and not released yet
+    rx_held: u32,
+    // the other side's blocks that we are done with, but could not send a release for yet
+    rx_released: u32,
+    // received descriptors that have not been returned yet
+    pending: Pending<N>,
+}
+
+// SAFETY: The blocks are only accessed through `&mut self`, see the impls for the transport.
+unsafe impl<M, W, const ALIGN: usize, const BLOCK: usize, const N: usize> Send
+    for IcbMsg<M, W, ALIGN, BLOCK, N>
+where
+    M: Notifier + Send,
+    W: WaitForNotify + Send,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Descriptor {
+    start: usize,
+    len: usize,
+}
+
+/// A queue of up to `N` received descriptors, oldest first, so that release messages behind them
+/// in the ring can be handled before they are returned.
+struct Pending<const N: usize> {
+    descs: [Descriptor; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> Pending<N> {
+    fn new() -> Self {
+        Self {
+            descs: [Descriptor { start: 0, len: 0 }; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Queue a descriptor. The queue must not be full.
+    fn push_back(&mut self, desc: Descriptor) {
+        self.descs[(self.head + self.len) % N] = desc;
+        self.len += 1;
+    }
+
+    /// Put back a descriptor that was just taken. The queue must not be full.
+    fn push_front(&mut self, desc: Descriptor) {
+        self.head = (self.head + N - 1) % N;
+        self.descs[self.head] = desc;
+        self.len += 1;
+    }
+
+    fn pop_front(&mut self) -> Option<Descriptor> {
+        if self.len == 0 {
+            return None;
+        }
+        let desc = self.descs[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(desc)
+    }
+
+    /// The blocks taken by the queued messages.
+    fn mask<const BLOCK: usize>(&self) -> u32 {
+        (0..self.len)
+            .map(|i| self.descs[(self.head + i) % N])
+            .fold(0, |mask, desc| {
+                mask | block_mask::<BLOCK>(desc.start, desc.len)
+            })
+    }
+}
+
+impl<M, W, const ALIGN: usize, const BLOCK: usize, const N: usize> IcbMsg<M, W, ALIGN, BLOCK, N>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Use the bonded `icmsg` as the control channel, with the data blocks at `tx_blocks` and
+    /// `rx_blocks`.
+    ///
+    /// # Safety
+    ///
+    /// `tx_blocks` and `rx_blocks` must each point to `N * BLOCK` bytes of shared memory that are
+    /// not used for anything else. The other side must use the same `BLOCK` and `N`, with the
+    /// two areas swapped.
+    pub unsafe fn new(icmsg: IcMsg<M, W, ALIGN>, tx_blocks: *mut (), rx_blocks: *mut ()) -> Self {
+        const { assert!(BLOCK > 0 && N > 0 && N <= 32, "N must be between 1 and 32") };
+        let (sender, receiver) = icmsg.split();
+        Self {
+            sender,
+            receiver,
+            blocks: Blocks {
+                tx: tx_blocks.cast(),
+                rx: rx_blocks.cast_const().cast(),
+                tx_allocated: 0,
+                rx_held: 0,
+                rx_released: 0,
+                pending: Pending::new(),
+            },
+        }
+    }
+
+    /// The size of the largest message that can be sent.
+    pub fn max_message_len(&self) -> usize {
+        (N * BLOCK).min(u16::MAX as usize)
+    }
+
+    /// Copy a message into free blocks and send its descriptor.
+    ///
+    /// Fails with [`Error::OutOfBlocks`] if there are not enough consecutive free blocks, which
+    /// is temporary as long as the other side keeps receiving.
+    pub fn send(&mut self, msg: &[u8]) -> Result<(), Error> {
+        if msg.len() > self.max_message_len() {
+            return Err(Error::TooLarge);
+        }
+        let blocks = &mut self.blocks;
+        blocks.flush_released(&mut self.sender)?;
+        let start = match blocks.allocate(msg.len()) {
+            Some(start) => start,
+            None => {
+                // Handle all the releases in the ring, including those behind descriptors that
+                // have not been received yet.
+                blocks.poll_control(&mut self.receiver.state, false)?;
+                blocks.allocate(msg.len()).ok_or(Error::OutOfBlocks)?
+            }
+        };
+
+        // SAFETY: The blocks are free, so the other side is not reading them.
+        unsafe {
+            msg.as_ptr()
+                .copy_to_nonoverlapping(blocks.tx.add(start * BLOCK), msg.len())
+        };
+        let [lo, hi] = (msg.len() as u16).to_le_bytes();
+        self.sender.send(&[DATA, start as u8, lo, hi])?;
+        blocks.tx_allocated |= block_mask::<BLOCK>(start, msg.len());
+        Ok(())
+    }
+
+    /// Receive a message by copying it out of its blocks, which are released right away. On
+    /// success, returns the size of the message.
+    ///
+    /// If the message doesn't fit in `msg`, this fails with [`RecvError::MessageTooBig`] and the
+    /// message stays queued, so it can still be received with
+    /// [`try_recv_block`][Self::try_recv_block].
+    pub fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, Error> {
+        let desc = self.next_descriptor()?;
+        if desc.len > msg.len() {
+            self.blocks.pending.push_front(desc);
+            return Err(RecvError::MessageTooBig { required: desc.len }.into());
+        }
+        let block = self.hold(desc);
+        msg[..block.len()].copy_from_slice(&block);
+        Ok(block.len())
+    }
+
+    /// Receive a message without copying it. The blocks are released when the returned
+    /// [`RecvBlock`] is dropped.
+    pub fn try_recv_block(&mut self) -> Result<RecvBlock<'_, M, W, ALIGN, BLOCK, N>, Error> {
+        let desc = self.next_descriptor()?;
+        Ok(self.hold(desc))
+    }
+
+    /// Wait for and receive a message without copying it. See
+    /// [`try_recv_block`][Self::try_recv_block].
+    pub async fn recv_block(&mut self) -> Result<RecvBlock<'_, M, W, ALIGN, BLOCK, N>, Error> {
+        let desc = loop {
+            // Let the waiter register its waker before attempting to recv
+            let mut wait_fut = pin!(self.receiver.waiter.wait_for_notify());
+            let r = crate::poll::poll(wait_fut.as_mut()).await;
+
+            let blocks = &mut self.blocks;
+            match blocks.next_descriptor(&mut self.sender, &mut self.receiver.state) {
+                Ok(desc) => break desc,
+                Err(Error::Recv(RecvError::Empty)) => {
+                    if r.is_pending() {
+                        wait_fut.await;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        Ok(self.hold(desc))
+    }
+
+    pub fn into_inner(self) -> IcMsg<M, W, ALIGN> {
+        IcMsg::from_parts(self.sender, self.receiver)
+    }
+
+    fn next_descriptor(&mut self) -> Result<Descriptor, Error> {
+        let blocks = &mut self.blocks;
+        blocks.next_descriptor(&mut self.sender, &mut self.receiver.state)
+    }
+
+    fn hold(&mut self, desc: Descriptor) -> RecvBlock<'_, M, W, ALIGN, BLOCK, N> {
+        self.blocks.rx_held |= block_mask::<BLOCK>(desc.start, desc.len);
+        RecvBlock { icbmsg: self, desc }
+    }
+}
+
+impl<const BLOCK: usize, const N: usize> Blocks<BLOCK, N> {
+    /// Find `len.div_ceil(BLOCK)` consecutive free blocks and return the first one.
+    fn allocate(&self, len: usize) -> Option<usize> {
+        let count = len.div_ceil(BLOCK);
+        (0..=N - count).find(|&start| self.tx_allocated & block_mask::<BLOCK>(start, len) == 0)
+    }
+
+    /// Get the next data descriptor, handling release messages on the way.
+    fn next_descriptor<M, const ALIGN: usize>(
+        &mut self,
+        sender: &mut Sender<M, ALIGN>,
+        state: &mut RecvState<ALIGN>,
+    ) -> Result<Descriptor, Error>
+    where
+        M: Notifier,
+        elain::Align<ALIGN>: elain::Alignment,
+    {
+        self.flush_released(sender)?;
+        if self.pending.is_empty() {
+            self.poll_control(state, true)?;
+        }
+        self.pending.pop_front().ok_or(RecvError::Empty.into())
+    }
+
+    /// Receive control messages until the ring is empty or, if `stop_at_data`, a data descriptor
+    /// is queued. Data descriptors are queued, and receiving stops early if the queue is full.
+    fn poll_control<const ALIGN: usize>(
+        &mut self,
+        state: &mut RecvState<ALIGN>,
+        stop_at_data: bool,
+    ) -> Result<(), Error>
+    where
+        elain::Align<ALIGN>: elain::Alignment,
+    {
+        let mut msg = [0; 5];
+        while !self.pending.is_full() && (!stop_at_data || self.pending.is_empty()) {
+            let n = match state.try_recv(&mut msg) {
+                Ok(n) => n,
+                Err(RecvError::Empty) => return Ok(()),
+                Err(RecvError::MessageTooBig { .. }) => {
+                    state.transport.discard_next()?;
+                    return Err(Error::InvalidDescriptor);
+                }
+                Err(e) => return Err(e.into()),
+            };
+            match msg[..n] {
+                [RELEASE, b0, b1, b2, b3] => {
+                    self.tx_allocated &= !u32::from_le_bytes([b0, b1, b2, b3]);
+                }
+                [DATA, start, lo, hi] => {
+                    let desc = Descriptor {
+                        start: start as usize,
+                        len: u16::from_le_bytes([lo, hi]) as usize,
+                    };
+                    let in_use = self.rx_held | self.pending.mask::<BLOCK>();
+                    if desc.start + desc.len.div_ceil(BLOCK) > N
+                        || in_use & block_mask::<BLOCK>(desc.start, desc.len) != 0
+                    {
+                        return Err(Error::InvalidDescriptor);
+                    }
+                    self.pending.push_back(desc);
+                }
+                _ => return Err(Error::InvalidDescriptor),
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a release for the blocks that have been dropped, unless the ring is full, in which
+    /// case it is retried on the next call.
+    fn flush_released<M, const ALIGN: usize>(
+        &mut self,
+        sender: &mut Sender<M, ALIGN>,
+    ) -> Result<(), SendError>
+    where
+        M: Notifier,
+        elain::Align<ALIGN>: elain::Alignment,
+    {
+        if self.rx_released == 0 {
+            return Ok(());
+        }
+        let [b0, b1, b2, b3] = self.rx_released.to_le_bytes();
+        match sender.send(&[RELEASE, b0, b1, b2, b3]) {
+            Ok(()) => {
+                self.rx_released = 0;
+                Ok(())
+            }
+            Err(SendError::InsufficientCapacity) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The blocks taken by a message of `len` bytes starting at block `start`.
+fn block_mask<const BLOCK: usize>(start: usize, len: usize) -> u32 {
+    (((1u64 << len.div_ceil(BLOCK)) - 1) << start) as u32
+}
+
+/// A received message, read in place from the other side's blocks.
+///
+/// The blocks are released when this is dropped. The release message is sent right away if there
+/// is room in the ring, otherwise on the next send or receive.
+pub struct RecvBlock<'a, M, W, const ALIGN: usize, const BLOCK: usize, const N: usize>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    icbmsg: &'a mut IcbMsg<M, W, ALIGN, BLOCK, N>,
+    desc: Descriptor,
+}
+
+impl<M, W, const ALIGN: usize, const BLOCK: usize, const N: usize> Deref
+    for RecvBlock<'_, M, W, ALIGN, BLOCK, N>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: The descriptor was checked to be in bounds, and the other side does not write
+        // to the blocks until they are released.
+        unsafe {
+            core::slice::from_raw_parts(
+                self.icbmsg.blocks.rx.add(self.desc.start * BLOCK),
+                self.desc.len,
+            )
+        }
+    }
+}
+
+impl<M, W, const ALIGN: usize, const BLOCK: usize, const N: usize> Drop
+    for RecvBlock<'_, M, W, ALIGN, BLOCK, N>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    fn drop(&mut self) {
+        let mask = block_mask::<BLOCK>(self.desc.start, self.desc.len);
+        let blocks = &mut self.icbmsg.blocks;
+        blocks.rx_held &= !mask;
+        blocks.rx_released |= mask;
+        // Errors other than a full ring are reported by the next send or receive.
+        let _ = blocks.flush_released(&mut self.icbmsg.sender);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    Send(SendError),
+    Recv(RecvError),
+    /// The message is bigger than [`IcbMsg::max_message_len`].
+    TooLarge,
+    /// There are not enough consecutive free blocks for the message.
+    OutOfBlocks,
+    /// The other side sent a control message that is not a valid descriptor, or that refers to
+    /// blocks that are still held.
+    InvalidDescriptor,
+}
+
+impl From<SendError> for Error {
+    fn from(e: SendError) -> Self {
+        Self::Send(e)
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(e: RecvError) -> Self {
+        Self::Recv(e)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Send(e) => write!(f, "send error: {e}"),
+            Error::Recv(e) => write!(f, "receive error: {e}"),
+            Error::TooLarge => write!(f, "message too large"),
+            Error::OutOfBlocks => write!(f, "out of blocks"),
+            Error::InvalidDescriptor => write!(f, "invalid descriptor"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
@@ -6,30 +6,59 @@
 
 #![no_std]
 
-use core::pin::pin;
+use core::{
+    cell::Cell,
+    mem::MaybeUninit,
+    ops::ControlFlow,
+    pin::pin,
+    ptr::NonNull,
+    task::{Context, Poll},
+};
 
 use embassy_futures::select::{Either, select};
 use embedded_hal_async::delay::DelayNs;
-use transport::IcMsgTransport;
+use protocol::{BONDING_MAGIC as MAGIC, CLOSE_MAGIC};
 pub use transport::Notifier;
+use transport::{Barrier, IcMsgTransport, NoBarrier, NoObserver, Observer};
 
+pub mod blocking;
+#[cfg(feature = "dispatch")]
+pub mod dispatch;
+pub mod duplex;
+#[cfg(feature = "embassy-sync")]
+mod embassy;
+pub mod endpoints;
+#[cfg(feature = "bt-hci")]
+pub mod hci;
+pub mod icbmsg;
 mod loom;
+pub mod protocol;
+pub mod reader;
+#[cfg(feature = "sink")]
+pub mod sink;
+#[cfg(feature = "stream")]
+pub mod stream;
+#[cfg(any(test, feature = "std"))]
+pub mod sync_notify;
+#[cfg(any(test, feature = "std"))]
+pub mod testing;
 pub mod transport;
+#[cfg(feature = "postcard")]
+pub mod typed;
+pub mod writer;
 #[macro_use]
 mod poll;
 
-const MAGIC: [u8; 13] = [
-    0x45, 0x6d, 0x31, 0x6c, 0x31, 0x4b, 0x30, 0x72, 0x6e, 0x33, 0x6c, 0x69, 0x34,
-];
-
-pub struct IcMsg<M, W, const ALIGN: usize>
+pub struct IcMsg<M, W, const ALIGN: usize, O = NoObserver, B = NoBarrier>
 where
     M: Notifier,
     W: WaitForNotify,
+    O: Observer,
+    B: Barrier,
     elain::Align<ALIGN>: elain::Alignment,
 {
-    sender: Sender<M, ALIGN>,
-    receiver: Receiver<W, ALIGN>,
+    sender: Sender<M, ALIGN, O, B>,
+    receiver: Receiver<W, ALIGN, O, B>,
 }
 
 impl<M, W, const ALIGN: usize> IcMsg<M, W, ALIGN>
@@ -40,70 +69,301 @@ where
 {
     /// Create a new IcMsg channel and perform [bonding][bond].
     ///
+    /// This uses the default [`BondingConfig`], which retries every 1 ms and waits forever for the
+    /// other side.
+    ///
     /// # Safety
     ///
-    /// The provided [`MemoryConfig`] must be correct.
+    /// The provided [`MemoryConfig`] must be correct. Misaligned or overlapping regions and invalid
+    /// buffer lengths are caught and returned as an [`InitError`] before the regions are touched,
+    /// but the regions must still be valid memory shared with the other side.
     ///
     /// [bond]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#bonding
     pub async unsafe fn init(
         config: MemoryConfig,
         notifier: M,
+        waiter: W,
+        delay: impl DelayNs,
+    ) -> Result<Self, InitError> {
+        unsafe {
+            Self::init_with_bonding_config(
+                config,
+                BondingConfig::default(),
+                notifier,
+                waiter,
+                delay,
+            )
+            .await
+        }
+    }
+
+    /// Create a new IcMsg channel and perform [bonding][bond] using the given [`BondingConfig`].
+    ///
+    /// Bonding runs the same state machine as [`Bonder`], which can be used instead to drive the
+    /// handshake from an existing state machine or timer, without [`DelayNs`].
+    ///
+    /// # Safety
+    ///
+    /// The provided [`MemoryConfig`] must be correct. Misaligned or overlapping regions and invalid
+    /// buffer lengths are caught and returned as an [`InitError`] before the regions are touched,
+    /// but the regions must still be valid memory shared with the other side.
+    ///
+    /// [bond]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#bonding
+    pub async unsafe fn init_with_bonding_config(
+        config: MemoryConfig,
+        bonding_config: BondingConfig,
+        notifier: M,
         mut waiter: W,
         mut delay: impl DelayNs,
     ) -> Result<Self, InitError> {
-        if config.send_buffer_len % 4 != 0 || config.recv_buffer_len % 4 != 0 {
-            return Err(InitError::InvalidSize);
-        }
+        let mut transport = unsafe { new_transport(config, notifier)? };
+        let (s, r) = transport.split_mut();
+        let peer = bond(s, r, &mut waiter, &mut delay, bonding_config).await?;
 
-        if config.send_buffer_len < 24 || config.recv_buffer_len < 24 {
-            return Err(InitError::TooSmall);
-        }
+        let (s, r) = transport.split();
+        let sender = Sender::from_transport(s);
+        let state = RecvState::bonded(r, &bonding_config, &peer);
+        let receiver = Receiver { state, waiter };
 
-        let mut transport = unsafe {
-            IcMsgTransport::new(
+        Ok(Self { sender, receiver })
+    }
+
+    /// Create a new IcMsg channel from a [`CheckedMemoryConfig`] and perform [bonding][bond].
+    ///
+    /// Unlike [`init`][Self::init], this is safe because the config was created from memory that
+    /// is owned by the channel.
+    ///
+    /// [bond]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#bonding
+    pub async fn init_checked(
+        config: CheckedMemoryConfig<ALIGN>,
+        notifier: M,
+        waiter: W,
+        delay: impl DelayNs,
+    ) -> Result<Self, InitError> {
+        // SAFETY: `config` was created by `MemoryConfig::from_slices` or
+        // `MemoryConfig::from_buffers` from two `&'static mut` regions that are aligned and large
+        // enough for `ALIGN`.
+        unsafe { Self::init(config.config, notifier, waiter, delay).await }
+    }
+
+    /// Resume a channel that was bonded before this core was reset, without bonding again.
+    ///
+    /// The indices already in shared memory are kept, so the other side, which kept running, can
+    /// carry on as if nothing happened. Any session ID exchanged during the original bonding is
+    /// not known after the reset, so a later rebond by the other side is not reported as
+    /// [`SessionLost`][transport::RecvError::SessionLost]. [CRC checking][BondingConfig::crc] is
+    /// not enabled either, so this can't resume a channel that was bonded with it.
+    ///
+    /// Fails with [`InitError::InvalidIndices`] if the indices in shared memory can't belong to a
+    /// channel with this config.
+    ///
+    /// # Safety
+    ///
+    /// The provided [`MemoryConfig`] must be correct, and the regions must hold the state of a
+    /// channel that was bonded with the same config before the reset. See
+    /// [`IcMsgTransport::new_preserve`].
+    pub unsafe fn resume(config: MemoryConfig, notifier: M, waiter: W) -> Result<Self, InitError> {
+        config.check::<ALIGN>()?;
+
+        let transport = unsafe {
+            IcMsgTransport::new_preserve(
                 config.send_region,
                 config.recv_region,
                 config.send_buffer_len,
                 config.recv_buffer_len,
                 notifier,
             )
+        }
+        .ok_or(InitError::InvalidIndices)?;
+        let (s, r) = transport.split();
+        let sender = Sender::from_transport(s);
+        let receiver = Receiver {
+            state: RecvState::new(r, None, &MAGIC),
+            waiter,
         };
 
-        transport
-            .send(&MAGIC)
-            .map_err(InitError::BondingSendError)?;
+        Ok(Self { sender, receiver })
+    }
 
-        // Repeat the notification every 1 ms until a notification is received.
-        {
+    /// Like [`init`][Self::init], but for when there is no timer yet to provide [`DelayNs`], e.g.
+    /// during early boot. Instead of waiting for a time, the other side is re-notified after every
+    /// `spins_per_retry` calls to `spin`, such as `core::hint::spin_loop`.
+    ///
+    /// The retry interval depends on how long `spin` takes and on how often the executor polls
+    /// this future, so it is only roughly fixed. Between calls to `spin`, this yields to the
+    /// executor, so other tasks keep running and a notification from the other side is seen as
+    /// soon as this is polled again.
+    ///
+    /// # Safety
+    ///
+    /// The provided [`MemoryConfig`] must be correct, see [`init`][Self::init].
+    pub async unsafe fn init_spinning(
+        config: MemoryConfig,
+        notifier: M,
+        mut waiter: W,
+        mut spin: impl FnMut(),
+        spins_per_retry: u32,
+    ) -> Result<Self, InitError> {
+        let transport = unsafe { new_transport(config, notifier)? };
+        let (sender, state) = {
+            // Start waiting before sending, in case the other side answers right away.
             let mut wait_fut = pin!(waiter.wait_for_notify());
+            let mut bonder = Bonder::new(transport, BondingConfig::default())?;
             loop {
-                let timeout = delay.delay_ms(1);
-                match select(wait_fut.as_mut(), timeout).await {
-                    Either::First(_) => break,
-                    Either::Second(_) => transport.notify(),
+                let spinning = spin_and_yield(&mut spin, spins_per_retry);
+                // A notification always completes bonding, so the finished wait is never polled
+                // again.
+                let notified =
+                    matches!(select(wait_fut.as_mut(), spinning).await, Either::First(_));
+                if let Poll::Ready(r) = bonder.poll_split(notified) {
+                    break r?;
                 }
             }
-            transport.notify();
+        };
+        let receiver = Receiver { state, waiter };
+
+        Ok(Self { sender, receiver })
+    }
+}
+
+impl<M, W, const ALIGN: usize, O, B> IcMsg<M, W, ALIGN, O, B>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Tear down the channel: tell the other side that this side is closing, wait until it has
+    /// read everything sent so far, and give back the memory config so the regions can be reused
+    /// or powered down.
+    ///
+    /// The other side's receive functions return
+    /// [`RecvError::PeerClosed`][transport::RecvError::PeerClosed] once it has received all
+    /// messages sent before this. Whether it has is checked again every time `waiter` is notified.
+    /// As for [`Sender::send_and_wait_drained`], the other side does not notify when it reads
+    /// messages, so `waiter` has to be something that fires after it does, e.g. a periodic timer.
+    /// The other side may still be reading the receive region, so its own teardown has to be
+    /// awaited before reusing that.
+    ///
+    /// Fails and gives the channel back if there is no room in the ring for the teardown message,
+    /// or if the other side's read index turns out to be invalid while waiting.
+    // The channel is given back by value on failure, there is no allocator to box it in.
+    #[allow(clippy::result_large_err)]
+    pub async fn deinit(mut self, waiter: &mut impl WaitForNotify) -> Result<MemoryConfig, Self> {
+        if self
+            .sender
+            .send_control_and_wait_drained(&CLOSE_MAGIC, waiter)
+            .await
+            .is_err()
+        {
+            return Err(self);
+        }
+        Ok(self.into_config())
+    }
+
+    /// Give back the memory config the channel was created with, without telling the other side.
+    ///
+    /// Unlike [`deinit`][Self::deinit], nothing is sent, not even with the `notify-on-drop`
+    /// feature, so this can be used after the other side is known to have stopped, e.g. to
+    /// reconfigure memory protection or hand the regions to something else.
+    #[cfg_attr(not(feature = "notify-on-drop"), allow(unused_mut))]
+    pub fn into_config(mut self) -> MemoryConfig {
+        #[cfg(feature = "notify-on-drop")]
+        {
+            self.sender.closed = true;
+        }
+        let (send_region, send_buffer_len) = self.sender.region();
+        let (recv_region, recv_buffer_len) = self.receiver.region();
+        MemoryConfig {
+            send_region,
+            recv_region,
+            send_buffer_len,
+            recv_buffer_len,
         }
+    }
+
+    /// Join the two halves of an already bonded channel back together.
+    pub fn from_parts(sender: Sender<M, ALIGN, O, B>, receiver: Receiver<W, ALIGN, O, B>) -> Self {
+        Self { sender, receiver }
+    }
 
-        // Allow larger messages for forward compatibility.
-        let mut message = [0; 32];
-        transport
-            .try_recv(&mut message)
-            .map_err(InitError::BondingRecvError)?;
+    /// Take the channel apart into the low-level transport halves and the waiter, e.g. to use
+    /// code written against the [`transport`] API. Nothing is sent, not even with the
+    /// `notify-on-drop` feature.
+    pub fn into_raw_parts(
+        self,
+    ) -> (
+        transport::Sender<M, ALIGN, O, B>,
+        transport::Receiver<ALIGN, O, B>,
+        W,
+    ) {
+        let (transport, waiter) = self.receiver.into_transport();
+        (self.sender.into_transport(), transport, waiter)
+    }
 
-        if message.get(..MAGIC.len()) != Some(&MAGIC) {
-            return Err(InitError::BondingWrongMagic);
+    /// Build a channel from low-level transport halves that have already bonded, without bonding
+    /// again.
+    ///
+    /// [Session-aware bonding][BondingConfig::session_id] is not tracked by the new channel, since
+    /// the other side's session ID is not known.
+    pub fn from_raw_parts(
+        sender: transport::Sender<M, ALIGN, O, B>,
+        receiver: transport::Receiver<ALIGN, O, B>,
+        waiter: W,
+    ) -> Self {
+        Self {
+            sender: Sender::from_transport(sender),
+            receiver: Receiver::from_transport(receiver, waiter),
         }
+    }
 
-        let (s, r) = transport.split();
-        let sender = Sender { transport: s };
-        let receiver = Receiver {
-            transport: r,
-            waiter,
-        };
+    /// Perform [bonding][bond] again on an existing channel, e.g. after the other core has been
+    /// reset.
+    ///
+    /// This uses the default [`BondingConfig`]. Both sides must bond again: the other side either
+    /// by calling [`init`][Self::init] after restarting or by calling `rebond` as well.
+    ///
+    /// Both rings are reset to empty, so any messages that were in flight in either direction are
+    /// lost.
+    ///
+    /// [bond]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#bonding
+    pub async fn rebond(&mut self, delay: impl DelayNs) -> Result<(), InitError> {
+        self.rebond_with_bonding_config(BondingConfig::default(), delay)
+            .await
+    }
 
-        Ok(Self { sender, receiver })
+    /// Perform [bonding][bond] again on an existing channel using the given [`BondingConfig`].
+    ///
+    /// See [`rebond`][Self::rebond].
+    ///
+    /// [bond]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#bonding
+    pub async fn rebond_with_bonding_config(
+        &mut self,
+        bonding_config: BondingConfig,
+        mut delay: impl DelayNs,
+    ) -> Result<(), InitError> {
+        let sender = &mut self.sender.transport;
+        let receiver = &mut self.receiver.state.transport;
+        sender.reset();
+        receiver.reset();
+
+        let peer = bond(
+            sender,
+            receiver,
+            &mut self.receiver.waiter,
+            &mut delay,
+            bonding_config,
+        )
+        .await?;
+        self.receiver.state.peer_session_id = bonding_config.session_id.and(peer.session_id);
+        self.receiver.state.peer_version = Some(peer.version);
+        self.receiver.state.peer_features = peer.features;
+        self.receiver.state.magic = bonding_config.magic;
+        self.receiver.state.peer_closed = false;
+        self.receiver.state.read_offset = 0;
+
+        Ok(())
     }
 
     /// Send a message
@@ -111,11 +371,137 @@ where
         self.sender.send(msg)
     }
 
+    /// See [`Sender::send_vectored`].
+    pub fn send_vectored(&mut self, parts: &[&[u8]]) -> Result<(), transport::SendError> {
+        self.sender.send_vectored(parts)
+    }
+
+    /// See [`Sender::send_typed`].
+    pub fn send_typed(&mut self, msg: &[u8], flags: u8) -> Result<(), transport::SendError> {
+        self.sender.send_typed(msg, flags)
+    }
+
+    /// See [`Sender::send_fragmented`].
+    pub fn send_fragmented(
+        &mut self,
+        msg: &[u8],
+        sent: &mut usize,
+    ) -> Result<(), transport::SendError> {
+        self.sender.send_fragmented(msg, sent)
+    }
+
+    /// See [`Sender::send_no_notify`].
+    pub fn send_no_notify(&mut self, msg: &[u8]) -> Result<(), transport::SendError> {
+        self.sender.send_no_notify(msg)
+    }
+
+    /// See [`Sender::send_all`].
+    pub fn send_all<'a>(
+        &mut self,
+        msgs: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<usize, transport::SendError> {
+        self.sender.send_all(msgs)
+    }
+
+    /// See [`Sender::notify`].
+    pub fn notify(&mut self) {
+        self.sender.notify()
+    }
+
+    /// See [`Sender::reserve`].
+    pub fn reserve(
+        &mut self,
+        len: usize,
+    ) -> Result<transport::SendSlot<'_, M, ALIGN, O, B>, transport::SendError> {
+        self.sender.reserve(len)
+    }
+
+    /// See [`Sender::capacity`].
+    pub fn capacity(&self) -> usize {
+        self.sender.capacity()
+    }
+
+    /// See [`Sender::free_space`].
+    pub fn free_space(&self) -> usize {
+        self.sender.free_space()
+    }
+
+    /// See [`Sender::can_send`].
+    pub fn can_send(&self, len: usize) -> bool {
+        self.sender.can_send(len)
+    }
+
+    /// See [`Sender::max_message_len`].
+    pub fn max_message_len(&self) -> usize {
+        self.sender.max_message_len()
+    }
+
+    /// See [`Receiver::peer_version`].
+    pub fn peer_version(&self) -> Option<u8> {
+        self.receiver.peer_version()
+    }
+
+    /// See [`Receiver::peer_features`].
+    pub fn peer_features(&self) -> Option<u32> {
+        self.receiver.peer_features()
+    }
+
     /// Receive a message. On success, returns the size of the message.
     pub fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, transport::RecvError> {
         self.receiver.try_recv(msg)
     }
 
+    /// See [`Receiver::try_recv_uninit`].
+    pub fn try_recv_uninit(
+        &mut self,
+        msg: &mut [MaybeUninit<u8>],
+    ) -> Result<usize, transport::RecvError> {
+        self.receiver.try_recv_uninit(msg)
+    }
+
+    /// See [`Receiver::try_recv_exact`].
+    #[cfg(feature = "heapless")]
+    pub fn try_recv_exact<const N: usize>(
+        &mut self,
+    ) -> Result<heapless::Vec<u8, N>, transport::RecvError> {
+        self.receiver.try_recv_exact()
+    }
+
+    /// See [`Receiver::try_recv_partial`].
+    pub fn try_recv_partial(
+        &mut self,
+        msg: &mut [u8],
+    ) -> Result<(usize, usize), transport::RecvError> {
+        self.receiver.try_recv_partial(msg)
+    }
+
+    /// See [`Receiver::try_recv_typed`].
+    pub fn try_recv_typed(&mut self, msg: &mut [u8]) -> Result<(usize, u8), transport::RecvError> {
+        self.receiver.try_recv_typed(msg)
+    }
+
+    /// See [`Receiver::try_recv_vectored`].
+    pub fn try_recv_vectored(
+        &mut self,
+        bufs: &mut [&mut [u8]],
+    ) -> Result<usize, transport::RecvError> {
+        self.receiver.try_recv_vectored(bufs)
+    }
+
+    /// See [`Receiver::try_recv_many`].
+    pub fn try_recv_many(
+        &mut self,
+        scratch: &mut [u8],
+        on_msg: impl FnMut(&[u8]) -> ControlFlow<()>,
+    ) -> Result<usize, transport::RecvError> {
+        self.receiver.try_recv_many(scratch, on_msg)
+    }
+
+    /// See [`Receiver::peek_len`].
+    pub fn peek_len(&mut self) -> Result<usize, transport::RecvError> {
+        self.receiver.peek_len()
+    }
+
     pub fn recv(
         &mut self,
         msg: &mut [u8],
@@ -123,237 +509,4536 @@ where
         self.receiver.recv(msg)
     }
 
-    pub fn split(self) -> (Sender<M, ALIGN>, Receiver<W, ALIGN>) {
+    /// See [`Receiver::recv_exact`].
+    #[cfg(feature = "heapless")]
+    pub fn recv_exact<const N: usize>(
+        &mut self,
+    ) -> impl Future<Output = Result<heapless::Vec<u8, N>, transport::RecvError>> {
+        self.receiver.recv_exact()
+    }
+
+    /// See [`Receiver::recv_reassembled`].
+    pub fn recv_reassembled(
+        &mut self,
+        msg: &mut [u8],
+    ) -> impl Future<Output = Result<usize, transport::RecvError>> {
+        self.receiver.recv_reassembled(msg)
+    }
+
+    /// See [`Receiver::recv_timeout`].
+    pub fn recv_timeout(
+        &mut self,
+        msg: &mut [u8],
+        timeout_us: u32,
+        delay: impl DelayNs,
+    ) -> impl Future<Output = Result<usize, RecvTimeoutError>> {
+        self.receiver.recv_timeout(msg, timeout_us, delay)
+    }
+
+    /// Send `payload` and wait for the other side to send it back, to check the link end to end,
+    /// e.g. during bring-up. The other side has to echo every message it receives.
+    ///
+    /// Fails with [`EchoError::Mismatch`] if the next message received is not `payload`, and with
+    /// [`EchoError::TimedOut`] if nothing arrives within `timeout_ms` milliseconds. The echo is
+    /// compared in place, so no receive buffer is needed.
+    pub async fn echo_roundtrip(
+        &mut self,
+        payload: &[u8],
+        mut delay: impl DelayNs,
+        timeout_ms: u32,
+    ) -> Result<(), EchoError> {
+        self.sender.send(payload)?;
+
+        let receiver = &mut self.receiver;
+        let echo =
+            transport::recv_with(&mut receiver.waiter, || receiver.state.try_recv_eq(payload));
+        match select(echo, delay.delay_ms(timeout_ms)).await {
+            Either::First(Ok(true)) => Ok(()),
+            Either::First(Ok(false)) => Err(EchoError::Mismatch),
+            Either::First(Err(e)) => Err(e.into()),
+            Either::Second(()) => Err(EchoError::TimedOut),
+        }
+    }
+
+    pub fn split(self) -> (Sender<M, ALIGN, O, B>, Receiver<W, ALIGN, O, B>) {
         (self.sender, self.receiver)
     }
 
-    pub fn split_mut(&mut self) -> (&mut Sender<M, ALIGN>, &mut Receiver<W, ALIGN>) {
+    pub fn split_mut(&mut self) -> (&mut Sender<M, ALIGN, O, B>, &mut Receiver<W, ALIGN, O, B>) {
         (&mut self.sender, &mut self.receiver)
     }
-}
 
-pub struct Sender<M, const ALIGN: usize>
-where
-    M: Notifier,
-    elain::Align<ALIGN>: elain::Alignment,
-{
-    transport: transport::Sender<M, ALIGN>,
+    /// Replace the [`Observer`] that is called on protocol events, e.g. to count messages or
+    /// measure timing. Each half gets its own clone of `observer`.
+    pub fn with_observer<O2>(self, observer: O2) -> IcMsg<M, W, ALIGN, O2, B>
+    where
+        O2: Observer + Clone,
+    {
+        IcMsg {
+            sender: self.sender.with_observer(observer.clone()),
+            receiver: self.receiver.with_observer(observer),
+        }
+    }
+
+    /// Replace the [`Barrier`] that orders the message bytes against the shared indices, for cores
+    /// that need one. Each half gets its own clone of `barrier`. The bonding messages were
+    /// exchanged without it; to cover them as well, bond a transport that already has the barrier
+    /// with [`Bonder`] and join its halves with [`from_raw_parts`][Self::from_raw_parts].
+    pub fn with_barrier<B2>(self, barrier: B2) -> IcMsg<M, W, ALIGN, O, B2>
+    where
+        B2: Barrier + Clone,
+    {
+        IcMsg {
+            sender: self.sender.with_barrier(barrier.clone()),
+            receiver: self.receiver.with_barrier(barrier),
+        }
+    }
 }
 
-impl<M, const ALIGN: usize> Sender<M, ALIGN>
+impl<M, W, const ALIGN: usize, O, B> IcMsg<M, W, ALIGN, O, B>
 where
     M: Notifier,
+    W: WaitForNotify + PollWaitForNotify,
+    O: Observer,
+    B: Barrier,
     elain::Align<ALIGN>: elain::Alignment,
 {
-    pub fn send(&mut self, msg: &[u8]) -> Result<(), transport::SendError> {
-        self.transport.send(msg)
+    /// See [`Receiver::poll_recv`].
+    pub fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        msg: &mut [u8],
+    ) -> Poll<Result<usize, transport::RecvError>> {
+        self.receiver.poll_recv(cx, msg)
     }
 }
 
-pub struct Receiver<W, const ALIGN: usize>
+/// The sending half of an [`IcMsg`].
+///
+/// With the `notify-on-drop` feature, dropping a sender sends the same teardown message as
+/// [`IcMsg::deinit`], so that the other side's receive functions return
+/// [`RecvError::PeerClosed`][transport::RecvError::PeerClosed] instead of waiting forever. This
+/// also happens when a whole [`IcMsg`] is dropped. Some things to keep in mind:
+///
+/// - The shared memory regions must still be valid when the sender is dropped.
+/// - If there is no room in the ring, nothing is sent.
+/// - Dropping the sender only closes this direction. If the [`Receiver`] is kept, the other side
+///   can still send to it, but it may stop doing so once it sees the teardown message.
+/// - Bonding again on the same regions, from either side, clears the teardown message.
+pub struct Sender<M, const ALIGN: usize, O = NoObserver, B = NoBarrier>
 where
-    W: WaitForNotify,
+    M: Notifier,
+    O: Observer,
+    B: Barrier,
     elain::Align<ALIGN>: elain::Alignment,
 {
-    transport: transport::Receiver<ALIGN>,
-    waiter: W,
+    transport: transport::Sender<M, ALIGN, O, B>,
+    // set by deinit, which already sent the teardown message
+    #[cfg(feature = "notify-on-drop")]
+    closed: bool,
 }
 
-impl<W, const ALIGN: usize> Receiver<W, ALIGN>
+impl<M, const ALIGN: usize, O, B> Sender<M, ALIGN, O, B>
 where
-    W: WaitForNotify,
+    M: Notifier,
+    O: Observer,
+    B: Barrier,
     elain::Align<ALIGN>: elain::Alignment,
 {
-    /// Try to receive a message if one is available. On success, returns the size of the message.
-    pub fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, transport::RecvError> {
-        self.transport.try_recv(msg)
+    /// Wrap a low-level transport half whose channel has already bonded.
+    pub fn from_transport(transport: transport::Sender<M, ALIGN, O, B>) -> Self {
+        Self {
+            transport,
+            #[cfg(feature = "notify-on-drop")]
+            closed: false,
+        }
     }
 
-    /// Wait for and receive a message. On success, returns the size of the message.
-    pub async fn recv(&mut self, msg: &mut [u8]) -> Result<usize, transport::RecvError> {
-        loop {
-            // Let the waiter register its waker before attempting to recv
-            let mut wait_fut = pin!(self.waiter.wait_for_notify());
-            let r = poll!(wait_fut.as_mut());
+    /// Unwrap the low-level transport half. Nothing is sent, not even with the `notify-on-drop`
+    /// feature.
+    pub fn into_transport(self) -> transport::Sender<M, ALIGN, O, B> {
+        let this = core::mem::ManuallyDrop::new(self);
+        // SAFETY: `this` is not used or dropped afterwards, and the other fields need no drop.
+        unsafe { core::ptr::read(&this.transport) }
+    }
 
-            match self.transport.try_recv(msg) {
-                Ok(n) => return Ok(n),
-                Err(transport::RecvError::Empty) => {
-                    if r.is_pending() {
-                        wait_fut.await;
-                    }
-                }
-                Err(e) => return Err(e),
-            }
+    pub fn transport(&self) -> &transport::Sender<M, ALIGN, O, B> {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut transport::Sender<M, ALIGN, O, B> {
+        &mut self.transport
+    }
+
+    /// Replace the [`Observer`] that is called on send events. See
+    /// [`transport::Sender::with_observer`].
+    pub fn with_observer<O2: Observer>(self, observer: O2) -> Sender<M, ALIGN, O2, B> {
+        #[cfg(feature = "notify-on-drop")]
+        let closed = self.closed;
+        Sender {
+            transport: self.into_transport().with_observer(observer),
+            #[cfg(feature = "notify-on-drop")]
+            closed,
         }
     }
-}
 
-/// The memory configuration of the channel.
-///
-/// `send_region` and `recv_region` must be properly aligned and appropriately sized.
-/// `send_buffer_len`/`recv_buffer_len` are the sizes of the
-/// [`data`][data] fields of the corresponding regions in bytes, which must be a multiple of 4.
-/// They should be at least 24 bytes large.
-///
-/// If data caching is enabled, the shared memory region provided to ICMsg must be aligned according
-/// to the cache requirement. If cache is not enabled, the required alignment is [4 bytes][ref].
-///
-/// [ref]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#shared-memory-region-organization
-/// [data]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#shared-memory-region-organization
-#[derive(Debug, Copy, Clone)]
-pub struct MemoryConfig {
-    /// Pointer to the send memory region.
-    pub send_region: *mut (),
-    /// Pointer to the recv memory region.
-    pub recv_region: *mut (),
-    /// Size of the [data][data] field of the send memory region in bytes.
-    ///
-    /// [data]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#shared-memory-region-organization
-    pub send_buffer_len: u32,
-    /// Size of the [data][data] field of the recv memory region in bytes.
-    ///
-    /// [data]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#shared-memory-region-organization
-    pub recv_buffer_len: u32,
-}
+    /// Replace the [`Barrier`]. See [`transport::Sender::with_barrier`].
+    pub fn with_barrier<B2: Barrier>(self, barrier: B2) -> Sender<M, ALIGN, O, B2> {
+        #[cfg(feature = "notify-on-drop")]
+        let closed = self.closed;
+        Sender {
+            transport: self.into_transport().with_barrier(barrier),
+            #[cfg(feature = "notify-on-drop")]
+            closed,
+        }
+    }
 
-pub trait WaitForNotify {
-    fn wait_for_notify(&mut self) -> impl Future<Output = ()>;
-}
+    pub fn send(&mut self, msg: &[u8]) -> Result<(), transport::SendError> {
+        self.transport.send(msg)
+    }
 
-#[derive(Debug, Copy, Clone)]
-pub enum InitError {
-    /// The send or recv regions were too small
-    TooSmall,
-    /// The send or recv buffer lengths were not a multiple of 4.
-    InvalidSize,
-    /// A [`SendError`][`transport::SendError`] occurred during bonding.
-    BondingSendError(transport::SendError),
-    /// A [`RecvError`][`transport::RecvError`] occurred during bonding.
-    BondingRecvError(transport::RecvError),
-    /// The magic sequence was not received during bonding.
-    BondingWrongMagic,
-}
+    /// Send a single message made up of the concatenation of `parts`.
+    pub fn send_vectored(&mut self, parts: &[&[u8]]) -> Result<(), transport::SendError> {
+        self.transport.send_vectored(parts)
+    }
 
-#[cfg(test)]
-mod tests {
-    extern crate std;
+    /// Send a message with `flags` in the packet header. See
+    /// [`transport::Sender::send_typed`].
+    ///
+    /// The [`CONTROL`][transport::CONTROL] bit is reserved for the bonding and teardown messages,
+    /// and is cleared.
+    pub fn send_typed(&mut self, msg: &[u8], flags: u8) -> Result<(), transport::SendError> {
+        self.transport.send_typed(msg, flags & !transport::CONTROL)
+    }
 
-    use embedded_hal_async::delay::DelayNs;
-    use tokio::sync::Notify;
+    /// Send a message that may be larger than the ring, split into fragments. See
+    /// [`transport::Sender::send_fragmented`].
+    pub fn send_fragmented(
+        &mut self,
+        msg: &[u8],
+        sent: &mut usize,
+    ) -> Result<(), transport::SendError> {
+        self.transport.send_fragmented(msg, sent)
+    }
+
+    /// Send a message without notifying the other side. The other side is only guaranteed to
+    /// receive it after a later call to [`notify`][Self::notify] or [`send`][Self::send].
+    ///
+    /// This only affects messages sent by the application. Bonding, including
+    /// [`IcMsg::rebond`], always notifies the other side, since it would never answer otherwise.
+    pub fn send_no_notify(&mut self, msg: &[u8]) -> Result<(), transport::SendError> {
+        self.transport.send_no_notify(msg)
+    }
+
+    /// Send as many of `msgs` as fit and notify the other side once. Returns how many were sent.
+    /// See [`transport::Sender::send_all`].
+    pub fn send_all<'a>(
+        &mut self,
+        msgs: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<usize, transport::SendError> {
+        self.transport.send_all(msgs)
+    }
+
+    /// Notify the other side, e.g. after a batch of [`send_no_notify`][Self::send_no_notify].
+    pub fn notify(&mut self) {
+        self.transport.notify()
+    }
+
+    /// Reserve space for a message of `len` bytes, to be written in place and sent with
+    /// [`SendSlot::commit`][transport::SendSlot::commit].
+    pub fn reserve(
+        &mut self,
+        len: usize,
+    ) -> Result<transport::SendSlot<'_, M, ALIGN, O, B>, transport::SendError> {
+        self.transport.reserve(len)
+    }
+
+    /// The number of bytes the ring can hold, including packet headers and padding.
+    pub fn capacity(&self) -> usize {
+        self.transport.capacity()
+    }
+
+    /// The number of bytes currently free in the ring, including space needed for packet headers
+    /// and padding.
+    pub fn free_space(&self) -> usize {
+        self.transport.free_space()
+    }
+
+    /// Whether a message of `len` bytes currently fits in the ring.
+    pub fn can_send(&self, len: usize) -> bool {
+        self.transport.can_send(len)
+    }
+
+    /// The size of the largest message that can be sent.
+    pub fn max_message_len(&self) -> usize {
+        self.transport.max_message_len()
+    }
+
+    /// See [`transport::Sender::region`].
+    pub fn region(&self) -> (NonNull<()>, u32) {
+        self.transport.region()
+    }
+
+    /// Wait until a message of `needed` bytes fits in the ring, e.g. to build a message only once
+    /// there is room to send it. Resolves immediately if it already fits, and never if `needed` is
+    /// more than [`max_message_len`][Self::max_message_len].
+    ///
+    /// The space is checked again every time `waiter` is notified. The other side does not notify
+    /// when it reads messages, so `waiter` has to be something that fires after it does, e.g. a
+    /// periodic timer, or the other side's messages if it answers every message.
+    ///
+    /// Messages sent with [`send_no_notify`][Self::send_no_notify] take up space until the other
+    /// side reads them, which it may not do until it is notified, so call
+    /// [`notify`][Self::notify] before waiting.
+    pub async fn wait_for_space(&mut self, needed: usize, mut waiter: impl WaitForNotify) {
+        loop {
+            // Let the waiter register its waker before checking for space
+            let mut wait_fut = pin!(waiter.wait_for_notify());
+            let r = poll!(wait_fut.as_mut());
+
+            if self.can_send(needed) {
+                return;
+            }
+            if r.is_pending() {
+                wait_fut.await;
+            }
+        }
+    }
+
+    /// Send a message and wait until the other side has read it, as a delivery confirmation
+    /// without an application-level acknowledgement.
+    ///
+    /// Whether the message has been read is checked again every time `waiter` is notified. As for
+    /// [`wait_for_space`][Self::wait_for_space], the other side does not notify when it reads
+    /// messages, so `waiter` has to be something that fires after it does.
+    pub async fn send_and_wait_drained(
+        &mut self,
+        msg: &[u8],
+        waiter: &mut impl WaitForNotify,
+    ) -> Result<(), transport::SendError> {
+        self.send(msg)?;
+        self.wait_drained(waiter).await
+    }
+
+    /// Send the teardown message, and wait until the other side has read it.
+    async fn send_control_and_wait_drained(
+        &mut self,
+        msg: &[u8],
+        waiter: &mut impl WaitForNotify,
+    ) -> Result<(), transport::SendError> {
+        self.transport.send_typed(msg, transport::CONTROL)?;
+        #[cfg(feature = "notify-on-drop")]
+        {
+            self.closed = true;
+        }
+        self.wait_drained(waiter).await
+    }
+
+    /// Wait until the other side has read everything sent so far.
+    async fn wait_drained(
+        &mut self,
+        waiter: &mut impl WaitForNotify,
+    ) -> Result<(), transport::SendError> {
+        let position = self.transport.position();
+        loop {
+            // Let the waiter register its waker before checking the other side's progress
+            let mut wait_fut = pin!(waiter.wait_for_notify());
+            let r = poll!(wait_fut.as_mut());
+
+            if self.transport.is_read_up_to(position)? {
+                return Ok(());
+            }
+            if r.is_pending() {
+                wait_fut.await;
+            }
+        }
+    }
+
+    /// See [`transport::Sender::stats`].
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> transport::Stats {
+        self.transport.stats()
+    }
+
+    /// See [`transport::Sender::high_water_mark`].
+    #[cfg(feature = "stats")]
+    pub fn high_water_mark(&self) -> usize {
+        self.transport.high_water_mark()
+    }
+}
+
+impl<M, const ALIGN: usize, O, B> embedded_io::ErrorType for Sender<M, ALIGN, O, B>
+where
+    M: Notifier,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    type Error = transport::SendError;
+}
+
+impl<M, const ALIGN: usize, O, B> embedded_io_async::Write for Sender<M, ALIGN, O, B>
+where
+    M: Notifier,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Send all of `buf` as a single message.
+    ///
+    /// This does not wait for space in the ring, and fails with
+    /// [`SendError::InsufficientCapacity`][transport::SendError::InsufficientCapacity] instead.
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.send(buf)?;
+        Ok(buf.len())
+    }
+
+    /// Messages are sent immediately, so this does nothing.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "notify-on-drop")]
+impl<M, const ALIGN: usize, O, B> Drop for Sender<M, ALIGN, O, B>
+where
+    M: Notifier,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.transport.send_typed(&CLOSE_MAGIC, transport::CONTROL);
+        }
+    }
+}
+
+pub struct Receiver<W, const ALIGN: usize, O = NoObserver, B = NoBarrier>
+where
+    W: WaitForNotify,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    state: RecvState<ALIGN, O, B>,
+    waiter: W,
+}
+
+impl<W, const ALIGN: usize, O, B> Receiver<W, ALIGN, O, B>
+where
+    W: WaitForNotify,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Wrap a low-level transport half whose channel has already bonded. See
+    /// [`IcMsg::from_raw_parts`].
+    pub fn from_transport(transport: transport::Receiver<ALIGN, O, B>, waiter: W) -> Self {
+        Self {
+            state: RecvState::new(transport, None, &MAGIC),
+            waiter,
+        }
+    }
+
+    /// Unwrap the low-level transport half and the waiter.
+    pub fn into_transport(self) -> (transport::Receiver<ALIGN, O, B>, W) {
+        (self.state.transport, self.waiter)
+    }
+
+    pub fn transport(&self) -> &transport::Receiver<ALIGN, O, B> {
+        &self.state.transport
+    }
+
+    /// Receiving through the transport half in the middle of a message that is being read with
+    /// [`Read::read`][embedded_io_async::Read::read] makes the next `read` skip the start of the
+    /// following message.
+    pub fn transport_mut(&mut self) -> &mut transport::Receiver<ALIGN, O, B> {
+        &mut self.state.transport
+    }
+
+    /// Replace the [`Observer`] that is called on receive events. See
+    /// [`transport::Receiver::with_observer`].
+    pub fn with_observer<O2: Observer>(self, observer: O2) -> Receiver<W, ALIGN, O2, B> {
+        Receiver {
+            state: self.state.with_observer(observer),
+            waiter: self.waiter,
+        }
+    }
+
+    /// Replace the [`Barrier`]. See [`transport::Receiver::with_barrier`].
+    pub fn with_barrier<B2: Barrier>(self, barrier: B2) -> Receiver<W, ALIGN, O, B2> {
+        Receiver {
+            state: self.state.with_barrier(barrier),
+            waiter: self.waiter,
+        }
+    }
+
+    /// Try to receive a message if one is available. On success, returns the size of the message.
+    ///
+    /// If [session-aware bonding][BondingConfig::session_id] is in use and the other side is seen
+    /// to have bonded again with a different session ID, this returns
+    /// [`RecvError::SessionLost`][transport::RecvError::SessionLost].
+    pub fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, transport::RecvError> {
+        self.state.try_recv(msg)
+    }
+
+    /// Try to receive a message into a buffer that may be uninitialized. See
+    /// [`transport::Receiver::try_recv_uninit`].
+    pub fn try_recv_uninit(
+        &mut self,
+        msg: &mut [MaybeUninit<u8>],
+    ) -> Result<usize, transport::RecvError> {
+        self.state.try_recv_uninit(msg)
+    }
+
+    /// Try to receive a message of up to `N` bytes into a [`heapless::Vec`].
+    ///
+    /// If the message is bigger than `N` bytes, this fails with
+    /// [`RecvError::MessageTooBig`][transport::RecvError::MessageTooBig] and the message is kept.
+    #[cfg(feature = "heapless")]
+    pub fn try_recv_exact<const N: usize>(
+        &mut self,
+    ) -> Result<heapless::Vec<u8, N>, transport::RecvError> {
+        self.state.try_recv_exact()
+    }
+
+    /// Try to receive a message along with the flags it was sent with. See
+    /// [`transport::Receiver::try_recv_typed`].
+    pub fn try_recv_typed(&mut self, msg: &mut [u8]) -> Result<(usize, u8), transport::RecvError> {
+        self.state.skip_control_messages()?;
+        let r = self.state.transport.try_recv_typed(msg)?;
+        self.state.read_offset = 0;
+        Ok(r)
+    }
+
+    /// Try to receive a message into the concatenation of `bufs`. See
+    /// [`transport::Receiver::try_recv_vectored`].
+    pub fn try_recv_vectored(
+        &mut self,
+        bufs: &mut [&mut [u8]],
+    ) -> Result<usize, transport::RecvError> {
+        self.state.skip_control_messages()?;
+        let n = self.state.transport.try_recv_vectored(bufs)?;
+        self.state.read_offset = 0;
+        Ok(n)
+    }
+
+    /// Receive all messages that are currently queued, passing each one to `on_msg`, and publish
+    /// `rd_idx` once at the end. Returns how many were received. See
+    /// [`transport::Receiver::try_recv_many`].
+    ///
+    /// Control messages from the other side end the batch early, and are handled by the next
+    /// call.
+    pub fn try_recv_many(
+        &mut self,
+        scratch: &mut [u8],
+        on_msg: impl FnMut(&[u8]) -> ControlFlow<()>,
+    ) -> Result<usize, transport::RecvError> {
+        self.state.try_recv_many(scratch, on_msg)
+    }
+
+    /// Try to receive a message, truncating it if it doesn't fit in `msg`. On success, returns the
+    /// number of bytes copied and the number of bytes dropped.
+    ///
+    /// Unlike [`try_recv`][Self::try_recv], this never fails with
+    /// [`RecvError::MessageTooBig`][transport::RecvError::MessageTooBig], which suits lossy
+    /// streams where the caller can't always provide a big enough buffer.
+    pub fn try_recv_partial(
+        &mut self,
+        msg: &mut [u8],
+    ) -> Result<(usize, usize), transport::RecvError> {
+        self.state.try_recv_partial(msg)
+    }
+
+    /// Return the size of the next message without receiving it.
+    ///
+    /// A following [`try_recv`][Self::try_recv] receives the same message.
+    pub fn peek_len(&mut self) -> Result<usize, transport::RecvError> {
+        self.state.skip_control_messages()?;
+        self.state.transport.peek_len()
+    }
+
+    /// Skip the next message without copying it, e.g. after
+    /// [`RecvError::MessageTooBig`][transport::RecvError::MessageTooBig]. On success, returns the
+    /// size of the skipped message.
+    pub fn discard_next(&mut self) -> Result<usize, transport::RecvError> {
+        self.state.skip_control_messages()?;
+        let n = self.state.transport.discard_next()?;
+        self.state.read_offset = 0;
+        Ok(n)
+    }
+
+    /// Discard all messages waiting to be received, including the remainder of a message that
+    /// was partially read through [`embedded_io_async::Read`]. Returns the number of messages
+    /// discarded.
+    pub fn clear(&mut self) -> usize {
+        self.state.read_offset = 0;
+        self.state.transport.clear()
+    }
+
+    /// Whether there are no messages waiting to be received.
+    pub fn is_empty(&self) -> bool {
+        self.state.transport.is_empty()
+    }
+
+    /// The number of bytes waiting to be received, including packet headers and padding.
+    pub fn pending_bytes(&self) -> usize {
+        self.state.transport.pending_bytes()
+    }
+
+    /// See [`transport::Receiver::region`].
+    pub fn region(&self) -> (NonNull<()>, u32) {
+        self.state.transport.region()
+    }
+
+    /// The protocol version the other side sent during bonding, 0 if it sent the bare magic like
+    /// the reference implementation. `None` if this channel was not bonded, e.g. if it was
+    /// [resumed][IcMsg::resume].
+    pub fn peer_version(&self) -> Option<u8> {
+        self.state.peer_version
+    }
+
+    /// The [feature bits][BondingConfig::features] the other side sent during bonding, `None` if it
+    /// didn't send any or if this channel was not bonded.
+    pub fn peer_features(&self) -> Option<u32> {
+        self.state.peer_features
+    }
+
+    /// See [`transport::Receiver::stats`].
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> transport::Stats {
+        self.state.transport.stats()
+    }
+
+    /// See [`transport::Receiver::high_water_mark`].
+    #[cfg(feature = "stats")]
+    pub fn high_water_mark(&self) -> usize {
+        self.state.transport.high_water_mark()
+    }
+
+    /// Wait for and receive a message. On success, returns the size of the message.
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe, e.g. to race it against a shutdown signal with `select`. A message is
+    /// only consumed by the poll that returns it, so dropping the future never loses one. Nor
+    /// does it lose a notification: the ring is checked after the waiter is polled, on every
+    /// call, so a message that is already in the ring is returned by the first poll of the next
+    /// call even if the waiter only reports each notification once.
+    pub async fn recv(&mut self, msg: &mut [u8]) -> Result<usize, transport::RecvError> {
+        transport::recv_with(&mut self.waiter, || self.state.try_recv(msg)).await
+    }
+
+    /// Wait for and receive a message of up to `N` bytes into a [`heapless::Vec`]. See
+    /// [`try_recv_exact`][Self::try_recv_exact].
+    #[cfg(feature = "heapless")]
+    pub async fn recv_exact<const N: usize>(
+        &mut self,
+    ) -> Result<heapless::Vec<u8, N>, transport::RecvError> {
+        transport::recv_with(&mut self.waiter, || self.state.try_recv_exact()).await
+    }
+
+    /// Wait for and receive a message sent by [`Sender::send_fragmented`], reassembling its
+    /// fragments into `msg`. On success, returns the size of the whole message.
+    ///
+    /// See [`transport::Receiver::try_recv_reassembled`] for how errors are reported. If this
+    /// future is dropped or fails partway through a message, the rest of its fragments are
+    /// received as separate messages.
+    pub async fn recv_reassembled(
+        &mut self,
+        msg: &mut [u8],
+    ) -> Result<usize, transport::RecvError> {
+        let state = &mut self.state;
+        let mut received = 0;
+        transport::recv_with(&mut self.waiter, || {
+            state.try_recv_reassembled(msg, &mut received)
+        })
+        .await
+    }
+
+    /// Like [`recv`][Self::recv], but gives up with [`RecvTimeoutError::TimedOut`] if no message
+    /// arrives within `timeout_us` microseconds, e.g. to notice a stalled other side.
+    ///
+    /// This is built on [`recv`][Self::recv], so the waiter is registered before the ring is
+    /// checked and a message that arrives in between is not missed. A message is only consumed
+    /// when it is returned, so no message is lost when the timeout fires or when this future is
+    /// dropped.
+    pub async fn recv_timeout(
+        &mut self,
+        msg: &mut [u8],
+        timeout_us: u32,
+        mut delay: impl DelayNs,
+    ) -> Result<usize, RecvTimeoutError> {
+        // `select` polls `recv` first, so a message that is already available wins over a timeout
+        // that fires at the same time.
+        match select(self.recv(msg), delay.delay_us(timeout_us)).await {
+            Either::First(r) => Ok(r?),
+            Either::Second(()) => Err(RecvTimeoutError::TimedOut),
+        }
+    }
+}
+
+impl<W, const ALIGN: usize, O, B> Receiver<W, ALIGN, O, B>
+where
+    W: WaitForNotify + PollWaitForNotify,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Poll for a message, for use in hand-written futures. On success, returns the size of the
+    /// message.
+    ///
+    /// If there is no message, `cx`'s waker is woken by the next notification. Like
+    /// [`recv`][Self::recv], the waker is registered before the ring is checked, so a message that
+    /// arrives in between is not missed. `recv` is equivalent to
+    /// `poll_fn(|cx| receiver.poll_recv(cx, msg)).await`.
+    pub fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        msg: &mut [u8],
+    ) -> Poll<Result<usize, transport::RecvError>> {
+        loop {
+            let r = self.waiter.poll_wait_for_notify(cx);
+
+            match self.state.try_recv(msg) {
+                Err(transport::RecvError::Empty) => {
+                    if r.is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+                r => return Poll::Ready(r),
+            }
+        }
+    }
+}
+
+impl<W, const ALIGN: usize, O, B> embedded_io::ErrorType for Receiver<W, ALIGN, O, B>
+where
+    W: WaitForNotify,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    type Error = transport::RecvError;
+}
+
+impl<W, const ALIGN: usize, O, B> embedded_io_async::Read for Receiver<W, ALIGN, O, B>
+where
+    W: WaitForNotify,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Read bytes from the next message, waiting for one if necessary. This turns the receiver
+    /// into a byte stream, e.g. for parsers that read a packet in several steps, without
+    /// buffering messages outside the ring. To take each message out of the ring right away
+    /// instead, wrap the receiver in a [`ReceiverReader`][reader::ReceiverReader].
+    ///
+    /// A single call never returns bytes from more than one message. If `buf` is too small for the
+    /// rest of the message, the remainder is left in the ring and returned by the next call.
+    /// Zero-length messages are skipped, since returning 0 would signal end of file. End of file
+    /// is returned once the other side has [torn down][IcMsg::deinit] the channel.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match transport::recv_with(&mut self.waiter, || self.state.try_read(buf)).await {
+            Err(transport::RecvError::PeerClosed) => Ok(0),
+            r => r,
+        }
+    }
+}
+
+/// The receiving state of a [`Receiver`], kept separate from the waiter so the two can be borrowed
+/// independently.
+struct RecvState<const ALIGN: usize, O = NoObserver, B = NoBarrier>
+where
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    transport: transport::Receiver<ALIGN, O, B>,
+
+    // the other side's session ID, if session-aware bonding is in use
+    peer_session_id: Option<u16>,
+    // the bonding magic, to recognize the other side bonding again
+    magic: &'static [u8; 13],
+    // what the other side advertised during bonding, if this channel was bonded
+    peer_version: Option<u8>,
+    peer_features: Option<u32>,
+    // set once the other side's teardown message has been received
+    peer_closed: bool,
+    // how much of the next message has already been returned by `Read::read`
+    read_offset: usize,
+}
+
+impl<const ALIGN: usize, O, B> RecvState<ALIGN, O, B>
+where
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    fn new(
+        transport: transport::Receiver<ALIGN, O, B>,
+        peer_session_id: Option<u16>,
+        magic: &'static [u8; 13],
+    ) -> Self {
+        Self {
+            transport,
+            peer_session_id,
+            magic,
+            peer_version: None,
+            peer_features: None,
+            peer_closed: false,
+            read_offset: 0,
+        }
+    }
+
+    /// The receiving state after bonding, which remembers what the other side's bonding message
+    /// carried.
+    fn bonded(
+        transport: transport::Receiver<ALIGN, O, B>,
+        bonding_config: &BondingConfig,
+        peer: &BondingMessage,
+    ) -> Self {
+        let mut state = Self::new(
+            transport,
+            bonding_config.session_id.and(peer.session_id),
+            bonding_config.magic,
+        );
+        state.peer_version = Some(peer.version);
+        state.peer_features = peer.features;
+        state
+    }
+
+    fn with_observer<O2: Observer>(self, observer: O2) -> RecvState<ALIGN, O2, B> {
+        RecvState {
+            transport: self.transport.with_observer(observer),
+            peer_session_id: self.peer_session_id,
+            magic: self.magic,
+            peer_version: self.peer_version,
+            peer_features: self.peer_features,
+            peer_closed: self.peer_closed,
+            read_offset: self.read_offset,
+        }
+    }
+
+    fn with_barrier<B2: Barrier>(self, barrier: B2) -> RecvState<ALIGN, O, B2> {
+        RecvState {
+            transport: self.transport.with_barrier(barrier),
+            peer_session_id: self.peer_session_id,
+            magic: self.magic,
+            peer_version: self.peer_version,
+            peer_features: self.peer_features,
+            peer_closed: self.peer_closed,
+            read_offset: self.read_offset,
+        }
+    }
+
+    fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, transport::RecvError> {
+        self.skip_control_messages()?;
+        let n = self.transport.try_recv(msg)?;
+        self.read_offset = 0;
+        Ok(n)
+    }
+
+    /// See [`transport::Receiver::try_recv_reassembled`]. Control messages are only looked for
+    /// before the first fragment.
+    fn try_recv_reassembled(
+        &mut self,
+        msg: &mut [u8],
+        received: &mut usize,
+    ) -> Result<usize, transport::RecvError> {
+        if *received == 0 {
+            self.skip_control_messages()?;
+        }
+        let n = self.transport.try_recv_reassembled(msg, received)?;
+        self.read_offset = 0;
+        Ok(n)
+    }
+
+    fn try_recv_uninit(
+        &mut self,
+        msg: &mut [MaybeUninit<u8>],
+    ) -> Result<usize, transport::RecvError> {
+        self.skip_control_messages()?;
+        let n = self.transport.try_recv_uninit(msg)?;
+        self.read_offset = 0;
+        Ok(n)
+    }
+
+    /// Receive the next message and return whether it is equal to `expected`, without copying it
+    /// out of the ring.
+    fn try_recv_eq(&mut self, expected: &[u8]) -> Result<bool, transport::RecvError> {
+        self.skip_control_messages()?;
+        let packet = self.transport.next_packet()?;
+        let mut equal = packet.len == expected.len();
+        let mut chunk = [0; 16];
+        let mut offset = 0;
+        while equal && offset < packet.len {
+            let n = chunk.len().min(packet.len - offset);
+            self.transport.copy_packet(&packet, offset, &mut chunk[..n]);
+            equal = chunk[..n] == expected[offset..offset + n];
+            offset += n;
+        }
+        self.transport.consume_packet(&packet);
+        self.read_offset = 0;
+        Ok(equal)
+    }
+
+    #[cfg(feature = "heapless")]
+    fn try_recv_exact<const N: usize>(
+        &mut self,
+    ) -> Result<heapless::Vec<u8, N>, transport::RecvError> {
+        let mut msg = heapless::Vec::new();
+        let n = self.try_recv_uninit(msg.spare_capacity_mut())?;
+        // SAFETY: `try_recv_uninit` initialized the first `n` bytes.
+        unsafe { msg.set_len(n) };
+        Ok(msg)
+    }
+
+    fn try_recv_partial(&mut self, msg: &mut [u8]) -> Result<(usize, usize), transport::RecvError> {
+        self.skip_control_messages()?;
+        let r = self.transport.try_recv_partial(msg)?;
+        self.read_offset = 0;
+        Ok(r)
+    }
+
+    fn try_recv_many(
+        &mut self,
+        scratch: &mut [u8],
+        mut on_msg: impl FnMut(&[u8]) -> ControlFlow<()>,
+    ) -> Result<usize, transport::RecvError> {
+        self.skip_control_messages()?;
+        // Control messages are only recognized at the head of the ring, so stop the batch at the
+        // first one.
+        let (peer_session_id, magic) = (self.peer_session_id, self.magic);
+        let is_control_message = |msg: &[u8], flags: u8| {
+            flags & transport::CONTROL != 0
+                && (*msg == CLOSE_MAGIC
+                    || peer_session_id.is_some()
+                        && parse_bonding_message(msg, magic)
+                            .is_some_and(|m| m.session_id.is_some()))
+        };
+        let mut received = false;
+        let r = self
+            .transport
+            .try_recv_many_until(scratch, is_control_message, |msg| {
+                received = true;
+                on_msg(msg)
+            });
+        if received {
+            self.read_offset = 0;
+        }
+        r
+    }
+
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize, transport::RecvError> {
+        loop {
+            if self.read_offset == 0 {
+                self.skip_control_messages()?;
+            }
+            let packet = self.transport.next_packet()?;
+            if packet.len == 0 {
+                self.transport.consume_packet(&packet);
+                continue;
+            }
+
+            let n = buf.len().min(packet.len - self.read_offset);
+            self.transport
+                .copy_packet(&packet, self.read_offset, &mut buf[..n]);
+            self.read_offset += n;
+            if self.read_offset == packet.len {
+                self.transport.consume_packet(&packet);
+                self.read_offset = 0;
+            }
+            return Ok(n);
+        }
+    }
+
+    /// Handle the control messages at the head of the ring.
+    ///
+    /// Control messages are only recognized if they are sent as [`CONTROL`][transport::CONTROL]
+    /// packets, so application messages with the same contents are received as usual.
+    ///
+    /// Returns [`RecvError::PeerClosed`][transport::RecvError::PeerClosed] if the next message is
+    /// the other side's teardown message, and for every later call until the channel bonds again.
+    /// The teardown message itself is consumed, so that the other side sees it read. If
+    /// session-aware bonding is in use, also consumes any bonding messages, returning
+    /// [`RecvError::SessionLost`][transport::RecvError::SessionLost] if the other side has
+    /// restarted.
+    fn skip_control_messages(&mut self) -> Result<(), transport::RecvError> {
+        if self.peer_closed {
+            return Err(transport::RecvError::PeerClosed);
+        }
+        if self.peer_session_id.is_some() {
+            self.transport.detect_peer_reset();
+        }
+        loop {
+            let packet = match self.transport.next_packet() {
+                Ok(packet) => packet,
+                Err(transport::RecvError::Empty) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            // Only the teardown message and bonding messages that carry a session ID can be seen
+            // here.
+            let mut message = [0; protocol::MAX_BONDING_MESSAGE_LEN];
+            if packet.flags & transport::CONTROL == 0
+                || !(CLOSE_MAGIC.len()..=message.len()).contains(&packet.len)
+            {
+                return Ok(());
+            }
+            let message = &mut message[..packet.len];
+            self.transport.copy_packet(&packet, 0, message);
+            if *message == CLOSE_MAGIC {
+                self.transport.consume_packet(&packet);
+                self.peer_closed = true;
+                return Err(transport::RecvError::PeerClosed);
+            }
+            let Some(current_id) = self.peer_session_id else {
+                return Ok(());
+            };
+            match parse_bonding_message(message, self.magic).and_then(|m| m.session_id) {
+                // The other side re-sent its bonding message without restarting, ignore it.
+                Some(id) if id == current_id => self.transport.consume_packet(&packet),
+                Some(id) => {
+                    self.transport.consume_packet(&packet);
+                    self.peer_session_id = Some(id);
+                    return Err(transport::RecvError::SessionLost);
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Create a new low-level transport and perform [bonding][bond] without an async executor.
+///
+/// Instead of awaiting a [`WaitForNotify`], this busy-waits on `poll_notified`, which should
+/// return `true` once a notification from the other side has been received since the last call.
+/// The other side is re-notified every `retry_polls` calls to `poll_notified`. Since the interval
+/// is measured in loop iterations rather than time, the core spins at full speed for the whole
+/// handshake; put a short delay or a `wfe` in `poll_notified` if that matters.
+///
+/// # Safety
+///
+/// The provided [`MemoryConfig`] must be correct.
+///
+/// [bond]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#bonding
+pub unsafe fn init_blocking<M, const ALIGN: usize>(
+    config: MemoryConfig,
+    notifier: M,
+    mut poll_notified: impl FnMut() -> bool,
+    retry_polls: u32,
+) -> Result<IcMsgTransport<M, ALIGN>, InitError>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    let transport = unsafe { new_transport(config, notifier)? };
+    let mut bonder = Bonder::new(transport, BondingConfig::default())?;
+
+    let notified = Cell::new(false);
+    let idle = || {
+        for _ in 0..retry_polls.max(1) {
+            if poll_notified() {
+                notified.set(true);
+                return;
+            }
+        }
+    };
+    blocking::drive(|n| bonder.poll_bonded(n), || notified.take(), idle)
+        .map(|(transport, _)| transport)
+}
+
+/// Call `spin` `spins` times, yielding to the executor after each call.
+async fn spin_and_yield(spin: &mut impl FnMut(), spins: u32) {
+    for _ in 0..spins {
+        spin();
+        let mut yielded = false;
+        core::future::poll_fn(|cx| {
+            if yielded {
+                return Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await;
+    }
+}
+
+/// [Bonding][bond] driven by hand, for main loops and threads without an async executor.
+///
+/// Sending the bonding message is started by [`new`][Self::new]. After that,
+/// [`poll`][Self::poll] has to be called every
+/// [`retry_interval_ms`][BondingConfig::retry_interval_ms], and as soon as a notification from
+/// the other side is received, until it returns [`BondPoll::Ready`] or [`BondPoll::Failed`].
+///
+/// [bond]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#bonding
+pub struct Bonder<M, const ALIGN: usize, B = NoBarrier>
+where
+    M: Notifier,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    // `None` once bonding is done
+    transport: Option<IcMsgTransport<M, ALIGN, B>>,
+    state: BondState,
+}
+
+/// The result of [`Bonder::poll`].
+pub enum BondPoll<M, const ALIGN: usize, B = NoBarrier>
+where
+    M: Notifier,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// The other side has not answered yet. It has been notified again.
+    Pending,
+    /// Bonding is done and the transport is ready to use.
+    Ready(IcMsgTransport<M, ALIGN, B>),
+    /// Bonding failed.
+    Failed(InitError),
+}
+
+impl<M, const ALIGN: usize, B> Bonder<M, ALIGN, B>
+where
+    M: Notifier,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Start bonding on a newly created transport by sending the bonding message. The transport's
+    /// [`Barrier`], if any, is run during bonding as well.
+    pub fn new(
+        mut transport: IcMsgTransport<M, ALIGN, B>,
+        bonding_config: BondingConfig,
+    ) -> Result<Self, InitError> {
+        let (sender, receiver) = transport.split_mut();
+        let state = BondState::start(sender, receiver, bonding_config)?;
+        Ok(Self {
+            transport: Some(transport),
+            state,
+        })
+    }
+
+    /// Advance bonding. `notified` must be `true` if a notification from the other side has been
+    /// received since the last call.
+    ///
+    /// Each call without a notification counts as one retry interval towards
+    /// [`timeout_ms`][BondingConfig::timeout_ms].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called again after returning [`BondPoll::Ready`].
+    pub fn poll(&mut self, notified: bool) -> BondPoll<M, ALIGN, B> {
+        match self.poll_bonded(notified) {
+            Poll::Pending => BondPoll::Pending,
+            Poll::Ready(Ok((transport, _))) => BondPoll::Ready(transport),
+            Poll::Ready(Err(e)) => BondPoll::Failed(e),
+        }
+    }
+
+    /// Like [`poll`][Self::poll], but also returns what the other side's bonding message carried.
+    fn poll_bonded(&mut self, notified: bool) -> Poll<Result<Bonded<M, ALIGN, B>, InitError>> {
+        let transport = self
+            .transport
+            .as_mut()
+            .expect("Bonder polled after bonding completed");
+        let (s, r) = transport.split_mut();
+        let peer = match self.state.poll(s, r, notified) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(r) => r?,
+        };
+        Poll::Ready(Ok((self.transport.take().unwrap(), peer)))
+    }
+
+    /// Like [`poll`][Self::poll], but splits the bonded transport into a high-level [`Sender`] and
+    /// the receiving state of a [`Receiver`], which know about the other side's session.
+    fn poll_split(&mut self, notified: bool) -> Poll<Result<BondedHalves<M, ALIGN, B>, InitError>> {
+        let (transport, peer) = match self.poll_bonded(notified) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(r) => r?,
+        };
+        let (s, r) = transport.split();
+        let state = RecvState::bonded(r, &self.state.bonding_config, &peer);
+        Poll::Ready(Ok((Sender::from_transport(s), state)))
+    }
+}
+
+/// A bonded transport and the other side's bonding message.
+type Bonded<M, const ALIGN: usize, B> = (IcMsgTransport<M, ALIGN, B>, BondingMessage);
+
+/// A bonded transport split into a high-level [`Sender`] and the receiving state of a [`Receiver`].
+type BondedHalves<M, const ALIGN: usize, B> = (
+    Sender<M, ALIGN, NoObserver, B>,
+    RecvState<ALIGN, NoObserver, B>,
+);
+
+/// The bonding handshake after the bonding message has been sent, shared by [`Bonder`] and the
+/// async [`bond`].
+struct BondState {
+    bonding_config: BondingConfig,
+    elapsed_ms: u32,
+    // this side's send and receive buffer lengths
+    buffer_lens: (u32, u32),
+}
+
+impl BondState {
+    fn start<M, const ALIGN: usize, O, B>(
+        sender: &mut transport::Sender<M, ALIGN, O, B>,
+        receiver: &mut transport::Receiver<ALIGN, O, B>,
+        bonding_config: BondingConfig,
+    ) -> Result<Self, InitError>
+    where
+        M: Notifier,
+        O: Observer,
+        B: Barrier,
+        elain::Align<ALIGN>: elain::Alignment,
+    {
+        sender.set_crc(bonding_config.crc);
+        receiver.set_crc(bonding_config.crc);
+        sender.set_sequence(bonding_config.sequence);
+        receiver.set_sequence(bonding_config.sequence);
+        let buffer_lens = (sender.region().1, receiver.region().1);
+        send_magic(sender, &bonding_config, buffer_lens)?;
+        Ok(Self {
+            bonding_config,
+            elapsed_ms: 0,
+            buffer_lens,
+        })
+    }
+
+    /// Re-notify the other side until it notifies us, then check its bonding message and enable
+    /// the features both sides advertised. Returns what the other side's bonding message carried.
+    fn poll<M, const ALIGN: usize, O, B>(
+        &mut self,
+        sender: &mut transport::Sender<M, ALIGN, O, B>,
+        receiver: &mut transport::Receiver<ALIGN, O, B>,
+        notified: bool,
+    ) -> Poll<Result<BondingMessage, InitError>>
+    where
+        M: Notifier,
+        O: Observer,
+        B: Barrier,
+        elain::Align<ALIGN>: elain::Alignment,
+    {
+        let config = &self.bonding_config;
+        if !notified {
+            self.elapsed_ms = self.elapsed_ms.saturating_add(self.retry_interval_ms());
+            if config.timeout_ms.is_some_and(|t| self.elapsed_ms >= t) {
+                return Poll::Ready(Err(InitError::BondingTimeout));
+            }
+            sender.notify();
+            return Poll::Pending;
+        }
+        sender.notify();
+        let theirs = match recv_magic(receiver, config, self.buffer_lens) {
+            Ok(theirs) => theirs,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        // Our bonding message was sent before the other side's was seen, so the negotiated
+        // features only apply to the messages after it, in both directions.
+        let common = config.features.unwrap_or(0) & theirs.features.unwrap_or(0);
+        if common & protocol::FEATURE_CRC != 0 {
+            sender.set_crc(true);
+            receiver.set_crc(true);
+        }
+        if common & protocol::FEATURE_SEQUENCE != 0 {
+            sender.set_sequence(true);
+            receiver.set_sequence(true);
+        }
+        Poll::Ready(Ok(theirs))
+    }
+
+    /// The retry interval, with 0 treated as 1 so that the timeout still fires.
+    fn retry_interval_ms(&self) -> u32 {
+        self.bonding_config.retry_interval_ms.max(1)
+    }
+}
+
+/// Validate the config and create the transport.
+///
+/// # Safety
+///
+/// The provided [`MemoryConfig`] must be correct.
+unsafe fn new_transport<M, const ALIGN: usize>(
+    config: MemoryConfig,
+    notifier: M,
+) -> Result<IcMsgTransport<M, ALIGN>, InitError>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    config.check::<ALIGN>()?;
+
+    Ok(unsafe {
+        IcMsgTransport::new(
+            config.send_region,
+            config.recv_region,
+            config.send_buffer_len,
+            config.recv_buffer_len,
+            notifier,
+        )
+    })
+}
+
+/// Send the bonding message: the magic, followed by the protocol version, the presence byte, and
+/// the feature bits, the session ID and this side's send and receive buffer lengths if they are
+/// configured.
+///
+/// The bare magic is sent if none of them is configured. Otherwise the version is 0 if none is
+/// configured.
+fn send_magic<M, const ALIGN: usize, O, B>(
+    sender: &mut transport::Sender<M, ALIGN, O, B>,
+    bonding_config: &BondingConfig,
+    buffer_lens: (u32, u32),
+) -> Result<(), InitError>
+where
+    M: Notifier,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    let magic = bonding_config.magic;
+    let mut message = [0; protocol::MAX_BONDING_MESSAGE_LEN];
+    message[..magic.len()].copy_from_slice(magic);
+    let mut len = magic.len();
+    if bonding_config.protocol_version.is_some()
+        || bonding_config.features.is_some()
+        || bonding_config.session_id.is_some()
+        || bonding_config.check_buffer_lens
+    {
+        message[len] = bonding_config.protocol_version.unwrap_or(0);
+        let presence = len + 1;
+        len += 2;
+        if let Some(features) = bonding_config.features {
+            message[presence] |= protocol::BONDING_FEATURES;
+            message[len..len + 4].copy_from_slice(&features.to_le_bytes());
+            len += 4;
+        }
+        if let Some(id) = bonding_config.session_id {
+            message[presence] |= protocol::BONDING_SESSION_ID;
+            message[len..len + 2].copy_from_slice(&id.to_le_bytes());
+            len += 2;
+        }
+        if bonding_config.check_buffer_lens {
+            message[presence] |= protocol::BONDING_BUFFER_LENS;
+            message[len..len + 4].copy_from_slice(&buffer_lens.0.to_le_bytes());
+            message[len + 4..len + 8].copy_from_slice(&buffer_lens.1.to_le_bytes());
+            len += 8;
+        }
+    }
+    sender
+        .send_typed(&message[..len], transport::CONTROL)
+        .map_err(InitError::BondingSendError)
+}
+
+/// Send the bonding message and wait for the other side's, re-notifying it every retry interval.
+/// Returns what the other side's bonding message carried.
+async fn bond<M, W, const ALIGN: usize, O, B>(
+    sender: &mut transport::Sender<M, ALIGN, O, B>,
+    receiver: &mut transport::Receiver<ALIGN, O, B>,
+    waiter: &mut W,
+    delay: &mut impl DelayNs,
+    bonding_config: BondingConfig,
+) -> Result<BondingMessage, InitError>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    // Start waiting before sending, in case the other side answers right away.
+    let mut wait_fut = pin!(waiter.wait_for_notify());
+    let mut state = BondState::start(sender, receiver, bonding_config)?;
+    loop {
+        let timeout = delay.delay_ms(state.retry_interval_ms());
+        // A notification always completes bonding, so the finished wait is never polled again.
+        let notified = matches!(select(wait_fut.as_mut(), timeout).await, Either::First(_));
+        if let Poll::Ready(r) = state.poll(sender, receiver, notified) {
+            return r;
+        }
+    }
+}
+
+/// Receive and check the other side's bonding message, after it has notified us.
+fn recv_magic<const ALIGN: usize, O, B>(
+    receiver: &mut transport::Receiver<ALIGN, O, B>,
+    bonding_config: &BondingConfig,
+    buffer_lens: (u32, u32),
+) -> Result<BondingMessage, InitError>
+where
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    // Allow larger messages for forward compatibility.
+    let mut message = [0; 32];
+    let n = receiver
+        .try_recv(&mut message)
+        .map_err(InitError::BondingRecvError)?;
+
+    let Some(theirs) = parse_bonding_message(&message[..n], bonding_config.magic) else {
+        return Err(InitError::BondingWrongMagic {
+            len: n,
+            data: message,
+        });
+    };
+
+    let ours = bonding_config.protocol_version.unwrap_or(0);
+    if theirs.version != ours && !bonding_config.accept_any_version {
+        return Err(InitError::VersionMismatch {
+            ours,
+            theirs: theirs.version,
+        });
+    }
+
+    // Our send buffer is their receive buffer and the other way around.
+    if bonding_config.check_buffer_lens
+        && let Some((their_send, their_recv)) = theirs.buffer_lens
+    {
+        let (our_send, our_recv) = buffer_lens;
+        if our_send != their_recv {
+            return Err(InitError::BufferLenMismatch {
+                ours: our_send,
+                theirs: their_recv,
+            });
+        }
+        if our_recv != their_send {
+            return Err(InitError::BufferLenMismatch {
+                ours: our_recv,
+                theirs: their_send,
+            });
+        }
+    }
+
+    Ok(theirs)
+}
+
+/// The contents of a bonding message after the magic.
+struct BondingMessage {
+    version: u8,
+    features: Option<u32>,
+    session_id: Option<u16>,
+    // the sender's send and receive buffer lengths
+    buffer_lens: Option<(u32, u32)>,
+}
+
+/// If `message` is a bonding message starting with `magic`, return what it carries.
+///
+/// The magic is optionally followed by a 1 byte protocol version and a presence byte that tells
+/// which of the other fields follow, see [`protocol::BONDING_FEATURES`]. A missing version is
+/// version 0, which is what the reference implementation sends. Unknown presence bits and bytes
+/// after the known fields are ignored for forward compatibility, but a message that is too short
+/// for the fields it claims to carry is not a bonding message.
+fn parse_bonding_message(message: &[u8], magic: &[u8; 13]) -> Option<BondingMessage> {
+    let u32_at = |bytes: &[u8], i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+    let (version, presence, mut rest) = match message.strip_prefix(magic)? {
+        [] => (0, 0, &[][..]),
+        [version] => (*version, 0, &[][..]),
+        [version, presence, rest @ ..] => (*version, *presence, rest),
+    };
+    let mut field = |bit: u8, len: usize| {
+        if presence & bit == 0 {
+            return Some(None);
+        }
+        let (field, tail) = rest.split_at_checked(len)?;
+        rest = tail;
+        Some(Some(field))
+    };
+    let features = field(protocol::BONDING_FEATURES, 4)?.map(|f| u32_at(f, 0));
+    let session_id =
+        field(protocol::BONDING_SESSION_ID, 2)?.map(|f| u16::from_le_bytes([f[0], f[1]]));
+    let buffer_lens =
+        field(protocol::BONDING_BUFFER_LENS, 8)?.map(|f| (u32_at(f, 0), u32_at(f, 4)));
+    Some(BondingMessage {
+        version,
+        features,
+        session_id,
+        buffer_lens,
+    })
+}
+
+/// The memory configuration of the channel.
+///
+/// `send_region` and `recv_region` must be properly aligned and appropriately sized.
+/// `send_buffer_len`/`recv_buffer_len` are the sizes of the
+/// [`data`][data] fields of the corresponding regions in bytes, which must be a multiple of 4.
+/// They should be at least 24 bytes large, which fits the bare bonding magic. The
+/// [`BondingConfig`] options that send more with it need more, see [`min_buffer_len`].
+///
+/// The two directions can be sized differently, e.g. a larger buffer for a core that mostly
+/// sends. Each core configures both lengths, so this core's `send_buffer_len` must equal the other
+/// core's `recv_buffer_len` and the other way around, otherwise the two sides wrap around at
+/// different places and the stream is corrupted.
+/// [`BondingConfig::check_buffer_lens`] catches a mismatch during bonding.
+///
+/// If data caching is enabled, the shared memory region provided to ICMsg must be aligned according
+/// to the cache requirement. If cache is not enabled, the required alignment is [4 bytes][ref].
+///
+/// [ref]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#shared-memory-region-organization
+/// [data]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#shared-memory-region-organization
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryConfig {
+    /// Pointer to the send memory region.
+    pub send_region: NonNull<()>,
+    /// Pointer to the recv memory region.
+    pub recv_region: NonNull<()>,
+    /// Size of the [data][data] field of the send memory region in bytes.
+    ///
+    /// [data]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#shared-memory-region-organization
+    pub send_buffer_len: u32,
+    /// Size of the [data][data] field of the recv memory region in bytes.
+    ///
+    /// [data]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#shared-memory-region-organization
+    pub recv_buffer_len: u32,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for MemoryConfig {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "MemoryConfig {{ send_region: {=usize:#x}, recv_region: {=usize:#x}, send_buffer_len: {=u32}, recv_buffer_len: {=u32} }}",
+            self.send_region.as_ptr().addr(),
+            self.recv_region.as_ptr().addr(),
+            self.send_buffer_len,
+            self.recv_buffer_len,
+        )
+    }
+}
+
+/// Parameters of the [bonding][bond] handshake performed by
+/// [`IcMsg::init_with_bonding_config`].
+///
+/// [bond]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#bonding
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BondingConfig {
+    /// How often the other side is re-notified while waiting for it, in milliseconds. 0 is treated
+    /// as 1.
+    pub retry_interval_ms: u32,
+    /// How long to wait for the other side before giving up with [`InitError::BondingTimeout`], in
+    /// milliseconds. `None` waits forever.
+    pub timeout_ms: Option<u32>,
+    /// Opt-in session ID sent along with the bonding magic.
+    ///
+    /// If both sides send a session ID, the [`Receiver`] remembers the other side's ID and returns
+    /// [`RecvError::SessionLost`][transport::RecvError::SessionLost] when it sees the other side
+    /// bond again with a different ID, i.e. when the other core has restarted. It is up to the
+    /// application to decide whether to bond again. Use a different ID every boot, e.g. a random
+    /// number or a boot counter. `None` sends the bare magic, like the reference implementation.
+    pub session_id: Option<u16>,
+    /// Opt-in protocol version sent along with the bonding magic.
+    ///
+    /// Bonding fails with [`InitError::VersionMismatch`] if the other side sends a different
+    /// version. A side that doesn't send a version, like the reference implementation, is treated as
+    /// version 0. `None` sends the bare magic.
+    pub protocol_version: Option<u8>,
+    /// Opt-in CRC checking of every message except the bonding and teardown messages. See
+    /// [`transport::Sender::set_crc`].
+    ///
+    /// Both sides have to enable it, otherwise bonding fails. It is not supported by the reference
+    /// implementation.
+    pub crc: bool,
+    /// Opt-in sequence numbers in every message except the bonding and teardown messages. See
+    /// [`transport::Sender::set_sequence`].
+    ///
+    /// Gaps are reported by [`RecvError::SequenceGap`][transport::RecvError::SequenceGap]. Only
+    /// enable it if the other side enables it as well, the reference implementation leaves the
+    /// header byte unspecified.
+    pub sequence: bool,
+    /// The magic sequence that starts the bonding message. Defaults to the one of the reference
+    /// implementation.
+    ///
+    /// Channels that share an IPC event can use different magics so that they don't bond with
+    /// each other by mistake: a channel that receives a different magic fails with
+    /// [`InitError::BondingWrongMagic`].
+    pub magic: &'static [u8; 13],
+    /// Opt-in exchange of the buffer lengths during bonding.
+    ///
+    /// Each side sends its send and receive buffer lengths along with the bonding magic, and
+    /// bonding fails with [`InitError::BufferLenMismatch`] if they don't match the other side's
+    /// receive and send buffer lengths, which would corrupt the stream. The check is only done if
+    /// both sides send their lengths, so this still bonds with the reference implementation. The
+    /// bonding message grows to up to 25 bytes, which needs buffers of at least 36 bytes. Along
+    /// with [`features`][Self::features], it is 29 bytes, which needs 40 bytes.
+    pub check_buffer_lens: bool,
+    /// Accept an other side that sends a different
+    /// [`protocol_version`][Self::protocol_version] instead of failing with
+    /// [`InitError::VersionMismatch`].
+    ///
+    /// The application can then look at [`IcMsg::peer_version`] and decide whether to carry on.
+    pub accept_any_version: bool,
+    /// Opt-in feature bits sent along with the bonding magic, e.g.
+    /// [`FEATURE_CRC`][protocol::FEATURE_CRC].
+    ///
+    /// The features that both sides advertise are enabled once bonding is done, for the messages
+    /// after the bonding messages. Unlike [`crc`][Self::crc] and [`sequence`][Self::sequence], this
+    /// still bonds with a side that doesn't support them, such as the reference implementation.
+    /// Unknown bits are sent but otherwise ignored. The other side's bits are available from
+    /// [`IcMsg::peer_features`].
+    pub features: Option<u32>,
+}
+
+impl Default for BondingConfig {
+    fn default() -> Self {
+        Self {
+            retry_interval_ms: 1,
+            timeout_ms: None,
+            session_id: None,
+            protocol_version: None,
+            crc: false,
+            sequence: false,
+            magic: &MAGIC,
+            check_buffer_lens: false,
+            accept_any_version: false,
+            features: None,
+        }
+    }
+}
+
+impl MemoryConfig {
+    /// Create a config from the region pointers and the lengths of their data fields.
+    ///
+    /// Region addresses that come as raw pointers, e.g. linker symbols, can be passed to
+    /// [`from_regions`][Self::from_regions], which also rejects null pointers, or converted with
+    /// [`NonNull::new`] first.
+    pub const fn new(
+        send_region: NonNull<()>,
+        recv_region: NonNull<()>,
+        send_buffer_len: u32,
+        recv_buffer_len: u32,
+    ) -> Self {
+        Self {
+            send_region,
+            recv_region,
+            send_buffer_len,
+            recv_buffer_len,
+        }
+    }
+
+    /// Create a config from the start address and total size in bytes of each shared memory
+    /// region, including the [`SharedMemoryRegionHeader`].
+    ///
+    /// Returns [`ConfigError::NullRegion`] if either region is null, [`ConfigError::Misaligned`]
+    /// if either region is not aligned to the header, [`ConfigError::TooSmall`] or
+    /// [`ConfigError::InvalidSize`] if the resulting data fields are too small or not a multiple
+    /// of 4 bytes, and [`ConfigError::Overlapping`] if the regions overlap.
+    ///
+    /// [`SharedMemoryRegionHeader`]: transport::SharedMemoryRegionHeader
+    pub fn from_regions<const ALIGN: usize>(
+        send_region: *mut (),
+        send_region_len: usize,
+        recv_region: *mut (),
+        recv_region_len: usize,
+    ) -> Result<Self, ConfigError>
+    where
+        elain::Align<ALIGN>: elain::Alignment,
+    {
+        let data_len = |region_len: usize| {
+            region_len
+                .checked_sub(header_size::<ALIGN>())
+                .and_then(|len| u32::try_from(len).ok())
+                .ok_or(ConfigError::TooSmall)
+        };
+        let (Some(send_region), Some(recv_region)) =
+            (NonNull::new(send_region), NonNull::new(recv_region))
+        else {
+            return Err(ConfigError::NullRegion);
+        };
+        let config = Self {
+            send_region,
+            recv_region,
+            send_buffer_len: data_len(send_region_len)?,
+            recv_buffer_len: data_len(recv_region_len)?,
+        };
+        config.check::<ALIGN>()?;
+        Ok(config)
+    }
+
+    /// Create a config from two regions of memory that are exclusively owned by the channel, e.g.
+    /// statics placed in the shared memory with `#[link_section]`. The regions include the
+    /// [`SharedMemoryRegionHeader`].
+    ///
+    /// The regions are checked the same way as in [`from_regions`][Self::from_regions]. The
+    /// returned config can be passed to the safe [`IcMsg::init_checked`].
+    ///
+    /// [`SharedMemoryRegionHeader`]: transport::SharedMemoryRegionHeader
+    pub fn from_slices<const ALIGN: usize>(
+        send_region: &'static mut [u8],
+        recv_region: &'static mut [u8],
+    ) -> Result<CheckedMemoryConfig<ALIGN>, ConfigError>
+    where
+        elain::Align<ALIGN>: elain::Alignment,
+    {
+        let config = Self::from_regions::<ALIGN>(
+            send_region.as_mut_ptr().cast(),
+            send_region.len(),
+            recv_region.as_mut_ptr().cast(),
+            recv_region.len(),
+        )?;
+        Ok(CheckedMemoryConfig { config })
+    }
+
+    /// Create a config from two [`IcMsgBuffer`]s. The returned config can be passed to the safe
+    /// [`IcMsg::init_checked`].
+    pub fn from_buffers<const SEND: usize, const RECV: usize, const ALIGN: usize>(
+        send_buffer: &'static mut IcMsgBuffer<SEND, ALIGN>,
+        recv_buffer: &'static mut IcMsgBuffer<RECV, ALIGN>,
+    ) -> CheckedMemoryConfig<ALIGN>
+    where
+        elain::Align<ALIGN>: elain::Alignment,
+    {
+        // The sizes are checked when the buffers are created, and two `&'static mut` can't overlap.
+        let config = Self {
+            send_region: NonNull::from(send_buffer).cast(),
+            recv_region: NonNull::from(recv_buffer).cast(),
+            send_buffer_len: SEND as u32,
+            recv_buffer_len: RECV as u32,
+        };
+        CheckedMemoryConfig { config }
+    }
+
+    /// Check everything about the config that can be checked without touching the regions.
+    fn check<const ALIGN: usize>(&self) -> Result<(), ConfigError>
+    where
+        elain::Align<ALIGN>: elain::Alignment,
+    {
+        type Hdr<const ALIGN: usize> = transport::SharedMemoryRegionHeader<ALIGN>;
+
+        if !self.send_region.cast::<Hdr<ALIGN>>().is_aligned()
+            || !self.recv_region.cast::<Hdr<ALIGN>>().is_aligned()
+        {
+            return Err(ConfigError::Misaligned);
+        }
+
+        self.check_lengths()?;
+
+        let send_start = self.send_region.as_ptr().addr();
+        let recv_start = self.recv_region.as_ptr().addr();
+        let send_end = send_start
+            .saturating_add(header_size::<ALIGN>())
+            .saturating_add(self.send_buffer_len as usize);
+        let recv_end = recv_start
+            .saturating_add(header_size::<ALIGN>())
+            .saturating_add(self.recv_buffer_len as usize);
+        if send_start < recv_end && recv_start < send_end {
+            return Err(ConfigError::Overlapping);
+        }
+
+        Ok(())
+    }
+
+    fn check_lengths(&self) -> Result<(), ConfigError> {
+        if !self.send_buffer_len.is_multiple_of(4) || !self.recv_buffer_len.is_multiple_of(4) {
+            return Err(ConfigError::InvalidSize);
+        }
+
+        if self.send_buffer_len < 24 || self.recv_buffer_len < 24 {
+            return Err(ConfigError::TooSmall);
+        }
+
+        Ok(())
+    }
+}
+
+/// A shared memory region with `DATA` bytes of data, for declaring the regions as Rust statics
+/// instead of linker symbols.
+///
+/// The regions are usually placed in a dedicated section with `#[link_section]`, so that both
+/// cores agree on their addresses, and passed to [`MemoryConfig::from_buffers`].
+///
+/// `DATA` must be a multiple of 4 and at least 24, which is checked at compile time.
+#[repr(C)]
+pub struct IcMsgBuffer<const DATA: usize, const ALIGN: usize>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    header: core::mem::MaybeUninit<transport::SharedMemoryRegionHeader<ALIGN>>,
+    data: [u8; DATA],
+}
+
+impl<const DATA: usize, const ALIGN: usize> IcMsgBuffer<DATA, ALIGN>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Create a new region. The header is initialized when the channel is created.
+    pub const fn new() -> Self {
+        const {
+            assert!(
+                DATA.is_multiple_of(4) && DATA >= 24 && DATA <= u32::MAX as usize,
+                "the data field must be a multiple of 4 and at least 24 bytes",
+            );
+        }
+        Self {
+            header: core::mem::MaybeUninit::zeroed(),
+            data: [0; DATA],
+        }
+    }
+}
+
+impl<const DATA: usize, const ALIGN: usize> Default for IcMsgBuffer<DATA, ALIGN>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [`MemoryConfig`] for regions that are owned by the channel, created by
+/// [`MemoryConfig::from_slices`] or [`MemoryConfig::from_buffers`].
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CheckedMemoryConfig<const ALIGN: usize> {
+    config: MemoryConfig,
+}
+
+impl<const ALIGN: usize> CheckedMemoryConfig<ALIGN> {
+    /// The underlying config.
+    pub fn config(&self) -> MemoryConfig {
+        self.config
+    }
+}
+
+/// The size of the [`SharedMemoryRegionHeader`][transport::SharedMemoryRegionHeader] at the start
+/// of each shared memory region.
+pub const fn header_size<const ALIGN: usize>() -> usize
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    transport::SharedMemoryRegionHeader::<ALIGN>::SIZE
+}
+
+/// The total size of a shared memory region with a data field of `data_len` bytes, including the
+/// header. Usable in const contexts, e.g. to check region sizes provided by the linker.
+pub const fn required_region_size<const ALIGN: usize>(data_len: usize) -> usize
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    transport::SharedMemoryRegionHeader::<ALIGN>::required_region_size(data_len)
+}
+
+/// The smallest data field length, a multiple of 4, that can hold a message of `max_msg` bytes,
+/// accounting for the packet header, the padding of packets to 4 bytes, and the byte that is
+/// always left free in the ring. It is never less than 24, the smallest length a [`MemoryConfig`]
+/// accepts, so for messages of up to 16 bytes it has room for bigger ones. Usable in const contexts, e.g. to size regions in a linker script
+/// or check them with a `const` assert.
+///
+/// Bonding needs room for the bonding message, which is 13 bytes long with the default
+/// [`BondingConfig`] and up to [`MAX_BONDING_MESSAGE_LEN`][protocol::MAX_BONDING_MESSAGE_LEN]
+/// with all of its options, so a data field should be at least
+/// `min_buffer_len(protocol::MAX_BONDING_MESSAGE_LEN)` long unless the options are known. With
+/// [`set_crc`][transport::Sender::set_crc], add 4 bytes for the trailer.
+///
+/// # Panics
+///
+/// Panics if `max_msg` is bigger than the 16-bit length field of the packet header allows.
+pub const fn min_buffer_len(max_msg: usize) -> u32 {
+    assert!(
+        max_msg <= u16::MAX as usize,
+        "message too large for the packet header"
+    );
+    let len = max_msg.next_multiple_of(4) + 8;
+    if len < 24 { 24 } else { len as u32 }
+}
+
+/// The size of the largest message that can be sent through a data field of `buffer_len` bytes.
+/// This is the inverse of [`min_buffer_len`].
+pub const fn max_message_len(buffer_len: u32) -> usize {
+    // The result doesn't depend on the alignment.
+    transport::SharedMemoryRegionHeader::<4>::max_message_len(buffer_len as usize)
+}
+
+/// Waits for notifications from the other side.
+///
+/// Receiving polls the returned future once before checking the ring, and only awaits it if the
+/// ring was empty. A notification sent after that first poll must therefore complete the future,
+/// even if it arrives before the future is polled again, or the message it announces is not
+/// received until the next notification. Waiters that latch, such as `embassy_sync`'s `Signal`,
+/// do this. With the `embassy-sync` feature, a `&Signal<M, ()>` can be used as a waiter, and as a
+/// [`Notifier`], directly.
+///
+/// No separate notification counter is kept in shared memory: the other side's `wr_idx` already
+/// only moves forward when there is something new to read, and it is checked after the future is
+/// first polled.
+pub trait WaitForNotify {
+    fn wait_for_notify(&mut self) -> impl Future<Output = ()>;
+}
+
+/// A [`WaitForNotify`] that can also be polled directly, needed for [`Receiver::poll_recv`].
+pub trait PollWaitForNotify {
+    /// Return [`Poll::Ready`] if a notification has been received since the last time this
+    /// returned `Ready`. Otherwise, arrange for `cx`'s waker to be woken by the next notification
+    /// and return [`Poll::Pending`].
+    fn poll_wait_for_notify(&mut self, cx: &mut Context<'_>) -> Poll<()>;
+}
+
+/// Closures returning a future can be used as waiters directly, e.g. `|| SIGNAL.wait()` with a
+/// `static SIGNAL: Signal<_, ()>`. The future can't borrow from the closure, so types whose wait
+/// method takes `&mut self` still need a wrapper implementing this trait.
+impl<F, Fut> WaitForNotify for F
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
+        self()
+    }
+}
+
+/// Error returned by the checked [`MemoryConfig`] constructors, and wrapped in
+/// [`InitError::Config`] by [`IcMsg::init`], when the memory config is invalid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// The send or recv regions were too small
+    TooSmall,
+    /// The send or recv buffer lengths were not a multiple of 4.
+    InvalidSize,
+    /// A region passed to [`MemoryConfig::from_regions`] was a null pointer.
+    NullRegion,
+    /// The send or recv region was not aligned to the
+    /// [`SharedMemoryRegionHeader`][transport::SharedMemoryRegionHeader].
+    Misaligned,
+    /// The send and recv regions overlap.
+    Overlapping,
+}
+
+impl core::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ConfigError::TooSmall => write!(f, "shared memory region too small"),
+            ConfigError::InvalidSize => write!(f, "buffer length not a multiple of 4"),
+            ConfigError::NullRegion => write!(f, "shared memory region is null"),
+            ConfigError::Misaligned => write!(f, "shared memory region misaligned"),
+            ConfigError::Overlapping => write!(f, "shared memory regions overlap"),
+        }
+    }
+}
+
+impl core::error::Error for ConfigError {}
+
+impl From<ConfigError> for InitError {
+    fn from(e: ConfigError) -> Self {
+        InitError::Config(e)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InitError {
+    /// The [`MemoryConfig`] is invalid.
+    Config(ConfigError),
+    /// A [`SendError`][`transport::SendError`] occurred during bonding.
+    BondingSendError(transport::SendError),
+    /// A [`RecvError`][`transport::RecvError`] occurred during bonding.
+    BondingRecvError(transport::RecvError),
+    /// The magic sequence was not received during bonding. `data` holds the first `len` bytes of
+    /// the message that was received instead, followed by zeroes.
+    ///
+    /// Up to 32 bytes of the message are kept, longer messages fail with
+    /// [`BondingRecvError`][Self::BondingRecvError] instead.
+    BondingWrongMagic { len: usize, data: [u8; 32] },
+    /// The other side did not respond within [`BondingConfig::timeout_ms`].
+    BondingTimeout,
+    /// The other side uses a different [`BondingConfig::protocol_version`].
+    VersionMismatch { ours: u8, theirs: u8 },
+    /// One of this side's buffer lengths differs from the other side's length of the same buffer,
+    /// see [`BondingConfig::check_buffer_lens`].
+    BufferLenMismatch { ours: u32, theirs: u32 },
+    /// The send region's `wr_idx` or the receive region's `rd_idx` found by [`IcMsg::resume`] is
+    /// out of range or misaligned, so the regions don't hold the state of a channel with this
+    /// config.
+    InvalidIndices,
+}
+
+impl core::fmt::Display for InitError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InitError::Config(_) => write!(f, "invalid memory config"),
+            InitError::BondingSendError(_) => write!(f, "failed to send during bonding"),
+            InitError::BondingRecvError(_) => write!(f, "failed to receive during bonding"),
+            InitError::BondingWrongMagic { .. } => write!(f, "wrong magic received during bonding"),
+            InitError::BondingTimeout => write!(f, "bonding timed out"),
+            InitError::VersionMismatch { ours, theirs } => {
+                write!(f, "protocol version mismatch, ours {ours}, theirs {theirs}")
+            }
+            InitError::BufferLenMismatch { ours, theirs } => {
+                write!(f, "buffer length mismatch, ours {ours}, theirs {theirs}")
+            }
+            InitError::InvalidIndices => write!(f, "invalid indices in shared memory"),
+        }
+    }
+}
+
+impl core::error::Error for InitError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            InitError::Config(e) => Some(e),
+            InitError::BondingSendError(e) => Some(e),
+            InitError::BondingRecvError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned by [`Receiver::recv_timeout`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecvTimeoutError {
+    /// Receiving failed before the timeout expired.
+    Recv(transport::RecvError),
+    /// No message arrived before the timeout expired.
+    TimedOut,
+}
+
+impl From<transport::RecvError> for RecvTimeoutError {
+    fn from(e: transport::RecvError) -> Self {
+        RecvTimeoutError::Recv(e)
+    }
+}
+
+impl core::fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RecvTimeoutError::Recv(_) => write!(f, "failed to receive"),
+            RecvTimeoutError::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+impl core::error::Error for RecvTimeoutError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            RecvTimeoutError::Recv(e) => Some(e),
+            RecvTimeoutError::TimedOut => None,
+        }
+    }
+}
+
+/// Error returned by [`IcMsg::echo_roundtrip`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EchoError {
+    /// Sending the payload failed.
+    Send(transport::SendError),
+    /// Receiving the echo failed.
+    Recv(transport::RecvError),
+    /// The message received back was not the payload that was sent.
+    Mismatch,
+    /// Nothing was received back before the timeout expired.
+    TimedOut,
+}
+
+impl From<transport::SendError> for EchoError {
+    fn from(e: transport::SendError) -> Self {
+        EchoError::Send(e)
+    }
+}
+
+impl From<transport::RecvError> for EchoError {
+    fn from(e: transport::RecvError) -> Self {
+        EchoError::Recv(e)
+    }
+}
+
+impl core::fmt::Display for EchoError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            EchoError::Send(_) => write!(f, "failed to send echo payload"),
+            EchoError::Recv(_) => write!(f, "failed to receive echo"),
+            EchoError::Mismatch => write!(f, "echo did not match payload"),
+            EchoError::TimedOut => write!(f, "echo timed out"),
+        }
+    }
+}
+
+impl core::error::Error for EchoError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            EchoError::Send(e) => Some(e),
+            EchoError::Recv(e) => Some(e),
+            EchoError::Mismatch | EchoError::TimedOut => None,
+        }
+    }
+}
+
+#[cfg(test)]
+// Channels are dropped before their regions are freed, which only matters with the
+// `notify-on-drop` feature.
+#[cfg_attr(
+    not(feature = "notify-on-drop"),
+    allow(clippy::drop_non_drop, clippy::forget_non_drop)
+)]
+mod tests {
+    extern crate std;
+
+    use embedded_hal_async::delay::DelayNs;
+    use tokio::sync::Notify;
 
     use crate::{
         Notifier, WaitForNotify,
-        transport::{SharedMemoryRegionHeader, tests::SyncThing},
-        loom::{alloc, sync::Arc},
+        loom::sync::Arc,
+        transport::{RecvError, SharedMemoryRegionHeader, tests::SyncThing},
+    };
+
+    use super::{
+        BondingConfig, ConfigError, EchoError, IcMsg, InitError, MemoryConfig, RecvTimeoutError,
     };
+    use core::{ptr::NonNull, time::Duration};
+
+    #[test]
+    fn test_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<IcMsg<&Notify, &Notify, 4>>();
+        assert_send::<super::Sender<&Notify, 4>>();
+        assert_send::<super::Receiver<&Notify, 4>>();
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    #[test]
+    async fn test_split_across_threads() {
+        use crate::sync_notify::pair;
+
+        let (icmsg_1, icmsg_2) = pair::<32, 4>().await.unwrap();
+        let (mut sender, _) = icmsg_1.split();
+        let (_, mut receiver) = icmsg_2.split();
+
+        // The halves are moved into tasks that may run on different threads.
+        let send_task = tokio::spawn(async move {
+            for i in 0..100u8 {
+                while sender.send(&[i; 3]).is_err() {
+                    tokio::task::yield_now().await;
+                }
+            }
+            sender
+        });
+        let recv_task = tokio::spawn(async move {
+            let mut buf = [0; 4];
+            for i in 0..100u8 {
+                assert_eq!(receiver.recv(&mut buf).await, Ok(3));
+                assert_eq!(&buf[..3], &[i; 3]);
+            }
+            receiver
+        });
+        send_task.await.unwrap();
+        recv_task.await.unwrap();
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_bonding() {
+        let expected_messages: &[&[u8]] = &[
+            b"",
+            b"0",
+            b"01",
+            b"012",
+            b"0123",
+            b"01234",
+            b"012345",
+            b"0123456",
+            b"01234567",
+        ];
+
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<24, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let notify_1 = Arc::new(Notify::new());
+        let notify_2 = Arc::new(Notify::new());
+
+        let recv_task = tokio::spawn(SyncThing({
+            let notify_1 = Arc::clone(&notify_1);
+            let notify_2 = Arc::clone(&notify_2);
+            async move {
+                let mut icmsg = unsafe {
+                    IcMsg::<_, _, ALIGN>::init(config_2, &*notify_2, &*notify_1, TokioDelay)
+                        .await
+                        .unwrap()
+                };
+
+                // receive messages
+                let mut buf = [0; 8];
+                for &expected_message in expected_messages {
+                    let n = icmsg.recv(&mut buf).await.unwrap();
+                    std::eprintln!("task 2 recv'd {:?}", &buf[..n]);
+                    assert_eq!(&buf[..n], expected_message);
+                }
+
+                // send messages
+                for msg in expected_messages {
+                    loop {
+                        let r = icmsg.send(msg);
+                        if r.is_err() {
+                            tokio::task::yield_now().await;
+                            continue;
+                        }
+                        std::eprintln!("task 2 sent {msg:?}");
+                        break;
+                    }
+                }
+            }
+        }));
+
+        let mut icmsg = unsafe {
+            IcMsg::<_, _, ALIGN>::init(config_1, &*notify_1, &*notify_2, TokioDelay)
+                .await
+                .unwrap()
+        };
+
+        // send messages
+        for msg in expected_messages {
+            loop {
+                let r = icmsg.send(msg);
+                if r.is_err() {
+                    tokio::task::yield_now().await;
+                    continue;
+                }
+                std::eprintln!("task 1 sent {msg:?}");
+                break;
+            }
+        }
+
+        // receive messages
+        let mut buf = [0; 8];
+        for &expected_message in expected_messages {
+            let n = icmsg.recv(&mut buf).await.unwrap();
+            std::eprintln!("task 1 recv'd {:?}", &buf[..n]);
+            assert_eq!(&buf[..n], expected_message);
+        }
+
+        recv_task.await.unwrap();
+        drop(icmsg);
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_bonding_wrong_magic() {
+        use crate::new_transport;
+
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<64, ALIGN>();
+        let (config, peer_config) = loopback.memory_configs();
+        let notify_1 = Notify::new();
+        let notify_2 = Notify::new();
+
+        let mut peer = unsafe { new_transport::<_, ALIGN>(peer_config, &notify_2).unwrap() };
+
+        let bonding_config = BondingConfig {
+            timeout_ms: Some(1000),
+            ..Default::default()
+        };
+        let (r, ()) = tokio::join!(
+            unsafe {
+                IcMsg::<_, _, ALIGN>::init_with_bonding_config(
+                    config,
+                    bonding_config,
+                    &notify_1,
+                    &notify_2,
+                    TokioDelay,
+                )
+            },
+            async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                peer.send(b"not the magic").unwrap();
+            },
+        );
+        let Err(InitError::BondingWrongMagic { len, data }) = r else {
+            panic!("expected BondingWrongMagic");
+        };
+        assert_eq!(len, 13);
+        assert_eq!(&data[..len], b"not the magic");
+        assert!(data[len..].iter().all(|&b| b == 0));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_bonding_timeout() {
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<24, ALIGN>();
+        let (config, _) = loopback.memory_configs();
+        let notify_1 = Notify::new();
+        let notify_2 = Notify::new();
+
+        let bonding_config = BondingConfig {
+            retry_interval_ms: 1,
+            timeout_ms: Some(10),
+            ..Default::default()
+        };
+        let r = unsafe {
+            IcMsg::<_, _, ALIGN>::init_with_bonding_config(
+                config,
+                bonding_config,
+                &notify_1,
+                &notify_2,
+                TokioDelay,
+            )
+            .await
+        };
+        assert!(matches!(r, Err(InitError::BondingTimeout)));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_init_spinning() {
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<24, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let notify_1 = Notify::new();
+        let notify_2 = Notify::new();
+
+        let spins = core::cell::Cell::new(0);
+        let spin = || {
+            spins.set(spins.get() + 1);
+            core::hint::spin_loop();
+        };
+        let notifies = core::cell::Cell::new(0);
+        let notifier_1 = || {
+            notifies.set(notifies.get() + 1);
+            notify_1.notify_one();
+        };
+        // The other side only starts once it has been re-notified twice, after the first
+        // notification that came with the bonding message.
+        let (icmsg_1, icmsg_2) = tokio::join!(
+            unsafe {
+                IcMsg::<_, _, ALIGN>::init_spinning(config_1, notifier_1, &notify_2, spin, 10)
+            },
+            async {
+                while notifies.get() < 3 {
+                    tokio::task::yield_now().await;
+                }
+                unsafe { IcMsg::<_, _, ALIGN>::init(config_2, &notify_2, &notify_1, TokioDelay) }
+                    .await
+            },
+        );
+        let (mut icmsg_1, mut icmsg_2) = (icmsg_1.unwrap(), icmsg_2.unwrap());
+        // Each retry took 10 spins.
+        assert!(spins.get() >= 20);
+
+        let mut buf = [0; 4];
+        icmsg_1.send(b"0123").unwrap();
+        assert_eq!(icmsg_2.try_recv(&mut buf), Ok(4));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_protocol_version() {
+        use crate::{new_transport, send_magic, testing::loopback};
+
+        const ALIGN: usize = 4;
+        let mut loopback = loopback::<28, ALIGN>();
+        let (_, config_2) = loopback.memory_configs();
+
+        let bonding_config = |protocol_version, session_id| BondingConfig {
+            protocol_version,
+            session_id,
+            ..Default::default()
+        };
+
+        let (r_1, r_2) =
+            loopback.connect_with(bonding_config(Some(1), None), bonding_config(Some(2), None));
+        assert!(matches!(
+            r_1,
+            Err(InitError::VersionMismatch { ours: 1, theirs: 2 }),
+        ));
+        assert!(matches!(
+            r_2,
+            Err(InitError::VersionMismatch { ours: 2, theirs: 1 }),
+        ));
+        drop((r_1, r_2));
+
+        // a side without a version is version 0
+        let (r_1, r_2) =
+            loopback.connect_with(bonding_config(Some(1), None), bonding_config(None, Some(5)));
+        assert!(matches!(
+            r_1,
+            Err(InitError::VersionMismatch { ours: 1, theirs: 0 }),
+        ));
+        assert!(matches!(
+            r_2,
+            Err(InitError::VersionMismatch { ours: 0, theirs: 1 }),
+        ));
+        drop((r_1, r_2));
+        let (r_1, r_2) =
+            loopback.connect_with(bonding_config(Some(0), None), bonding_config(None, None));
+        assert!(r_1.is_ok() && r_2.is_ok());
+        drop((r_1, r_2));
+
+        // the version and session ID can be combined
+        let (r_1, r_2) = loopback.connect_with(
+            bonding_config(Some(1), Some(1)),
+            bonding_config(Some(1), Some(2)),
+        );
+        let mut icmsg_1 = r_1.unwrap();
+        r_2.unwrap();
+        let mut buf = [0; 16];
+        let mut transport_2 = unsafe { new_transport::<_, ALIGN>(config_2, || {}).unwrap() };
+        let buffer_lens = (config_2.send_buffer_len, config_2.recv_buffer_len);
+        let bonding_config_2 = bonding_config(Some(1), Some(3));
+        send_magic(transport_2.split_mut().0, &bonding_config_2, buffer_lens).unwrap();
+        assert_eq!(icmsg_1.try_recv(&mut buf), Err(RecvError::SessionLost));
+        assert_eq!(icmsg_1.try_recv(&mut buf), Err(RecvError::Empty));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_version_negotiation() {
+        use crate::{
+            protocol::{FEATURE_CRC, FEATURE_SEQUENCE},
+            testing::loopback,
+        };
+
+        let mut loopback = loopback::<48, 4>();
+
+        let bonding_config = |protocol_version, features| BondingConfig {
+            protocol_version,
+            features,
+            accept_any_version: true,
+            ..Default::default()
+        };
+        let plain_max_message_len = crate::max_message_len(48);
+
+        // a peer that sends the bare magic is version 0
+        let (icmsg_1, icmsg_2) =
+            loopback.connect_with(bonding_config(Some(1), None), bonding_config(None, None));
+        let (icmsg_1, icmsg_2) = (icmsg_1.unwrap(), icmsg_2.unwrap());
+        assert_eq!(icmsg_1.peer_version(), Some(0));
+        assert_eq!(icmsg_2.peer_version(), Some(1));
+        assert_eq!(icmsg_1.peer_features(), None);
+        drop((icmsg_1, icmsg_2));
+
+        let (icmsg_1, icmsg_2) =
+            loopback.connect_with(bonding_config(Some(1), None), bonding_config(Some(1), None));
+        let (icmsg_1, icmsg_2) = (icmsg_1.unwrap(), icmsg_2.unwrap());
+        assert_eq!(icmsg_1.peer_version(), Some(1));
+        assert_eq!(icmsg_2.peer_version(), Some(1));
+        drop((icmsg_1, icmsg_2));
+
+        // a newer peer is reported, for the application to decide
+        let (icmsg_1, icmsg_2) =
+            loopback.connect_with(bonding_config(Some(1), None), bonding_config(Some(2), None));
+        let (icmsg_1, icmsg_2) = (icmsg_1.unwrap(), icmsg_2.unwrap());
+        assert_eq!(icmsg_1.peer_version(), Some(2));
+        assert_eq!(icmsg_2.peer_version(), Some(1));
+        drop((icmsg_1, icmsg_2));
+
+        // only the features both sides advertise are enabled
+        let (icmsg_1, icmsg_2) = loopback.connect_with(
+            bonding_config(None, Some(FEATURE_CRC | FEATURE_SEQUENCE | 1 << 31)),
+            bonding_config(Some(2), Some(FEATURE_CRC)),
+        );
+        let (mut icmsg_1, mut icmsg_2) = (icmsg_1.unwrap(), icmsg_2.unwrap());
+        assert_eq!(icmsg_1.peer_features(), Some(FEATURE_CRC));
+        assert_eq!(
+            icmsg_2.peer_features(),
+            Some(FEATURE_CRC | FEATURE_SEQUENCE | 1 << 31)
+        );
+        assert_eq!(icmsg_1.max_message_len(), plain_max_message_len - 4);
+        assert_eq!(icmsg_2.max_message_len(), plain_max_message_len - 4);
+        icmsg_1.send(b"hello").unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(icmsg_2.try_recv(&mut buf), Ok(5));
+        drop((icmsg_1, icmsg_2));
+
+        let (icmsg_1, icmsg_2) = loopback.connect_with(
+            bonding_config(None, Some(FEATURE_CRC)),
+            bonding_config(None, None),
+        );
+        let (icmsg_1, icmsg_2) = (icmsg_1.unwrap(), icmsg_2.unwrap());
+        assert_eq!(icmsg_1.max_message_len(), plain_max_message_len);
+        assert_eq!(icmsg_2.max_message_len(), plain_max_message_len);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_magic() {
+        use crate::testing::loopback;
+
+        let mut loopback = loopback::<24, 4>();
+
+        let bonding_config = |magic| BondingConfig {
+            magic,
+            ..Default::default()
+        };
+
+        let magic_a = b"channel-a-mag";
+        let magic_b = b"channel-b-mag";
+        let (
+            Err(InitError::BondingWrongMagic {
+                len: len_1,
+                data: data_1,
+            }),
+            Err(InitError::BondingWrongMagic {
+                len: len_2,
+                data: data_2,
+            }),
+        ) = loopback.connect_with(bonding_config(magic_a), bonding_config(magic_b))
+        else {
+            panic!("expected BondingWrongMagic");
+        };
+        assert_eq!(&data_1[..len_1], magic_b);
+        assert_eq!(&data_2[..len_2], magic_a);
+
+        let (r_1, r_2) = loopback.connect_with(bonding_config(magic_b), bonding_config(magic_b));
+        let (mut icmsg_1, mut icmsg_2) = (r_1.unwrap(), r_2.unwrap());
+        icmsg_1.send(b"hello").unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(icmsg_2.try_recv(&mut buf), Ok(5));
+    }
+
+    #[test]
+    fn test_parse_bonding_message() {
+        use crate::{parse_bonding_message, protocol::*};
+
+        let message = |bytes: &[u8]| [&BONDING_MAGIC[..], bytes].concat();
+        let parse = |bytes: &[u8]| {
+            parse_bonding_message(&message(bytes), &BONDING_MAGIC)
+                .map(|m| (m.version, m.features, m.session_id, m.buffer_lens))
+        };
+
+        assert_eq!(parse(&[]), Some((0, None, None, None)));
+        assert_eq!(parse(&[2]), Some((2, None, None, None)));
+        assert_eq!(
+            parse(&[1, BONDING_SESSION_ID, 5, 0]),
+            Some((1, None, Some(5), None))
+        );
+        const FLAGS: u8 = BONDING_FEATURES | BONDING_BUFFER_LENS;
+        assert_eq!(
+            parse(&[0, FLAGS, 3, 0, 0, 0, 32, 0, 0, 0, 64, 0, 0, 0]),
+            Some((0, Some(3), None, Some((32, 64))))
+        );
+        // unknown presence bits and trailing bytes from a later version are ignored
+        assert_eq!(
+            parse(&[1, BONDING_SESSION_ID | 1 << 7, 5, 0, 0xaa, 0xbb, 0xcc, 0xdd]),
+            Some((1, None, Some(5), None))
+        );
+        assert_eq!(
+            parse(&[1, 1 << 7, 0xaa, 0xbb, 0xcc, 0xdd]),
+            Some((1, None, None, None))
+        );
+        // too short for the fields it claims to carry
+        assert_eq!(parse(&[1, BONDING_FEATURES, 3, 0]), None);
+        assert!(parse_bonding_message(&CLOSE_MAGIC, &BONDING_MAGIC).is_none());
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_check_buffer_lens() {
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<48, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let (notify_1, notify_2) = (&Notify::new(), &Notify::new());
+
+        let config_2 = |recv_buffer_len| MemoryConfig {
+            recv_buffer_len,
+            ..config_2
+        };
+        let bonding_config = |check_buffer_lens, session_id| BondingConfig {
+            check_buffer_lens,
+            session_id,
+            ..Default::default()
+        };
+        let bond = |config_2, bonding_config_1, bonding_config_2| async move {
+            tokio::join!(
+                unsafe {
+                    IcMsg::<_, _, ALIGN>::init_with_bonding_config(
+                        config_1,
+                        bonding_config_1,
+                        notify_1,
+                        notify_2,
+                        TokioDelay,
+                    )
+                },
+                unsafe {
+                    IcMsg::<_, _, ALIGN>::init_with_bonding_config(
+                        config_2,
+                        bonding_config_2,
+                        notify_2,
+                        notify_1,
+                        TokioDelay,
+                    )
+                },
+            )
+        };
+
+        // side 2 thinks side 1's send buffer is smaller than it is
+        let (r_1, r_2) = bond(
+            config_2(40),
+            bonding_config(true, None),
+            bonding_config(true, Some(2)),
+        )
+        .await;
+        assert!(matches!(
+            r_1,
+            Err(InitError::BufferLenMismatch {
+                ours: 48,
+                theirs: 40
+            }),
+        ));
+        assert!(matches!(
+            r_2,
+            Err(InitError::BufferLenMismatch {
+                ours: 40,
+                theirs: 48
+            }),
+        ));
+
+        // a side that doesn't send its lengths, like the reference implementation, is not checked
+        let (r_1, r_2) = bond(
+            config_2(48),
+            bonding_config(true, Some(1)),
+            bonding_config(false, None),
+        )
+        .await;
+        assert!(r_1.is_ok() && r_2.is_ok());
+        drop((r_1, r_2));
+
+        let (r_1, r_2) = bond(
+            config_2(48),
+            bonding_config(true, Some(1)),
+            bonding_config(true, Some(2)),
+        )
+        .await;
+        let (mut icmsg_1, mut icmsg_2) = (r_1.unwrap(), r_2.unwrap());
+        icmsg_1.send(b"hello").unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(icmsg_2.try_recv(&mut buf), Ok(5));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_session_lost() {
+        use crate::{new_transport, protocol::FEATURE_CRC, send_magic, testing::loopback};
+
+        const ALIGN: usize = 4;
+        for features in [None, Some(FEATURE_CRC)] {
+            let mut loopback = loopback::<32, ALIGN>();
+            let (_, config_2) = loopback.memory_configs();
+
+            let bonding_config = |id| BondingConfig {
+                session_id: Some(id),
+                features,
+                ..Default::default()
+            };
+            let (icmsg_1, icmsg_2) = loopback.connect_with(bonding_config(1), bonding_config(2));
+            let mut icmsg_1 = icmsg_1.unwrap();
+            let mut icmsg_2 = icmsg_2.unwrap();
+
+            let mut buf = [0; 16];
+            for msg in [&b"0"[..], b"01", b"012"] {
+                icmsg_2.send(msg).unwrap();
+                let n = icmsg_1.try_recv(&mut buf).unwrap();
+                assert_eq!(&buf[..n], msg);
+            }
+
+            // The other side restarts, without running destructors, and bonds again with a new
+            // session ID. Its bonding message has no CRC, even if CRC checking was negotiated.
+            core::mem::forget(icmsg_2);
+            let mut transport_2 = unsafe { new_transport::<_, ALIGN>(config_2, || {}).unwrap() };
+            let buffer_lens = (config_2.send_buffer_len, config_2.recv_buffer_len);
+            send_magic(transport_2.split_mut().0, &bonding_config(3), buffer_lens).unwrap();
+            assert_eq!(icmsg_1.try_recv(&mut buf), Err(RecvError::SessionLost));
+            assert_eq!(icmsg_1.try_recv(&mut buf), Err(RecvError::Empty));
+
+            transport_2.split_mut().0.set_crc(features.is_some());
+            transport_2.send(b"0123").unwrap();
+            let n = icmsg_1.try_recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"0123");
+        }
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_rebond() {
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<24, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let notify_1 = Notify::new();
+        let notify_2 = Notify::new();
+
+        let (icmsg_1, icmsg_2) = tokio::join!(
+            unsafe { IcMsg::<_, _, ALIGN>::init(config_1, &notify_1, &notify_2, TokioDelay) },
+            unsafe { IcMsg::<_, _, ALIGN>::init(config_2, &notify_2, &notify_1, TokioDelay) },
+        );
+        let (sender_1, receiver_1) = icmsg_1.unwrap().split();
+        let mut icmsg_2 = icmsg_2.unwrap();
+
+        let mut buf = [0; 8];
+        icmsg_2.send(b"012").unwrap();
+        icmsg_2.send(b"0123").unwrap();
+
+        // The other side restarts while a message is still in flight.
+        let mut icmsg_1 = IcMsg::from_parts(sender_1, receiver_1);
+        let n = icmsg_1.try_recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"012");
+        icmsg_1.send(b"01").unwrap();
+        // A core that resets does not run destructors.
+        core::mem::forget(icmsg_2);
+        let (r1, icmsg_2) = tokio::join!(icmsg_1.rebond(TokioDelay), unsafe {
+            IcMsg::<_, _, ALIGN>::init(config_2, &notify_2, &notify_1, TokioDelay)
+        });
+        r1.unwrap();
+        let mut icmsg_2 = icmsg_2.unwrap();
+
+        assert_eq!(icmsg_1.try_recv(&mut buf), Err(RecvError::Empty));
+        assert_eq!(icmsg_2.try_recv(&mut buf), Err(RecvError::Empty));
+        icmsg_1.send(b"01234").unwrap();
+        icmsg_2.send(b"012345").unwrap();
+        let n = icmsg_1.try_recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"012345");
+        let n = icmsg_2.try_recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"01234");
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_resume() {
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<32, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let notify_1 = Notify::new();
+        let notify_2 = Notify::new();
+
+        let (icmsg_1, icmsg_2) = tokio::join!(
+            unsafe { IcMsg::<_, _, ALIGN>::init(config_1, &notify_1, &notify_2, TokioDelay) },
+            unsafe { IcMsg::<_, _, ALIGN>::init(config_2, &notify_2, &notify_1, TokioDelay) },
+        );
+        let mut icmsg_1 = icmsg_1.unwrap();
+        let mut icmsg_2 = icmsg_2.unwrap();
+
+        let mut buf = [0; 8];
+        icmsg_1.send(b"01").unwrap();
+        icmsg_2.send(b"012").unwrap();
+        icmsg_2.send(b"0123").unwrap();
+        let n = icmsg_1.try_recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"012");
+
+        // Side 1 restarts with messages in flight in both directions, while side 2 keeps running.
+        // It does not run destructors.
+        core::mem::forget(icmsg_1);
+        let mut icmsg_1 =
+            unsafe { IcMsg::<_, _, ALIGN>::resume(config_1, &notify_1, &notify_2) }.unwrap();
+
+        let n = icmsg_1.try_recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"0123");
+        assert_eq!(icmsg_1.try_recv(&mut buf), Err(RecvError::Empty));
+        let n = icmsg_2.try_recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"01");
+
+        // Both directions keep working, including across the end of the ring.
+        for msg in [&b"01234"[..], b"012345", b"0123456"] {
+            icmsg_1.send(msg).unwrap();
+            let n = icmsg_2.try_recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], msg);
+            icmsg_2.send(msg).unwrap();
+            let n = icmsg_1.try_recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], msg);
+        }
+
+        // Indices that can't be where side 1 left off are rejected: the send region's wr_idx, at
+        // offset ALIGN, past the end, and the receive region's rd_idx misaligned.
+        core::mem::forget(icmsg_1);
+        let send_wr_idx = unsafe { config_1.send_region.byte_add(ALIGN).cast::<u32>() };
+        let recv_rd_idx = config_1.recv_region.cast::<u32>();
+        let (wr_idx, rd_idx) = unsafe { (send_wr_idx.read(), recv_rd_idx.read()) };
+        unsafe { send_wr_idx.write(config_1.send_buffer_len.to_le()) };
+        assert!(matches!(
+            unsafe { IcMsg::<_, _, ALIGN>::resume(config_1, &notify_1, &notify_2) },
+            Err(InitError::InvalidIndices)
+        ));
+        unsafe { send_wr_idx.write(wr_idx) };
+        unsafe { recv_rd_idx.write(2u32.to_le()) };
+        assert!(matches!(
+            unsafe { IcMsg::<_, _, ALIGN>::resume(config_1, &notify_1, &notify_2) },
+            Err(InitError::InvalidIndices)
+        ));
+        unsafe { recv_rd_idx.write(rd_idx) };
+        let icmsg_1 =
+            unsafe { IcMsg::<_, _, ALIGN>::resume(config_1, &notify_1, &notify_2) }.unwrap();
+
+        drop((icmsg_1, icmsg_2));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_deinit() {
+        use core::pin::pin;
+        use embedded_io_async::Read;
+
+        let mut loopback = crate::testing::loopback::<32, 4>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let (mut icmsg_1, mut icmsg_2) = loopback.connect().unwrap();
+
+        // Application data that looks like the teardown message is still delivered as data.
+        let mut buf = [0; 16];
+        icmsg_2.send(&crate::CLOSE_MAGIC).unwrap();
+        let n = icmsg_1.try_recv(&mut buf).unwrap();
+        assert_eq!(buf[..n], crate::CLOSE_MAGIC);
+        icmsg_2
+            .send_typed(&crate::CLOSE_MAGIC, crate::transport::CONTROL)
+            .unwrap();
+        let n = icmsg_1.try_recv(&mut buf).unwrap();
+        assert_eq!(buf[..n], crate::CLOSE_MAGIC);
+
+        // There is no room for the teardown message while the ring is full.
+        icmsg_2.send(b"0123456789").unwrap();
+        icmsg_2.send(b"01").unwrap();
+        let drained = Notify::new();
+        let mut waiter = &drained;
+        let icmsg_2 = icmsg_2.deinit(&mut waiter).await.unwrap_err();
+
+        let n = icmsg_1.try_recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"0123456789");
+
+        // The teardown only completes once the other side has read everything.
+        let mut deinit = pin!(icmsg_2.deinit(&mut waiter));
+        assert!(poll!(deinit.as_mut()).is_pending());
+
+        // Messages sent before the teardown are still received, then every receive fails.
+        let n = icmsg_1.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"01");
+        drained.notify_one();
+        assert!(poll!(deinit.as_mut()).is_pending());
+        assert_eq!(icmsg_1.try_recv(&mut buf), Err(RecvError::PeerClosed));
+        drained.notify_one();
+        let Ok(config) = deinit.await else {
+            panic!("deinit failed");
+        };
+        assert_eq!(config.send_region, config_2.send_region);
+        assert_eq!(config.recv_region, config_2.recv_region);
+        assert_eq!(config.send_buffer_len, config_2.send_buffer_len);
+        assert_eq!(config.recv_buffer_len, config_2.recv_buffer_len);
+        assert_eq!(icmsg_1.recv(&mut buf).await, Err(RecvError::PeerClosed));
+        let (sender_1, mut receiver_1) = icmsg_1.split();
+        assert_eq!(receiver_1.peek_len(), Err(RecvError::PeerClosed));
+        assert_eq!(receiver_1.read(&mut buf).await, Ok(0));
+
+        assert_eq!(sender_1.region(), (config_1.send_region, 32));
+        assert_eq!(receiver_1.region(), (config_1.recv_region, 32));
+        let config = IcMsg::from_parts(sender_1, receiver_1).into_config();
+        assert_eq!(config.send_region, config_1.send_region);
+        assert_eq!(config.recv_region, config_1.recv_region);
+        assert_eq!(config.send_buffer_len, config_1.send_buffer_len);
+        assert_eq!(config.recv_buffer_len, config_1.recv_buffer_len);
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_raw_parts() {
+        let mut loopback = crate::testing::loopback::<32, 4>();
+        let (config_1, _) = loopback.memory_configs();
+        let (icmsg_1, mut icmsg_2) = loopback.connect().unwrap();
+        let mut buf = [0; 8];
+
+        // Taking the channel apart sends nothing, even with `notify-on-drop`.
+        let (mut sender, mut receiver, waiter) = icmsg_1.into_raw_parts();
+        assert_eq!(icmsg_2.try_recv(&mut buf), Err(RecvError::Empty));
+
+        sender.send(b"raw").unwrap();
+        assert_eq!(icmsg_2.recv(&mut buf).await, Ok(3));
+        icmsg_2.send(b"raw").unwrap();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(3));
+
+        let mut icmsg_1 = IcMsg::from_raw_parts(sender, receiver, waiter);
+        icmsg_1.send(b"0123").unwrap();
+        assert_eq!(icmsg_2.recv(&mut buf).await, Ok(4));
+        assert_eq!(&buf[..4], b"0123");
+        icmsg_2.send(b"4567").unwrap();
+        assert_eq!(icmsg_1.recv(&mut buf).await, Ok(4));
+        assert_eq!(&buf[..4], b"4567");
+
+        // The high-level halves lend out their transport halves.
+        let (mut sender_1, mut receiver_1) = icmsg_1.split();
+        sender_1.transport_mut().send(b"89").unwrap();
+        assert_eq!(icmsg_2.recv(&mut buf).await, Ok(2));
+        icmsg_2.send(b"89").unwrap();
+        assert_eq!(receiver_1.transport().region(), (config_1.recv_region, 32));
+        assert_eq!(receiver_1.transport_mut().try_recv(&mut buf), Ok(2));
+        assert_eq!(sender_1.transport().free_space(), 31);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_poll_recv() {
+        use core::{
+            cell::{Cell, RefCell},
+            future::poll_fn,
+            sync::atomic::{AtomicU32, Ordering},
+            task::{Context, Poll, Waker},
+        };
+        use std::{sync::Arc, task::Wake};
+
+        use crate::{
+            IcMsgBuffer, MAGIC, PollWaitForNotify, Receiver, RecvState, transport::IcMsgTransport,
+        };
+
+        struct WakeCounter(AtomicU32);
+
+        impl Wake for WakeCounter {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        /// Receives notifications through a flag, and runs `after_register` once it has decided
+        /// that there is no notification, to simulate the other side racing with the receiver.
+        struct Waiter<'a, F: FnMut()> {
+            notified: &'a Cell<bool>,
+            waker: &'a RefCell<Option<Waker>>,
+            after_register: F,
+        }
+
+        impl<F: FnMut()> PollWaitForNotify for Waiter<'_, F> {
+            fn poll_wait_for_notify(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+                *self.waker.borrow_mut() = Some(cx.waker().clone());
+                if self.notified.replace(false) {
+                    return Poll::Ready(());
+                }
+                (self.after_register)();
+                Poll::Pending
+            }
+        }
+
+        impl<F: FnMut()> WaitForNotify for Waiter<'_, F> {
+            fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
+                poll_fn(|cx| self.poll_wait_for_notify(cx))
+            }
+        }
+
+        const ALIGN: usize = 4;
+        // The channel sends to itself.
+        let mut region = IcMsgBuffer::<32, ALIGN>::new();
+        let shared_region = NonNull::from(&mut region).cast();
+        let notified = &Cell::new(false);
+        let waker = &RefCell::new(None::<Waker>);
+        let notify = || {
+            notified.set(true);
+            if let Some(waker) = &*waker.borrow() {
+                waker.wake_by_ref();
+            }
+        };
+        let transport = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(shared_region, shared_region, 32, 32, notify)
+        };
+        let (sender, receiver) = transport.split();
+        let sender = &RefCell::new(sender);
+        let race = &Cell::new(None::<&[u8]>);
+        let mut receiver = Receiver {
+            state: RecvState::new(receiver, None, &MAGIC),
+            waiter: Waiter {
+                notified,
+                waker,
+                after_register: || {
+                    if let Some(msg) = race.take() {
+                        sender.borrow_mut().send(msg).unwrap();
+                    }
+                },
+            },
+        };
+
+        let wakes = Arc::new(WakeCounter(AtomicU32::new(0)));
+        let cx_waker = Waker::from(Arc::clone(&wakes));
+        let mut cx = Context::from_waker(&cx_waker);
+        let mut buf = [0; 8];
+
+        // A message that arrives between registering the waker and checking the ring is received
+        // right away.
+        race.set(Some(b"012"));
+        assert_eq!(receiver.poll_recv(&mut cx, &mut buf), Poll::Ready(Ok(3)));
+        assert_eq!(&buf[..3], b"012");
+
+        // A message that arrives after the ring was found empty wakes the waker.
+        notified.set(false);
+        let wakes_before = wakes.0.load(Ordering::Relaxed);
+        assert_eq!(receiver.poll_recv(&mut cx, &mut buf), Poll::Pending);
+        sender.borrow_mut().send(b"0123").unwrap();
+        assert_eq!(wakes.0.load(Ordering::Relaxed), wakes_before + 1);
+        assert_eq!(receiver.poll_recv(&mut cx, &mut buf), Poll::Ready(Ok(4)));
+        assert_eq!(&buf[..4], b"0123");
+        assert_eq!(receiver.poll_recv(&mut cx, &mut buf), Poll::Pending);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_recv_cancel_safety() {
+        use core::{
+            cell::Cell,
+            future::poll_fn,
+            pin::pin,
+            task::{Context, Poll, Waker},
+        };
+
+        use crate::{IcMsgBuffer, MAGIC, Receiver, RecvState, transport::IcMsgTransport};
+
+        /// Only completes for notifications that happen after it is first polled, and forgets
+        /// them when dropped.
+        struct EdgeWaiter<'a>(&'a Cell<u32>);
+
+        impl WaitForNotify for EdgeWaiter<'_> {
+            fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
+                let notifications = self.0;
+                let mut registered = None;
+                poll_fn(move |_| match registered {
+                    Some(n) if n != notifications.get() => Poll::Ready(()),
+                    Some(_) => Poll::Pending,
+                    None => {
+                        registered = Some(notifications.get());
+                        Poll::Pending
+                    }
+                })
+            }
+        }
+
+        const ALIGN: usize = 4;
+        // The channel sends to itself.
+        let mut region = IcMsgBuffer::<32, ALIGN>::new();
+        let shared_region = NonNull::from(&mut region).cast();
+        let notifications = &Cell::new(0);
+        let notify = || notifications.set(notifications.get() + 1);
+        let transport = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(shared_region, shared_region, 32, 32, notify)
+        };
+        let (mut sender, receiver) = transport.split();
+        let mut receiver = Receiver {
+            state: RecvState::new(receiver, None, &MAGIC),
+            waiter: EdgeWaiter(notifications),
+        };
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut buf = [0; 4];
+
+        // Drop the future before it is polled, while it waits, and while it waits again after a
+        // spurious notification. In each case the message sent meanwhile must be returned by the
+        // first poll of the next call.
+        for i in 0..3 {
+            {
+                let mut fut = pin!(receiver.recv(&mut buf));
+                if i > 0 {
+                    assert!(fut.as_mut().poll(&mut cx).is_pending());
+                }
+                if i > 1 {
+                    notify();
+                    assert!(fut.as_mut().poll(&mut cx).is_pending());
+                }
+                sender.send(&[i]).unwrap();
+            }
+            let r = pin!(receiver.recv(&mut buf)).poll(&mut cx);
+            assert_eq!(r, Poll::Ready(Ok(1)));
+            assert_eq!(buf[0], i);
+        }
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_recv_timeout() {
+        let mut loopback = crate::testing::loopback::<32, 4>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let (mut sender, _) = icmsg_1.split();
+        let (_, mut receiver) = icmsg_2.split();
+        let mut buf = [0; 8];
+
+        // the other side is silent
+        assert_eq!(
+            receiver.recv_timeout(&mut buf, 10_000, TokioDelay).await,
+            Err(RecvTimeoutError::TimedOut),
+        );
+
+        // a message that is already there wins, even with no time to wait
+        sender.send(b"0123").unwrap();
+        assert_eq!(receiver.recv_timeout(&mut buf, 0, TokioDelay).await, Ok(4));
+        assert_eq!(&buf[..4], b"0123");
+
+        let (r, ()) = tokio::join!(
+            receiver.recv_timeout(&mut buf, 1_000_000, TokioDelay),
+            async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                sender.send(b"4567").unwrap();
+            }
+        );
+        assert_eq!(r, Ok(4));
+        assert_eq!(&buf[..4], b"4567");
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_echo_roundtrip() {
+        let mut loopback = crate::testing::loopback::<64, 4>();
+        let (mut icmsg_1, mut icmsg_2) = loopback.connect().unwrap();
+        let payload = b"longer than one comparison chunk";
+
+        let (r, ()) = tokio::join!(icmsg_1.echo_roundtrip(payload, TokioDelay, 1000), async {
+            let mut buf = [0; 64];
+            let n = icmsg_2.recv(&mut buf).await.unwrap();
+            icmsg_2.send(&buf[..n]).unwrap();
+        });
+        assert_eq!(r, Ok(()));
+
+        // a reply that differs only in its last byte
+        let (r, ()) = tokio::join!(icmsg_1.echo_roundtrip(payload, TokioDelay, 1000), async {
+            let mut buf = [0; 64];
+            let n = icmsg_2.recv(&mut buf).await.unwrap();
+            buf[n - 1] ^= 1;
+            icmsg_2.send(&buf[..n]).unwrap();
+        });
+        assert_eq!(r, Err(EchoError::Mismatch));
+
+        // nobody echoes
+        assert_eq!(
+            icmsg_1.echo_roundtrip(b"ping", TokioDelay, 10).await,
+            Err(EchoError::TimedOut),
+        );
+        let mut buf = [0; 8];
+        assert_eq!(icmsg_2.try_recv(&mut buf), Ok(4));
+        assert_eq!(icmsg_1.try_recv(&mut buf), Err(RecvError::Empty));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_sender_writer() {
+        use embedded_io_async::Write;
+
+        use crate::writer::SenderWriter;
+
+        const ALIGN: usize = 4;
+        let mut loopback = crate::testing::loopback::<32, ALIGN>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let (sender, _) = icmsg_1.split();
+        let (_, mut receiver) = icmsg_2.split();
+        let mut writer = SenderWriter::<_, _, ALIGN, 8>::new(sender, TokioDelay, 100);
+
+        // nothing is sent until the frame is flushed
+        writer.write_all(b"012").await.unwrap();
+        writer.write_all(b"345").await.unwrap();
+        assert!(receiver.is_empty());
+        writer.flush().await.unwrap();
+        writer.flush().await.unwrap();
+        let mut buf = [0; 64];
+        assert_eq!(receiver.try_recv(&mut buf), Ok(6));
+        assert_eq!(&buf[..6], b"012345");
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+
+        // frames bigger than the writer's buffer and the ring are sent in fragments as the
+        // receiver makes room
+        let frames: [&[u8]; 3] = [&[0xaa; 40], b"01234567", &[0x55; 17]];
+        let ((), ()) = tokio::join!(
+            async {
+                for frame in frames {
+                    writer.write_all(frame).await.unwrap();
+                    writer.flush().await.unwrap();
+                }
+            },
+            async {
+                for frame in frames {
+                    let n = receiver.recv_reassembled(&mut buf).await.unwrap();
+                    assert_eq!(&buf[..n], frame);
+                }
+            },
+        );
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_read_write() {
+        use embedded_io_async::{Read, Write};
+
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<32, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let notify_1 = Notify::new();
+        let notify_2 = Notify::new();
+
+        let (icmsg_1, icmsg_2) = tokio::join!(
+            unsafe { IcMsg::<_, _, ALIGN>::init(config_1, &notify_1, &notify_2, TokioDelay) },
+            // closures work as the notifier and the waiter too
+            unsafe {
+                IcMsg::<_, _, ALIGN>::init(
+                    config_2,
+                    || notify_2.notify_waiters(),
+                    || notify_1.notified(),
+                    TokioDelay,
+                )
+            },
+        );
+        let (mut sender, _) = icmsg_1.unwrap().split();
+        let (_, mut receiver) = icmsg_2.unwrap().split();
+
+        assert_eq!(sender.write(b"0123456").await, Ok(7));
+        sender.send(b"").unwrap();
+        assert_eq!(sender.write(b"01").await, Ok(2));
+
+        // message boundaries are preserved and the remainder of a message is kept for later
+        let mut buf = [0; 3];
+        assert_eq!(receiver.read(&mut buf).await, Ok(3));
+        assert_eq!(&buf, b"012");
+        assert_eq!(receiver.read(&mut buf).await, Ok(3));
+        assert_eq!(&buf, b"345");
+        assert_eq!(receiver.read(&mut buf).await, Ok(1));
+        assert_eq!(&buf[..1], b"6");
+        // the empty message is skipped
+        assert_eq!(receiver.read(&mut buf).await, Ok(2));
+        assert_eq!(&buf[..2], b"01");
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+    }
+
+    #[cfg(all(not(loom), feature = "embassy-sync"))]
+    #[tokio::main]
+    #[test]
+    async fn test_embassy_signal() {
+        use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<32, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let notify_1 = Signal::<NoopRawMutex, ()>::new();
+        let notify_2 = Signal::<NoopRawMutex, ()>::new();
+
+        // A notification before the wait must not be lost.
+        Notifier::notify(&mut &notify_1);
+        (&notify_1).wait_for_notify().await;
+
+        let (icmsg_1, icmsg_2) = tokio::join!(
+            unsafe { IcMsg::<_, _, ALIGN>::init(config_1, &notify_1, &notify_2, TokioDelay) },
+            unsafe { IcMsg::<_, _, ALIGN>::init(config_2, &notify_2, &notify_1, TokioDelay) },
+        );
+        let mut icmsg_1 = icmsg_1.unwrap();
+        let mut icmsg_2 = icmsg_2.unwrap();
+
+        // One task sends requests and waits for each reply, the other echoes them.
+        tokio::join!(
+            async {
+                let mut buf = [0; 4];
+                for i in 0..20u8 {
+                    icmsg_1.send(&[i; 3]).unwrap();
+                    assert_eq!(icmsg_1.recv(&mut buf).await, Ok(3));
+                    assert_eq!(&buf[..3], &[i; 3]);
+                }
+            },
+            async {
+                let mut buf = [0; 4];
+                for _ in 0..20 {
+                    let n = icmsg_2.recv(&mut buf).await.unwrap();
+                    icmsg_2.send(&buf[..n]).unwrap();
+                }
+            },
+        );
+
+        let mut buf = [0; 4];
+        icmsg_2.send(b"poll").unwrap();
+        let r = core::future::poll_fn(|cx| icmsg_1.poll_recv(cx, &mut buf)).await;
+        assert_eq!(r, Ok(4));
+    }
+
+    #[cfg(all(not(loom), feature = "bt-hci"))]
+    #[tokio::main]
+    #[test]
+    async fn test_hci_transport() {
+        use bt_hci_transport::{
+            PacketKind, PacketToController, PacketToHost, ReadHciError, Transport, WithIndicator,
+        };
+        use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+        use embedded_io::ReadExactError;
+
+        use crate::hci::{Error, HciTransport};
+
+        struct Cmd<'a>(&'a [u8]);
+        impl PacketToController for Cmd<'_> {
+            const KIND: PacketKind = PacketKind::Cmd;
+            fn size(&self) -> usize {
+                self.0.len()
+            }
+            fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+                writer.write_all(self.0)
+            }
+            async fn write_hci_async<W: embedded_io_async::Write>(
+                &self,
+                mut writer: W,
+            ) -> Result<(), W::Error> {
+                writer.write_all(self.0).await
+            }
+        }
+
+        // everything after the indicator byte
+        #[derive(Debug, PartialEq)]
+        struct Packet<'a>(PacketKind, &'a [u8]);
+        impl<'d> PacketToHost<'d> for Packet<'d> {
+            fn read_hci<R: embedded_io::Read>(
+                kind: PacketKind,
+                data: &mut R,
+                buf: &'d mut [u8],
+            ) -> Result<Self, ReadHciError<R::Error>> {
+                let mut n = 0;
+                loop {
+                    let r = data.read(&mut buf[n..]);
+                    match r.map_err(|e| ReadHciError::Read(ReadExactError::Other(e)))? {
+                        0 => return Ok(Packet(kind, &buf[..n])),
+                        m => n += m,
+                    }
+                    if n == buf.len() {
+                        return Err(ReadHciError::BufferTooSmall);
+                    }
+                }
+            }
+            async fn read_hci_async<R: embedded_io_async::Read>(
+                kind: PacketKind,
+                data: &mut R,
+                buf: &'d mut [u8],
+            ) -> Result<Self, ReadHciError<R::Error>> {
+                let mut n = 0;
+                loop {
+                    let r = data.read(&mut buf[n..]).await;
+                    match r.map_err(|e| ReadHciError::Read(ReadExactError::Other(e)))? {
+                        0 => return Ok(Packet(kind, &buf[..n])),
+                        m => n += m,
+                    }
+                    if n == buf.len() {
+                        return Err(ReadHciError::BufferTooSmall);
+                    }
+                }
+            }
+        }
+
+        const ALIGN: usize = 4;
+        let mut loopback = crate::testing::loopback::<48, ALIGN>();
+        let (icmsg_1, mut controller) = loopback.connect().unwrap();
+        let (sender, receiver) = icmsg_1.split();
+        let host = HciTransport::<NoopRawMutex, _, _, ALIGN, 8>::new(receiver, sender);
+        let mut buf = [0; 16];
+        let mut rx = [0; 16];
+
+        // packets are sent with their indicator
+        host.write(&Cmd(b"0123")).await.unwrap();
+        assert_eq!(controller.try_recv(&mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"\x010123");
+        assert_eq!(
+            host.write(&Cmd(b"01234567")).await,
+            Err(Error::PacketTooBig)
+        );
+        assert_eq!(WithIndicator::new(&Cmd(b"0123456")).size(), 8);
+        host.write(&Cmd(b"0123456")).await.unwrap();
+        assert_eq!(controller.try_recv(&mut buf), Ok(8));
+
+        controller.send(b"\x04012").unwrap();
+        assert_eq!(
+            host.read(&mut rx).await,
+            Ok(Packet(PacketKind::Event, b"012"))
+        );
+
+        // a packet that doesn't fit is dropped
+        controller.send(b"\x04012345678").unwrap();
+        controller.send(b"\x09").unwrap();
+        controller.send(b"\x0201").unwrap();
+        assert_eq!(
+            host.read::<Packet>(&mut rx).await,
+            Err(Error::Recv(RecvError::MessageTooBig { required: 10 }))
+        );
+        assert_eq!(
+            host.read::<Packet>(&mut rx).await,
+            Err(Error::InvalidPacket(ReadHciError::InvalidValue))
+        );
+        assert_eq!(
+            host.read(&mut rx).await,
+            Ok(Packet(PacketKind::AclData, b"01"))
+        );
+
+        let (receiver, sender) = host.into_inner();
+        assert!(receiver.is_empty());
+        assert_eq!(sender.free_space(), sender.capacity());
+    }
+
+    #[cfg(all(not(loom), feature = "postcard"))]
+    #[tokio::main]
+    #[test]
+    async fn test_typed() {
+        use serde::{Deserialize, Serialize};
+
+        use crate::typed::{TypedRecvError, TypedSendError};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Command {
+            Reset,
+            SetLed { index: u8, on: bool },
+            Write(u32, [u8; 16]),
+        }
+
+        let mut loopback = crate::testing::loopback::<64, 4>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let (mut sender, _) = icmsg_1.split_typed::<Command, 32>();
+        let (_, mut receiver) = icmsg_2.split_typed::<Command, 8>();
+
+        let commands = [
+            Command::SetLed { index: 3, on: true },
+            Command::Write(0x1234, [0xaa; 16]),
+            Command::Reset,
+        ];
+        for command in &commands {
+            sender.send(command).unwrap();
+        }
+        assert_eq!(
+            receiver.recv().await,
+            Ok(Command::SetLed { index: 3, on: true })
+        );
+        // too big for the receiver's buffer, the next value is still received
+        assert_eq!(
+            receiver.recv().await,
+            Err(TypedRecvError::TooLarge { required: 19 })
+        );
+        assert_eq!(receiver.recv().await, Ok(Command::Reset));
+        assert_eq!(
+            receiver.try_recv(),
+            Err(TypedRecvError::Recv(RecvError::Empty))
+        );
+
+        let mut sender = sender.into_inner();
+        sender.send(&[0xff]).unwrap();
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(TypedRecvError::Deserialize(_))
+        ));
+
+        let (mut small_sender, _) =
+            IcMsg::from_parts(sender, receiver.into_inner()).split_typed::<Command, 8>();
+        assert_eq!(
+            small_sender.send(&commands[1]),
+            Err(TypedSendError::TooLarge)
+        );
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_send_and_wait_drained() {
+        use core::pin::pin;
+
+        let drained = Notify::new();
+        let mut loopback = crate::testing::loopback::<32, 4>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let (mut sender, _) = icmsg_1.split();
+        let (_, mut receiver) = icmsg_2.split();
+        let mut buf = [0; 16];
+
+        // The bonding message took up 20 bytes, so this one wraps around and ends at index 4,
+        // behind the other side's rd_idx until it reads it.
+        {
+            let mut waiter = &drained;
+            let mut wait_fut = pin!(sender.send_and_wait_drained(b"0123456789ab", &mut waiter));
+            assert!(poll!(wait_fut.as_mut()).is_pending());
+            // notified while still unread
+            drained.notify_one();
+            assert!(poll!(wait_fut.as_mut()).is_pending());
+
+            assert_eq!(receiver.try_recv(&mut buf), Ok(12));
+            drained.notify_one();
+            assert_eq!(wait_fut.await, Ok(()));
+        }
+        assert_eq!(sender.transport().position(), 4);
+
+        // read before the first check
+        let (r, ()) = tokio::join!(
+            async {
+                let mut waiter = &drained;
+                sender.send_and_wait_drained(b"hello", &mut waiter).await
+            },
+            async {
+                assert_eq!(receiver.recv(&mut buf).await, Ok(5));
+                drained.notify_one();
+            },
+        );
+        assert_eq!(r, Ok(()));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_wait_for_space() {
+        use core::pin::pin;
+
+        let space = Notify::new();
+        let mut loopback = crate::testing::loopback::<64, 4>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let (mut sender, _) = icmsg_1.split();
+        let (_, mut receiver) = icmsg_2.split();
+
+        sender.wait_for_space(16, &space).await;
+        while sender.send(&[0; 16]).is_ok() {}
+
+        {
+            let mut wait_fut = pin!(sender.wait_for_space(16, &space));
+            assert!(poll!(wait_fut.as_mut()).is_pending());
+            // notified while still full
+            space.notify_one();
+            assert!(poll!(wait_fut.as_mut()).is_pending());
+
+            let mut buf = [0; 16];
+            assert_eq!(receiver.try_recv(&mut buf), Ok(16));
+            space.notify_one();
+            wait_fut.await;
+        }
+        sender.send(&[0; 16]).unwrap();
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_duplex() {
+        use core::pin::pin;
+
+        let space = Notify::new();
+        let mut loopback = crate::testing::loopback::<64, 4>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let (mut sender, _) = icmsg_1.split_duplex(&space, &space);
+        let (_, mut receiver) = icmsg_2.split_duplex(&space, &space);
+
+        while sender.try_send(&[0; 16]).is_ok() {}
+
+        {
+            let mut send_fut = pin!(sender.send(&[1; 16]));
+            assert!(poll!(send_fut.as_mut()).is_pending());
+
+            let mut buf = [0; 16];
+            assert_eq!(receiver.try_recv(&mut buf), Ok(16));
+            send_fut.await.unwrap();
+        }
+        assert_eq!(
+            sender.try_send(&[0; 16]),
+            Err(crate::transport::SendError::InsufficientCapacity)
+        );
+    }
+
+    #[cfg(all(not(loom), feature = "dispatch"))]
+    #[tokio::main]
+    #[test]
+    async fn test_notify_dispatcher() {
+        use crate::dispatch::NotifyDispatcher;
+        use embassy_futures::select::{Either, select};
+
+        const ALIGN: usize = 4;
+        let loopback_a = crate::testing::loopback::<32, ALIGN>();
+        let loopback_b = crate::testing::loopback::<32, ALIGN>();
+        let (config_a_1, config_a_2) = loopback_a.memory_configs();
+        let (config_b_1, config_b_2) = loopback_b.memory_configs();
+        // Both channels notify the second side through `shared`.
+        let shared = Notify::new();
+        let notify_a = Notify::new();
+        let notify_b = Notify::new();
+
+        let mut dispatcher = NotifyDispatcher::<_, 2>::new(&shared);
+        let (mut runner, [wait_a, wait_b]) = dispatcher.split();
+        let test = async {
+            let (a_1, a_2, b_1, b_2) = tokio::join!(
+                unsafe { IcMsg::<_, _, ALIGN>::init(config_a_1, &shared, &notify_a, TokioDelay) },
+                unsafe { IcMsg::<_, _, ALIGN>::init(config_a_2, &notify_a, wait_a, TokioDelay) },
+                unsafe { IcMsg::<_, _, ALIGN>::init(config_b_1, &shared, &notify_b, TokioDelay) },
+                unsafe { IcMsg::<_, _, ALIGN>::init(config_b_2, &notify_b, wait_b, TokioDelay) },
+            );
+            let (mut a_1, mut a_2) = (a_1.unwrap(), a_2.unwrap());
+            let (mut b_1, mut b_2) = (b_1.unwrap(), b_2.unwrap());
+
+            // Each second side echoes, so every send on one channel also wakes the other.
+            let echo = async |side_1: &mut IcMsg<_, _, ALIGN>, side_2: &mut IcMsg<_, _, ALIGN>| {
+                let mut buf = [0; 4];
+                for i in 0..20u8 {
+                    side_1.send(&[i; 3]).unwrap();
+                    assert_eq!(side_2.recv(&mut buf).await, Ok(3));
+                    side_2.send(&buf[..3]).unwrap();
+                    assert_eq!(side_1.recv(&mut buf).await, Ok(3));
+                    assert_eq!(&buf[..3], &[i; 3]);
+                }
+            };
+            tokio::join!(echo(&mut a_1, &mut a_2), echo(&mut b_1, &mut b_2));
+        };
+        let r = select(test, runner.run()).await;
+        assert!(matches!(r, Either::First(())));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_endpoints() {
+        use crate::endpoints::Endpoints;
+
+        const ALIGN: usize = 4;
+        let mut loopback = crate::testing::loopback::<64, ALIGN>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let mut side_1 = Endpoints::<_, _, ALIGN, 3, 8, 2>::new(icmsg_1);
+        let mut side_2 = Endpoints::<_, _, ALIGN, 3, 8, 2>::new(icmsg_2);
+        let mut buf = [0; 16];
+
+        side_1.send(0, b"a0").unwrap();
+        side_1.send(1, b"b0").unwrap();
+        side_1.send(2, b"c0").unwrap();
+        side_1.send(1, b"b1").unwrap();
+        side_1.send(0, b"a1").unwrap();
+
+        // endpoints 0 and 1 are not read yet, but don't hold up endpoint 2
+        assert_eq!(side_2.endpoint(2).recv(&mut buf).await, Ok(2));
+        assert_eq!(&buf[..2], b"c0");
+        assert_eq!(side_2.try_recv(2, &mut buf), Err(RecvError::Empty));
+        let mut endpoint = side_2.endpoint(1);
+        assert_eq!(endpoint.try_recv(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"b0");
+        assert_eq!(endpoint.try_recv(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"b1");
+        assert_eq!(endpoint.try_recv(&mut buf), Err(RecvError::Empty));
+        assert_eq!(
+            side_2.try_recv(0, &mut buf[..1]),
+            Err(RecvError::MessageTooBig { required: 2 })
+        );
+        assert_eq!(side_2.try_recv(0, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"a0");
+        assert_eq!(side_2.try_recv(0, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"a1");
+
+        // the queue for endpoint 0 holds two messages, and endpoint 1 messages of up to 8 bytes
+        for msg in [b"a2", b"a3", b"a4"] {
+            side_1.send(0, msg).unwrap();
+        }
+        side_1.send(1, b"too long!").unwrap();
+        side_1.send(2, b"c1").unwrap();
+        assert_eq!(side_2.try_recv(2, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"c1");
+        assert_eq!(side_2.dropped(0), 1);
+        assert_eq!(side_2.dropped(1), 1);
+        assert_eq!(side_2.try_recv(1, &mut buf), Err(RecvError::Empty));
+        assert_eq!(side_2.try_recv(0, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"a2");
+        assert_eq!(side_2.try_recv(0, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"a3");
+        assert_eq!(side_2.try_recv(0, &mut buf), Err(RecvError::Empty));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_icbmsg() {
+        use crate::icbmsg::{Error, IcbMsg};
+
+        const ALIGN: usize = 4;
+        let mut blocks = [[0u32; 32]; 2];
+        let [blocks_1, blocks_2] = blocks.each_mut().map(|b| b.as_mut_ptr().cast());
+        let mut loopback = crate::testing::loopback::<64, ALIGN>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let mut side_1 = unsafe { IcbMsg::<_, _, ALIGN, 32, 4>::new(icmsg_1, blocks_1, blocks_2) };
+        let mut side_2 = unsafe { IcbMsg::<_, _, ALIGN, 32, 4>::new(icmsg_2, blocks_2, blocks_1) };
+
+        // bigger than the ring
+        let msg: [u8; 100] = core::array::from_fn(|i| i as u8);
+        side_1.send(&msg).unwrap();
+        assert_eq!(side_1.send(b"x"), Err(Error::OutOfBlocks));
+        assert_eq!(side_1.send(&[0; 129]), Err(Error::TooLarge));
+
+        let block = side_2.recv_block().await.unwrap();
+        assert_eq!(&*block, &msg);
+        // the blocks are released when the message is dropped
+        drop(block);
+        side_1.send(&msg[..40]).unwrap();
+        side_1.send(&msg[40..80]).unwrap();
+        side_1.send(b"").unwrap();
+        assert_eq!(side_1.send(b"y"), Err(Error::OutOfBlocks));
+
+        let mut buf = [0; 64];
+        assert_eq!(
+            side_2.try_recv(&mut buf[..32]),
+            Err(Error::Recv(RecvError::MessageTooBig { required: 40 })),
+        );
+        assert_eq!(side_2.try_recv(&mut buf), Ok(40));
+        assert_eq!(&buf[..40], &msg[..40]);
+        assert_eq!(side_2.try_recv(&mut buf), Ok(40));
+        assert_eq!(&buf[..40], &msg[40..80]);
+        assert_eq!(side_2.try_recv(&mut buf), Ok(0));
+        assert_eq!(
+            side_2.try_recv(&mut buf),
+            Err(Error::Recv(RecvError::Empty))
+        );
+        side_1.send(&msg).unwrap();
+
+        // a release behind a descriptor that has not been received yet still frees the blocks
+        side_2.send(&msg[..40]).unwrap();
+        drop(side_2.try_recv_block().unwrap());
+        side_1.send(b"z").unwrap();
+        // and so does one behind a descriptor that was too big to receive
+        assert_eq!(
+            side_1.try_recv(&mut buf[..8]),
+            Err(Error::Recv(RecvError::MessageTooBig { required: 40 })),
+        );
+        assert_eq!(side_2.try_recv(&mut buf), Ok(1));
+        side_1.send(&msg).unwrap();
+        assert_eq!(side_1.try_recv(&mut buf), Ok(40));
+        assert_eq!(&buf[..40], &msg[..40]);
+    }
 
-    use super::{IcMsg, MemoryConfig};
-    use core::{alloc::Layout, time::Duration};
+    #[cfg(all(not(loom), feature = "stream"))]
+    #[tokio::main]
+    #[test]
+    async fn test_stream() {
+        use core::{future::poll_fn, pin::pin};
+        use futures_core::Stream;
+
+        let expected_messages: &[&[u8]] = &[b"", b"0", b"0123", b"012345678", b"01234567"];
+
+        let (icmsg_1, icmsg_2) = crate::sync_notify::pair::<24, 4>().await.unwrap();
+        let (mut sender, _) = icmsg_1.split();
+        let (_, receiver) = icmsg_2.split();
+        let mut stream = pin!(receiver.into_stream::<8>());
+
+        let send = async {
+            for msg in expected_messages {
+                while sender.send(msg).is_err() {
+                    tokio::task::yield_now().await;
+                }
+            }
+        };
+        let recv = async {
+            for &expected_message in expected_messages {
+                let msg = poll_fn(|cx| stream.as_mut().poll_next(cx)).await.unwrap();
+                if expected_message.len() > 8 {
+                    // too big, and discarded so the stream carries on
+                    assert_eq!(msg, Err(RecvError::MessageTooBig { required: 9 }));
+                } else {
+                    assert_eq!(msg.unwrap(), expected_message);
+                }
+            }
+        };
+        tokio::join!(send, recv);
+    }
+
+    #[cfg(all(not(loom), feature = "sink"))]
+    #[tokio::main]
+    #[test]
+    async fn test_sink() {
+        use core::{
+            pin::Pin,
+            task::{Context, Poll},
+        };
+        use futures_util::SinkExt;
+        use std::{boxed::Box, vec::Vec};
+
+        use crate::{PollWaitForNotify, transport::SendError};
+
+        /// Fires every millisecond, as the other side doesn't notify when it reads.
+        struct RetryTimer(Pin<Box<tokio::time::Sleep>>);
+
+        impl PollWaitForNotify for RetryTimer {
+            fn poll_wait_for_notify(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+                let r = self.0.as_mut().poll(cx);
+                if r.is_ready() {
+                    let deadline = tokio::time::Instant::now() + Duration::from_millis(1);
+                    self.0.as_mut().reset(deadline);
+                }
+                r
+            }
+        }
+
+        // Many more messages than fit in the ring at once.
+        let expected_messages: Vec<Vec<u8>> = (0..64u8).map(|i| (0..i % 9).collect()).collect();
+
+        let mut loopback = crate::testing::loopback::<32, 4>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let (sender, _) = icmsg_1.split();
+        let (_, mut receiver) = icmsg_2.split();
+        let timer = RetryTimer(Box::pin(tokio::time::sleep(Duration::ZERO)));
+        let mut sink = sender.into_sink(timer, 8);
+
+        assert_eq!(
+            sink.send([0; 9].as_slice()).await,
+            Err(SendError::MessageTooLarge)
+        );
+
+        let send = async {
+            for msg in &expected_messages {
+                // Only flushed when the ring fills up.
+                sink.feed(msg.as_slice()).await.unwrap();
+            }
+            sink.flush().await.unwrap();
+        };
+        let recv = async {
+            for expected_message in &expected_messages {
+                let mut buf = [0; 8];
+                let n = receiver.recv(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], expected_message.as_slice());
+            }
+        };
+        tokio::join!(send, recv);
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn test_error_display() {
+        use crate::transport::SendError;
+        use core::error::Error;
+        use std::string::ToString;
+
+        assert_eq!(InitError::BondingTimeout.to_string(), "bonding timed out");
+        assert!(InitError::BondingTimeout.source().is_none());
+
+        let e = InitError::BondingSendError(SendError::InsufficientCapacity);
+        assert_eq!(e.to_string(), "failed to send during bonding");
+        assert_eq!(e.source().unwrap().to_string(), "insufficient capacity");
+
+        let e = InitError::BondingRecvError(RecvError::MessageTooBig { required: 40 });
+        assert_eq!(e.to_string(), "failed to receive during bonding");
+        assert_eq!(
+            e.source().unwrap().to_string(),
+            "message too big, 40 bytes required",
+        );
+        assert!(e.source().unwrap().source().is_none());
+
+        let e = InitError::from(ConfigError::Overlapping);
+        assert_eq!(e.to_string(), "invalid memory config");
+        assert_eq!(
+            e.source().unwrap().to_string(),
+            "shared memory regions overlap"
+        );
+
+        // all of them can be propagated with `?` into a boxed error, as host tools do
+        fn boxed<E: Error + 'static>(e: E) -> Result<(), std::boxed::Box<dyn Error>> {
+            Err(e)?
+        }
+        let e = boxed(InitError::BondingSendError(SendError::InvalidState)).unwrap_err();
+        assert_eq!(e.to_string(), "failed to send during bonding");
+        assert!(e.source().is_some());
+        assert_eq!(
+            boxed(SendError::InsufficientCapacity)
+                .unwrap_err()
+                .to_string(),
+            "insufficient capacity",
+        );
+        assert_eq!(
+            boxed(RecvError::MessageTooBig { required: 40 })
+                .unwrap_err()
+                .to_string(),
+            "message too big, 40 bytes required",
+        );
+    }
 
     #[cfg(not(loom))]
     #[tokio::main]
     #[test]
-    async fn test_bonding() {
-        let expected_messages: &[&[u8]] = &[
-            b"",
-            b"0",
-            b"01",
-            b"012",
-            b"0123",
-            b"01234",
-            b"012345",
-            b"0123456",
-            b"01234567",
-        ];
+    async fn test_init_checked() {
+        use std::boxed::Box;
+
+        const ALIGN: usize = 64;
+        #[repr(C, align(64))]
+        struct Region([u8; 256]);
+        fn region() -> &'static mut [u8] {
+            &mut Box::leak(Box::new(Region([0; 256]))).0
+        }
+
+        assert!(matches!(
+            MemoryConfig::from_slices::<ALIGN>(&mut region()[4..], region()),
+            Err(ConfigError::Misaligned),
+        ));
+        assert!(matches!(
+            MemoryConfig::from_slices::<ALIGN>(&mut region()[..128 + 20], region()),
+            Err(ConfigError::TooSmall),
+        ));
+        assert!(matches!(
+            MemoryConfig::from_slices::<ALIGN>(&mut region()[..128 + 26], region()),
+            Err(ConfigError::InvalidSize),
+        ));
+
+        let (region_1, region_2) = (region(), region());
+        let (ptr_1, ptr_2) = (region_1.as_mut_ptr(), region_2.as_mut_ptr());
+        let config_1 = MemoryConfig::from_slices::<ALIGN>(region_1, region_2).unwrap();
+        assert_eq!(config_1.config().send_region.as_ptr(), ptr_1.cast());
+        assert_eq!(config_1.config().recv_region.as_ptr(), ptr_2.cast());
+        assert_eq!(config_1.config().send_buffer_len, 128);
+        assert_eq!(config_1.config().recv_buffer_len, 128);
+        // Both sides of the channel are in this process, so the second config has to alias the
+        // first one's regions. It is derived from the first config's pointers rather than from
+        // new references, which would invalidate them.
+        let config_2 = MemoryConfig {
+            send_region: config_1.config().recv_region,
+            recv_region: config_1.config().send_region,
+            send_buffer_len: 128,
+            recv_buffer_len: 128,
+        };
+
+        let notify_1 = Notify::new();
+        let notify_2 = Notify::new();
+        let (icmsg_1, icmsg_2) = tokio::join!(
+            IcMsg::<_, _, ALIGN>::init_checked(config_1, &notify_1, &notify_2, TokioDelay),
+            unsafe { IcMsg::<_, _, ALIGN>::init(config_2, &notify_2, &notify_1, TokioDelay) },
+        );
+        let mut icmsg_1 = icmsg_1.unwrap();
+        let mut icmsg_2 = icmsg_2.unwrap();
+
+        icmsg_1.send(b"0123").unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(icmsg_2.try_recv(&mut buf), Ok(4));
+        assert_eq!(&buf, b"0123");
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_buffers() {
+        use crate::IcMsgBuffer;
+        use std::boxed::Box;
+
+        const ALIGN: usize = 64;
+        static _BUFFER: IcMsgBuffer<24, ALIGN> = IcMsgBuffer::new();
+
+        let buffer_1 = Box::leak(Box::new(IcMsgBuffer::<64, ALIGN>::new()));
+        let buffer_2 = Box::leak(Box::new(IcMsgBuffer::<128, ALIGN>::new()));
+        let (ptr_1, ptr_2) = (&raw mut *buffer_1, &raw mut *buffer_2);
+        assert_eq!(size_of::<IcMsgBuffer<64, ALIGN>>(), 128 + 64);
+
+        let config_1 = MemoryConfig::from_buffers(buffer_1, buffer_2);
+        assert_eq!(config_1.config().send_region.as_ptr(), ptr_1.cast());
+        assert_eq!(config_1.config().recv_region.as_ptr(), ptr_2.cast());
+        assert_eq!(config_1.config().send_buffer_len, 64);
+        assert_eq!(config_1.config().recv_buffer_len, 128);
+        // Both sides of the channel are in this process, so the second config has to alias the
+        // first one's buffers. It is derived from the first config's pointers rather than from
+        // new references, which would invalidate them.
+        let config_2 = MemoryConfig {
+            send_region: config_1.config().recv_region,
+            recv_region: config_1.config().send_region,
+            send_buffer_len: 128,
+            recv_buffer_len: 64,
+        };
+
+        let notify_1 = Notify::new();
+        let notify_2 = Notify::new();
+        let (icmsg_1, icmsg_2) = tokio::join!(
+            IcMsg::<_, _, ALIGN>::init_checked(config_1, &notify_1, &notify_2, TokioDelay),
+            unsafe { IcMsg::<_, _, ALIGN>::init(config_2, &notify_2, &notify_1, TokioDelay) },
+        );
+        let mut icmsg_1 = icmsg_1.unwrap();
+        let mut icmsg_2 = icmsg_2.unwrap();
+
+        icmsg_2.send(&[0x55; 100]).unwrap();
+        let mut buf = [0; 100];
+        assert_eq!(icmsg_1.try_recv(&mut buf), Ok(100));
+        assert_eq!(buf, [0x55; 100]);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_from_regions() {
+        use crate::header_size;
+
+        assert_eq!(header_size::<4>(), 8);
+        assert_eq!(header_size::<64>(), 128);
+        assert_eq!(header_size::<4>(), SharedMemoryRegionHeader::<4>::SIZE);
+        assert_eq!(crate::required_region_size::<64>(256), 384);
+
+        const _: () = assert!(crate::min_buffer_len(16) == 24);
+        assert_eq!(crate::min_buffer_len(0), 24);
+        assert_eq!(crate::min_buffer_len(12), 24);
+        assert_eq!(crate::min_buffer_len(13), 24);
+        assert_eq!(crate::min_buffer_len(17), 28);
+        assert_eq!(
+            crate::min_buffer_len(crate::protocol::MAX_BONDING_MESSAGE_LEN),
+            40
+        );
+        for len in [0, 1, 4, 12, 13, 16, 17, 100, u16::MAX as usize] {
+            let buffer_len = crate::min_buffer_len(len);
+            assert!(crate::max_message_len(buffer_len) >= len);
+            assert!(buffer_len == 24 || crate::max_message_len(buffer_len - 4) < len);
+            let config = MemoryConfig::new(
+                NonNull::dangling(),
+                NonNull::dangling(),
+                buffer_len,
+                buffer_len,
+            );
+            assert_eq!(config.check_lengths(), Ok(()));
+        }
+
+        let region = core::ptr::without_provenance_mut::<()>(0x2000_0000);
+        let config = MemoryConfig::from_regions::<64>(
+            region,
+            128 + 256,
+            region.wrapping_byte_add(512),
+            128 + 64,
+        )
+        .unwrap();
+        assert_eq!(config.send_buffer_len, 256);
+        assert_eq!(config.recv_buffer_len, 64);
+
+        let other_region = region.wrapping_byte_add(1024);
+        assert!(matches!(
+            MemoryConfig::from_regions::<4>(core::ptr::null_mut(), 256, other_region, 256),
+            Err(ConfigError::NullRegion),
+        ));
+        let misaligned = region.wrapping_byte_add(4);
+        assert!(matches!(
+            MemoryConfig::from_regions::<64>(misaligned, 256, other_region, 256),
+            Err(ConfigError::Misaligned),
+        ));
+        assert!(matches!(
+            MemoryConfig::from_regions::<4>(region, 8 + 20, other_region, 64),
+            Err(ConfigError::TooSmall),
+        ));
+        assert!(matches!(
+            MemoryConfig::from_regions::<4>(region, 4, other_region, 64),
+            Err(ConfigError::TooSmall),
+        ));
+        assert!(matches!(
+            MemoryConfig::from_regions::<4>(region, 8 + 26, other_region, 64),
+            Err(ConfigError::InvalidSize),
+        ));
+        assert!(matches!(
+            MemoryConfig::from_regions::<4>(region, 8 + 1024, other_region, 64),
+            Err(ConfigError::Overlapping),
+        ));
+    }
+
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_init_invalid_regions() {
+        const ALIGN: usize = 64;
+        let notify = Notify::new();
+        let region = core::ptr::without_provenance_mut::<()>(0x2000_0000);
+        let at = |offset| NonNull::new(region.wrapping_byte_offset(offset)).unwrap();
+
+        let config = MemoryConfig {
+            send_region: at(4),
+            recv_region: at(1024),
+            send_buffer_len: 256,
+            recv_buffer_len: 256,
+        };
+        let r = unsafe { IcMsg::<_, _, ALIGN>::init(config, &notify, &notify, TokioDelay).await };
+        assert!(matches!(r, Err(InitError::Config(ConfigError::Misaligned))));
+
+        // each region is a 128 byte header followed by 256 bytes of data
+        for recv_offset in [0, 320, -320] {
+            let config = MemoryConfig {
+                send_region: at(0),
+                recv_region: at(recv_offset),
+                send_buffer_len: 256,
+                recv_buffer_len: 256,
+            };
+            let r =
+                unsafe { IcMsg::<_, _, ALIGN>::init(config, &notify, &notify, TokioDelay).await };
+            assert!(matches!(
+                r,
+                Err(InitError::Config(ConfigError::Overlapping))
+            ));
+        }
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_bonder() {
+        use core::cell::Cell;
+
+        use crate::{BondPoll, Bonder, new_transport};
 
         const ALIGN: usize = 4;
-        type Hdr = SharedMemoryRegionHeader<ALIGN>;
-        let buf_size = 24;
-        let shared_region_layout =
-            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
-        let shared_region_1 = unsafe { alloc::alloc(shared_region_layout) }.cast::<()>();
-        let shared_region_2 = unsafe { alloc::alloc(shared_region_layout) }.cast::<()>();
-        let notify_1 = Arc::new(Notify::new());
-        let notify_2 = Arc::new(Notify::new());
+        let loopback = crate::testing::loopback::<24, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let (notified_1, notified_2) = (&Cell::new(false), &Cell::new(false));
 
-        let recv_task = tokio::spawn(SyncThing({
-            let notify_1 = Arc::clone(&notify_1);
-            let notify_2 = Arc::clone(&notify_2);
-            async move {
-                let config = MemoryConfig {
-                    send_region: shared_region_2,
-                    recv_region: shared_region_1,
-                    send_buffer_len: buf_size as u32,
-                    recv_buffer_len: buf_size as u32,
+        let new_bonder_1 = || {
+            let transport =
+                unsafe { new_transport::<_, ALIGN>(config_1, || notified_2.set(true)).unwrap() };
+            Bonder::new(transport, BondingConfig::default()).unwrap()
+        };
+        let new_bonder_2 = || {
+            let transport =
+                unsafe { new_transport::<_, ALIGN>(config_2, || notified_1.set(true)).unwrap() };
+            Bonder::new(transport, BondingConfig::default()).unwrap()
+        };
+
+        // How many times side 1 is polled before side 2 starts, and whether side 2 is polled
+        // first once both have started.
+        for (early_polls, side_2_first) in [(0, false), (0, true), (3, false), (3, true)] {
+            notified_1.set(false);
+            notified_2.set(false);
+            let mut bonder_1 = new_bonder_1();
+            for _ in 0..early_polls {
+                assert!(matches!(
+                    bonder_1.poll(notified_1.replace(false)),
+                    BondPoll::Pending
+                ));
+            }
+            let mut bonder_2 = new_bonder_2();
+
+            let (mut transport_1, mut transport_2) = (None, None);
+            for _ in 0..4 {
+                let mut poll_1 = || match bonder_1.poll(notified_1.replace(false)) {
+                    BondPoll::Ready(t) => transport_1 = Some(t),
+                    BondPoll::Pending => {}
+                    BondPoll::Failed(e) => panic!("side 1 failed: {e:?}"),
                 };
-                let mut icmsg = unsafe {
-                    IcMsg::<_, _, ALIGN>::init(config, &*notify_2, &*notify_1, TokioDelay)
-                        .await
-                        .unwrap()
+                let mut poll_2 = || match bonder_2.poll(notified_2.replace(false)) {
+                    BondPoll::Ready(t) => transport_2 = Some(t),
+                    BondPoll::Pending => {}
+                    BondPoll::Failed(e) => panic!("side 2 failed: {e:?}"),
                 };
-
-                // receive messages
-                let mut buf = [0; 8];
-                for &expected_message in expected_messages {
-                    let n = icmsg.recv(&mut buf).await.unwrap();
-                    std::eprintln!("task 2 recv'd {:?}", &buf[..n]);
-                    assert_eq!(&buf[..n], expected_message);
+                if side_2_first {
+                    poll_2();
+                    poll_1();
+                } else {
+                    poll_1();
+                    poll_2();
+                }
+                if transport_1.is_some() && transport_2.is_some() {
+                    break;
                 }
+            }
+            let (mut transport_1, mut transport_2) = (transport_1.unwrap(), transport_2.unwrap());
 
-                // send messages
-                for msg in expected_messages {
-                    loop {
-                        let r = icmsg.send(msg);
-                        if r.is_err() {
-                            tokio::task::yield_now().await;
-                            continue;
-                        }
-                        std::eprintln!("task 2 sent {msg:?}");
+            let mut buf = [0; 8];
+            transport_1.send(b"012").unwrap();
+            let n = transport_2.try_recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"012");
+            transport_2.send(b"0123").unwrap();
+            let n = transport_1.try_recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"0123");
+        }
+
+        // Every poll without a notification counts as one retry interval.
+        let transport =
+            unsafe { new_transport::<_, ALIGN>(config_1, || notified_2.set(true)).unwrap() };
+        let bonding_config = BondingConfig {
+            retry_interval_ms: 10,
+            timeout_ms: Some(30),
+            ..Default::default()
+        };
+        let mut bonder = Bonder::new(transport, bonding_config).unwrap();
+        assert!(matches!(bonder.poll(false), BondPoll::Pending));
+        assert!(matches!(bonder.poll(false), BondPoll::Pending));
+        assert!(matches!(
+            bonder.poll(false),
+            BondPoll::Failed(InitError::BondingTimeout)
+        ));
+
+        // A retry interval of 0 still counts towards the timeout.
+        let transport =
+            unsafe { new_transport::<_, ALIGN>(config_1, || notified_2.set(true)).unwrap() };
+        let bonding_config = BondingConfig {
+            retry_interval_ms: 0,
+            timeout_ms: Some(2),
+            ..Default::default()
+        };
+        let mut bonder = Bonder::new(transport, bonding_config).unwrap();
+        assert!(matches!(bonder.poll(false), BondPoll::Pending));
+        assert!(matches!(
+            bonder.poll(false),
+            BondPoll::Failed(InitError::BondingTimeout)
+        ));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_blocking() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        use crate::{Bonder, blocking, loom::thread, new_transport, transport::SendError};
+
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<24, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let config_2 = SyncThing(config_2);
+        static NOTIFIED_1: AtomicBool = AtomicBool::new(false);
+        static NOTIFIED_2: AtomicBool = AtomicBool::new(false);
+
+        // More messages than fit in the ring at once, so the sender has to wait for space.
+        let messages: &[&[u8]] = &[b"0", b"01234567", b"012", b"0123456", b"01", b"012345"];
+
+        let recv_thread = thread::spawn(move || {
+            let config = { config_2 }.0;
+            let transport = unsafe {
+                new_transport::<_, ALIGN>(config, || NOTIFIED_1.store(true, Ordering::Release))
+                    .unwrap()
+            };
+            let bonder = Bonder::new(transport, BondingConfig::default()).unwrap();
+            let (_, mut receiver) = blocking::bond(
+                bonder,
+                || NOTIFIED_2.swap(false, Ordering::Acquire),
+                thread::yield_now,
+            )
+            .unwrap();
+
+            let mut buf = [0; 8];
+            for &msg in messages {
+                let n = receiver.recv(&mut buf, thread::yield_now).unwrap();
+                assert_eq!(&buf[..n], msg);
+            }
+            assert_eq!(
+                receiver.recv(&mut buf, thread::yield_now),
+                Err(RecvError::PeerClosed)
+            );
+        });
+
+        let config = config_1;
+        let transport = unsafe {
+            new_transport::<_, ALIGN>(config, || NOTIFIED_2.store(true, Ordering::Release)).unwrap()
+        };
+        let bonder = Bonder::new(transport, BondingConfig::default()).unwrap();
+        let (mut sender, _) = blocking::bond(
+            bonder,
+            || NOTIFIED_1.swap(false, Ordering::Acquire),
+            thread::yield_now,
+        )
+        .unwrap();
+        for &msg in messages {
+            sender.send(msg, thread::yield_now).unwrap();
+        }
+        // The teardown message is reported as such instead of being received as data.
+        let transport = sender.inner().transport_mut();
+        while transport.send_typed(&crate::CLOSE_MAGIC, crate::transport::CONTROL)
+            == Err(SendError::InsufficientCapacity)
+        {
+            thread::yield_now();
+        }
+
+        recv_thread.join().unwrap();
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_init_blocking() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        use crate::{init_blocking, loom::thread};
+
+        const ALIGN: usize = 4;
+        let loopback = crate::testing::loopback::<24, ALIGN>();
+        let (config_1, config_2) = loopback.memory_configs();
+        let config_2 = SyncThing(config_2);
+        static NOTIFIED_1: AtomicBool = AtomicBool::new(false);
+        static NOTIFIED_2: AtomicBool = AtomicBool::new(false);
+
+        let recv_thread = thread::spawn(move || {
+            let config = { config_2 }.0;
+            let mut transport = unsafe {
+                init_blocking::<_, ALIGN>(
+                    config,
+                    || NOTIFIED_1.store(true, Ordering::Release),
+                    || NOTIFIED_2.swap(false, Ordering::Acquire),
+                    100,
+                )
+                .unwrap()
+            };
+
+            let mut buf = [0; 8];
+            loop {
+                match transport.try_recv(&mut buf) {
+                    Ok(n) => {
+                        assert_eq!(&buf[..n], b"hello");
                         break;
                     }
+                    Err(RecvError::Empty) => thread::yield_now(),
+                    Err(e) => panic!("{e:?}"),
                 }
             }
-        }));
+        });
 
-        let config = MemoryConfig {
-            send_region: shared_region_1,
-            recv_region: shared_region_2,
-            send_buffer_len: buf_size as u32,
-            recv_buffer_len: buf_size as u32,
-        };
-        let mut icmsg = unsafe {
-            IcMsg::<_, _, ALIGN>::init(config, &*notify_1, &*notify_2, TokioDelay)
-                .await
-                .unwrap()
+        let config = config_1;
+        let mut transport = unsafe {
+            init_blocking::<_, ALIGN>(
+                config,
+                || NOTIFIED_2.store(true, Ordering::Release),
+                || NOTIFIED_1.swap(false, Ordering::Acquire),
+                100,
+            )
+            .unwrap()
         };
+        // The other side may not have read our bonding message yet, which leaves no room.
+        while transport.send(b"hello") == Err(crate::transport::SendError::InsufficientCapacity) {
+            thread::yield_now();
+        }
 
-        // send messages
-        for msg in expected_messages {
-            loop {
-                let r = icmsg.send(msg);
-                if r.is_err() {
-                    tokio::task::yield_now().await;
-                    continue;
+        recv_thread.join().unwrap();
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_sync_notify() {
+        use crate::sync_notify::{Channel, pair};
+        use embassy_futures::block_on;
+
+        // A notification before the wait must not be lost.
+        let channel = Channel::new();
+        Notifier::notify(&mut &channel);
+        block_on((&channel).wait_for_notify());
+
+        let (mut icmsg_1, mut icmsg_2) = block_on(pair::<64, 4>()).unwrap();
+        let mut buf = [0; 8];
+        icmsg_1.send(b"ping").unwrap();
+        let n = block_on(icmsg_2.recv(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        icmsg_2.send(b"pong").unwrap();
+        let n = block_on(icmsg_1.recv(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], b"pong");
+
+        // Both sides can run on plain threads.
+        let thread = std::thread::spawn(move || {
+            let n = block_on(icmsg_2.recv(&mut buf)).unwrap();
+            assert_eq!(&buf[..n], b"threads");
+        });
+        icmsg_1.send(b"threads").unwrap();
+        thread.join().unwrap();
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_loopback() {
+        use crate::testing::loopback;
+        use embassy_futures::block_on;
+
+        let mut loopback = loopback::<64, 4>();
+        let mut buf = [0; 8];
+        for _ in 0..2 {
+            let (mut icmsg_1, mut icmsg_2) = loopback.connect().unwrap();
+            icmsg_1.send(b"ping").unwrap();
+            let n = block_on(icmsg_2.recv(&mut buf)).unwrap();
+            assert_eq!(&buf[..n], b"ping");
+            icmsg_2.send(b"pong").unwrap();
+            let n = block_on(icmsg_1.recv(&mut buf)).unwrap();
+            assert_eq!(&buf[..n], b"pong");
+        }
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_receiver_reader() {
+        use crate::{reader::ReceiverReader, testing::loopback};
+        use embassy_futures::block_on;
+        use embedded_io::{Error, ErrorKind};
+        use embedded_io_async::{BufRead, Read};
+
+        let mut loopback = loopback::<64, 4>();
+        let (icmsg_1, icmsg_2) = loopback.connect().unwrap();
+        let (mut sender, _) = icmsg_1.split();
+        let (_, receiver) = icmsg_2.split();
+        let mut reader = ReceiverReader::<_, 4, 8>::new(receiver);
+        let mut buf = [0; 3];
+
+        // a message bigger than the caller's buffer is handed out over several reads, and leaves
+        // the ring as soon as its first bytes are read
+        sender.send(b"012345").unwrap();
+        assert_eq!(block_on(reader.read(&mut buf)), Ok(3));
+        assert_eq!(&buf, b"012");
+        assert_eq!(sender.free_space(), sender.capacity());
+        sender.send(b"67").unwrap();
+        assert_eq!(block_on(reader.read(&mut buf)), Ok(3));
+        assert_eq!(&buf, b"345");
+
+        // empty messages are skipped
+        sender.send(b"").unwrap();
+        sender.send(b"89").unwrap();
+        assert_eq!(block_on(reader.read(&mut buf)), Ok(2));
+        assert_eq!(&buf[..2], b"67");
+        assert_eq!(block_on(reader.fill_buf()), Ok(&b"89"[..]));
+        reader.consume(1);
+        assert_eq!(block_on(reader.read(&mut buf)), Ok(1));
+        assert_eq!(&buf[..1], b"9");
+
+        // a message bigger than the reader's buffer is dropped, with an error that keeps its kind
+        sender.send(b"012345678").unwrap();
+        sender.send(b"0").unwrap();
+        let e = block_on(reader.read(&mut buf)).unwrap_err();
+        assert_eq!(e, RecvError::MessageTooBig { required: 9 });
+        assert_eq!(e.kind(), ErrorKind::OutOfMemory);
+        assert_eq!(block_on(reader.read(&mut buf)), Ok(1));
+        assert_eq!(&buf[..1], b"0");
+
+        let mut receiver = reader.into_inner();
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_notify_race() {
+        use crate::{sync_notify::pair, transport::SendError};
+        use embassy_futures::block_on;
+
+        const COUNT: u32 = 1000;
+
+        // A ring that only holds a couple of messages, so that the receiver keeps finding it empty
+        // and the sender keeps notifying right around when the receiver starts waiting.
+        let (mut icmsg_1, mut icmsg_2) = block_on(pair::<24, 4>()).unwrap();
+        let thread = std::thread::spawn(move || {
+            for i in 0..COUNT {
+                loop {
+                    match icmsg_1.send(&i.to_le_bytes()) {
+                        Ok(()) => break,
+                        Err(SendError::InsufficientCapacity) => std::thread::yield_now(),
+                        Err(e) => panic!("{e:?}"),
+                    }
                 }
-                std::eprintln!("task 1 sent {msg:?}");
-                break;
             }
+        });
+
+        let mut buf = [0; 4];
+        for i in 0..COUNT {
+            let n = block_on(icmsg_2.recv(&mut buf)).unwrap();
+            assert_eq!(&buf[..n], i.to_le_bytes());
         }
+        thread.join().unwrap();
+    }
 
-        // receive messages
+    #[cfg(not(loom))]
+    #[test]
+    fn test_recv_many() {
+        use crate::sync_notify::pair;
+        use core::ops::ControlFlow;
+        use embassy_futures::{block_on, join::join};
+
+        let (mut icmsg_1, mut icmsg_2) = block_on(pair::<64, 4>()).unwrap();
+        let messages: [&[u8]; 2] = [b"0", b"12"];
+        for msg in messages {
+            icmsg_2.send(msg).unwrap();
+        }
+
+        // The teardown message ends the batch and is reported by the next call.
+        let drained = Notify::new();
+        let mut waiter = &drained;
+        let (r, _) = block_on(join(icmsg_2.deinit(&mut waiter), async {
+            let mut scratch = [0; 16];
+            let mut i = 0;
+            let r = icmsg_1.try_recv_many(&mut scratch, |msg| {
+                assert_eq!(msg, messages[i]);
+                i += 1;
+                ControlFlow::Continue(())
+            });
+            assert_eq!(r, Ok(2));
+            assert_eq!(
+                icmsg_1.try_recv_many(&mut scratch, |_| unreachable!()),
+                Err(RecvError::PeerClosed)
+            );
+            drained.notify_one();
+        }));
+        assert!(r.is_ok());
+    }
+
+    #[cfg(all(not(loom), feature = "notify-on-drop"))]
+    #[test]
+    fn test_notify_on_drop() {
+        use crate::sync_notify::pair;
+        use embassy_futures::{block_on, join::join};
+
+        let (icmsg_1, mut icmsg_2) = block_on(pair::<64, 4>()).unwrap();
+        let (_, mut receiver_1) = icmsg_1.split();
         let mut buf = [0; 8];
-        for &expected_message in expected_messages {
-            let n = icmsg.recv(&mut buf).await.unwrap();
-            std::eprintln!("task 1 recv'd {:?}", &buf[..n]);
-            assert_eq!(&buf[..n], expected_message);
+        assert_eq!(icmsg_2.try_recv(&mut buf), Err(RecvError::PeerClosed));
+
+        icmsg_2.send(b"0").unwrap();
+        drop(icmsg_2);
+        let n = block_on(receiver_1.recv(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], b"0");
+        assert_eq!(
+            block_on(receiver_1.recv(&mut buf)),
+            Err(RecvError::PeerClosed)
+        );
+
+        // Only one teardown message is sent after deinit.
+        let (mut icmsg_3, icmsg_4) = block_on(pair::<64, 4>()).unwrap();
+        let drained = Notify::new();
+        let mut waiter = &drained;
+        let (r, _) = block_on(join(icmsg_4.deinit(&mut waiter), async {
+            assert_eq!(icmsg_3.recv(&mut buf).await, Err(RecvError::PeerClosed));
+            drained.notify_one();
+        }));
+        assert!(r.is_ok());
+        let (_, receiver_3) = icmsg_3.split();
+        assert_eq!(receiver_3.pending_bytes(), 0);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_observer() {
+        use crate::{sync_notify::pair, transport::Observer};
+        use embassy_futures::block_on;
+
+        #[derive(Clone, Default)]
+        struct Counter {
+            sent: usize,
+            recv: usize,
         }
 
-        recv_task.await.unwrap();
-        unsafe {
-            alloc::dealloc(shared_region_1.cast(), shared_region_layout);
-            alloc::dealloc(shared_region_2.cast(), shared_region_layout);
+        impl Observer for Counter {
+            fn on_send(&mut self, len: usize) {
+                self.sent += len;
+            }
+            fn on_recv(&mut self, len: usize) {
+                self.recv += len;
+            }
         }
+
+        let (icmsg_1, icmsg_2) = block_on(pair::<64, 4>()).unwrap();
+        let mut icmsg_1 = icmsg_1.with_observer(Counter::default());
+        let mut icmsg_2 = icmsg_2.with_observer(Counter::default());
+
+        let mut buf = [0; 8];
+        icmsg_1.send(b"012").unwrap();
+        icmsg_1.send(b"01234").unwrap();
+        assert_eq!(icmsg_2.try_recv(&mut buf), Ok(3));
+        assert_eq!(icmsg_2.try_recv(&mut buf), Ok(5));
+
+        let (sender_1, _) = icmsg_1.split();
+        let (_, receiver_2) = icmsg_2.split();
+        assert_eq!(sender_1.transport().observer().sent, 8);
+        assert_eq!(receiver_2.transport().observer().recv, 8);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_barrier() {
+        use crate::sync_notify::pair;
+        use core::cell::Cell;
+        use embassy_futures::block_on;
+
+        let (icmsg_1, icmsg_2) = block_on(pair::<64, 4>()).unwrap();
+        let (sent, received) = (Cell::new(0), Cell::new(0));
+        let mut icmsg_1 = icmsg_1.with_barrier(|| sent.set(sent.get() + 1));
+        let mut icmsg_2 = icmsg_2.with_barrier(|| received.set(received.get() + 1));
+
+        let mut buf = [0; 8];
+        icmsg_1.send(b"012").unwrap();
+        assert_eq!((sent.get(), received.get()), (1, 0));
+        assert_eq!(icmsg_2.try_recv(&mut buf), Ok(3));
+        // once to look for control messages and once to receive
+        assert_eq!((sent.get(), received.get()), (1, 2));
+    }
+
+    #[cfg(all(not(loom), feature = "heapless"))]
+    #[test]
+    fn test_recv_exact() {
+        use crate::sync_notify::pair;
+        use embassy_futures::block_on;
+
+        let (mut icmsg_1, mut icmsg_2) = block_on(pair::<64, 4>()).unwrap();
+        assert_eq!(icmsg_2.try_recv_exact::<8>(), Err(RecvError::Empty));
+
+        icmsg_1.send(b"0123456789").unwrap();
+        icmsg_1.send(b"").unwrap();
+        assert_eq!(
+            icmsg_2.try_recv_exact::<8>(),
+            Err(RecvError::MessageTooBig { required: 10 })
+        );
+        let msg = block_on(icmsg_2.recv_exact::<10>()).unwrap();
+        assert_eq!(msg, b"0123456789");
+        let msg = block_on(icmsg_2.recv_exact::<0>()).unwrap();
+        assert!(msg.is_empty());
     }
 
     impl Notifier for &'_ Notify {
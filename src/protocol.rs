@@ -0,0 +1,98 @@
+//! The wire format of ICMsg, for tools that inspect the shared memory regions from outside, e.g.
+//! to check a RAM dump.
+//!
+//! Each region starts with a [`SharedMemoryRegionHeader`] holding the little-endian `rd_idx` and
+//! `wr_idx`, followed by the data buffer, which is used as a ring. Each message is stored in the
+//! ring as a packet:
+//!
+//! - a [`HEADER_SIZE`] byte header: the message length as a big-endian `u16`, then a flags byte
+//!   and a byte for the sequence number, both of which the reference implementation leaves
+//!   unspecified,
+//! - the message, padded with unspecified bytes to [`padded_len`],
+//! - a 4 byte CRC-32 trailer, only if [CRC checking][crate::transport::Sender::set_crc] is
+//!   enabled and the packet is not a [`CONTROL`] packet.
+//!
+//! A packet starts at a multiple of 4 and wraps around the end of the ring. `wr_idx` is the index
+//! after the last packet and `rd_idx` the index of the first unread one. Since `rd_idx == wr_idx`
+//! means empty, at most [`capacity`] bytes are in use at a time.
+//!
+//! Rings with a `PAD` other than the reference implementation's 4, see
+//! [`Sender`][crate::transport::Sender], pad messages with [`padded_len_with`] and start packets
+//! at multiples of `PAD` instead, so the header and trailer can wrap around as well.
+//!
+//! [`SharedMemoryRegionHeader`]: crate::transport::SharedMemoryRegionHeader
+
+pub use crate::transport::{CONTROL, MORE_FRAGMENTS};
+
+/// The first message each side sends after initializing its send region. The bonding message
+/// may carry more bytes after the magic, see [`BONDING_FEATURES`] and
+/// [`BondingConfig`][crate::BondingConfig].
+pub const BONDING_MAGIC: [u8; 13] = [
+    0x45, 0x6d, 0x31, 0x6c, 0x31, 0x4b, 0x30, 0x72, 0x6e, 0x33, 0x6c, 0x69, 0x34,
+];
+
+/// The longest bonding message sent by this crate: the magic, the protocol version, the
+/// [presence byte][BONDING_FEATURES], the feature bits, the session ID and both buffer lengths. A
+/// data field of [`min_buffer_len(MAX_BONDING_MESSAGE_LEN)`][crate::min_buffer_len] bytes fits the
+/// bonding message with any [`BondingConfig`][crate::BondingConfig].
+pub const MAX_BONDING_MESSAGE_LEN: usize = BONDING_MAGIC.len() + 1 + 1 + 4 + 2 + 8;
+
+/// Set in the presence byte of a bonding message if it carries the 4 byte little-endian feature
+/// bits.
+///
+/// A bonding message that carries more than the magic continues with a protocol version byte and
+/// a presence byte, followed by the fields whose bits are set, in the order of the bits: the
+/// feature bits, the [session ID][BONDING_SESSION_ID] and the [buffer lengths][BONDING_BUFFER_LENS].
+/// Unknown bits and any bytes after the known fields are ignored, so later versions can add fields
+/// at the end.
+pub const BONDING_FEATURES: u8 = 1 << 0;
+
+/// Set in the presence byte of a bonding message if it carries the 2 byte little-endian session
+/// ID, see [`BONDING_FEATURES`].
+pub const BONDING_SESSION_ID: u8 = 1 << 1;
+
+/// Set in the presence byte of a bonding message if it carries the sender's send and receive
+/// buffer lengths, each a 4 byte little-endian `u32`, see [`BONDING_FEATURES`].
+pub const BONDING_BUFFER_LENS: u8 = 1 << 2;
+
+/// Sent by [`IcMsg::deinit`][crate::IcMsg::deinit] to tell the other side that the channel is
+/// being torn down. This is not part of the reference implementation, it is [`BONDING_MAGIC`]
+/// reversed.
+pub const CLOSE_MAGIC: [u8; 13] = [
+    0x34, 0x69, 0x6c, 0x33, 0x6e, 0x72, 0x30, 0x4b, 0x31, 0x6c, 0x31, 0x6d, 0x45,
+];
+
+/// The feature bit for [CRC checking][crate::transport::Sender::set_crc], see
+/// [`BondingConfig::features`][crate::BondingConfig::features].
+pub const FEATURE_CRC: u32 = 1 << 0;
+
+/// The feature bit for [sequence numbers][crate::transport::Sender::set_sequence], see
+/// [`BondingConfig::features`][crate::BondingConfig::features].
+pub const FEATURE_SEQUENCE: u32 = 1 << 1;
+
+/// The size of the header in front of each packet.
+pub const HEADER_SIZE: usize = 4;
+
+/// The message length stored in a packet header, which is big-endian.
+pub const fn message_len(header: [u8; HEADER_SIZE]) -> usize {
+    u16::from_be_bytes([header[0], header[1]]) as usize
+}
+
+/// The size of a message of `len` bytes in the ring, not including the header and trailer.
+/// Messages are padded to a multiple of 4 so that every header is aligned.
+pub const fn padded_len(len: usize) -> usize {
+    padded_len_with(len, 4)
+}
+
+/// Like [`padded_len`], for a ring whose messages are padded to a multiple of `pad` bytes instead,
+/// see the `PAD` parameter of [`Sender`][crate::transport::Sender].
+pub const fn padded_len_with(len: usize, pad: usize) -> usize {
+    len + (pad - len % pad) % pad
+}
+
+/// The most bytes that can be in use in a data buffer of `buffer_len` bytes, including headers,
+/// padding and trailers. One byte is always left free, so that a full ring can be told apart from
+/// an empty one.
+pub const fn capacity(buffer_len: usize) -> usize {
+    buffer_len.saturating_sub(1)
+}
@@ -0,0 +1,108 @@
+//! An [`embedded_io_async::Read`] that takes messages out of the ring one at a time.
+
+use crate::{Receiver, WaitForNotify, transport::RecvError};
+
+/// A reader that receives one message at a time into a buffer of `BUF` bytes and hands its bytes
+/// out across several reads, for parsers that read a packet in several steps.
+///
+/// Unlike reading from the [`Receiver`] itself, which leaves the unread rest of a message in the
+/// ring, each message is taken out of the ring as soon as the first of its bytes is read, so the
+/// other side can reuse the space while the message is parsed. Empty messages are skipped. A
+/// message bigger than `BUF` bytes is discarded and the read fails with
+/// [`RecvError::MessageTooBig`], so the next read carries on with the following message.
+///
+/// Besides [`Read`][embedded_io_async::Read], this implements
+/// [`BufRead`][embedded_io_async::BufRead], whose
+/// [`fill_buf`][embedded_io_async::BufRead::fill_buf] returns the unread rest of the current
+/// message.
+pub struct ReceiverReader<W, const ALIGN: usize, const BUF: usize>
+where
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    receiver: Receiver<W, ALIGN>,
+    buf: [u8; BUF],
+    // the unread part of the current message is `buf[pos..len]`
+    pos: usize,
+    len: usize,
+}
+
+impl<W, const ALIGN: usize, const BUF: usize> ReceiverReader<W, ALIGN, BUF>
+where
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn new(receiver: Receiver<W, ALIGN>) -> Self {
+        Self {
+            receiver,
+            buf: [0; BUF],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Return the underlying [`Receiver`], dropping the unread rest of the current message.
+    pub fn into_inner(self) -> Receiver<W, ALIGN> {
+        self.receiver
+    }
+
+    /// Receive the next non-empty message if the current one has been read completely.
+    async fn fill(&mut self) -> Result<(), RecvError> {
+        while self.pos == self.len {
+            match self.receiver.recv(&mut self.buf).await {
+                Ok(n) => (self.pos, self.len) = (0, n),
+                Err(e @ RecvError::MessageTooBig { .. }) => {
+                    self.receiver.discard_next()?;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W, const ALIGN: usize, const BUF: usize> embedded_io::ErrorType
+    for ReceiverReader<W, ALIGN, BUF>
+where
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    type Error = RecvError;
+}
+
+impl<W, const ALIGN: usize, const BUF: usize> embedded_io_async::Read
+    for ReceiverReader<W, ALIGN, BUF>
+where
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Read bytes from the current message, waiting for the next one if it has been read
+    /// completely. A single call never returns bytes from more than one message.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, RecvError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.fill().await?;
+        let n = buf.len().min(self.len - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<W, const ALIGN: usize, const BUF: usize> embedded_io_async::BufRead
+    for ReceiverReader<W, ALIGN, BUF>
+where
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    async fn fill_buf(&mut self) -> Result<&[u8], RecvError> {
+        self.fill().await?;
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.len);
+    }
+}
@@ -0,0 +1,131 @@
+//! A [`Sink`] of messages to send.
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_sink::Sink;
+
+use crate::{Notifier, PollWaitForNotify, Sender, transport::SendError};
+
+/// A [`Sink`] of messages sent by a [`Sender`], created by [`Sender::into_sink`].
+///
+/// The sink is ready once a message of `max_len` bytes fits in the ring, so any item of up to
+/// `max_len` bytes can then be sent without waiting. An item bigger than that is rejected with
+/// [`SendError::MessageTooLarge`]. Items are sent without notifying the other side;
+/// [`poll_flush`][Sink::poll_flush] notifies it, and so does `poll_ready` before it waits for
+/// space, since the other side may not read the unflushed messages otherwise.
+///
+/// # Backpressure
+///
+/// The space is checked again every time `waiter` is notified. The other side does not notify
+/// when it reads messages, so, as for [`Sender::wait_for_space`], `waiter` has to be something
+/// that fires after it does, e.g. a periodic retry timer, or the other side's messages if it
+/// answers every message. With a timer, a full ring is only noticed to have drained on the next
+/// tick, so its period bounds the added latency.
+pub struct MessageSink<M, W, const ALIGN: usize>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    sender: Sender<M, ALIGN>,
+    waiter: W,
+    max_len: usize,
+    unflushed: bool,
+}
+
+// Nothing is pinned structurally.
+impl<M, W, const ALIGN: usize> Unpin for MessageSink<M, W, ALIGN>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+}
+
+impl<M, const ALIGN: usize> Sender<M, ALIGN>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Turn this sender into a [`Sink`] of messages of at most `max_len` bytes, which waits for
+    /// space using `waiter`.
+    pub fn into_sink<W: PollWaitForNotify>(
+        self,
+        waiter: W,
+        max_len: usize,
+    ) -> MessageSink<M, W, ALIGN> {
+        MessageSink {
+            sender: self,
+            waiter,
+            max_len,
+            unflushed: false,
+        }
+    }
+}
+
+impl<M, W, const ALIGN: usize> MessageSink<M, W, ALIGN>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Get the sender and waiter back. Messages that have not been flushed yet are still in the
+    /// ring, but the other side may not have been notified of them.
+    pub fn into_inner(self) -> (Sender<M, ALIGN>, W) {
+        (self.sender, self.waiter)
+    }
+
+    fn flush(&mut self) {
+        if self.unflushed {
+            self.sender.notify();
+            self.unflushed = false;
+        }
+    }
+}
+
+impl<M, W, const ALIGN: usize> Sink<&[u8]> for MessageSink<M, W, ALIGN>
+where
+    M: Notifier,
+    W: PollWaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    type Error = SendError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        let this = self.get_mut();
+        if this.max_len > this.sender.max_message_len() {
+            return Poll::Ready(Err(SendError::MessageTooLarge));
+        }
+        loop {
+            // Let the waiter register its waker before checking for space
+            let r = this.waiter.poll_wait_for_notify(cx);
+
+            if this.sender.can_send(this.max_len) {
+                return Poll::Ready(Ok(()));
+            }
+            this.flush();
+            if r.is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: &[u8]) -> Result<(), SendError> {
+        let this = self.get_mut();
+        if item.len() > this.max_len {
+            return Err(SendError::MessageTooLarge);
+        }
+        this.sender.send_no_notify(item)?;
+        this.unflushed = true;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        self.get_mut().flush();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        <Self as Sink<&[u8]>>::poll_flush(self, cx)
+    }
+}
@@ -0,0 +1,86 @@
+//! A [`Stream`] of received messages.
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+
+use crate::{PollWaitForNotify, Receiver, WaitForNotify, transport::RecvError};
+
+/// A [`Stream`] of messages received by a [`Receiver`], created by [`Receiver::into_stream`].
+///
+/// Each message is copied into a [`heapless::Vec`] of capacity `N`. A message bigger than `N`
+/// bytes is discarded and yielded as [`RecvError::MessageTooBig`], and the stream carries on with
+/// the next message. The stream ends when the other side [tears down][crate::IcMsg::deinit] the
+/// channel.
+pub struct ReceiverStream<W, const ALIGN: usize, const N: usize>
+where
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    receiver: Receiver<W, ALIGN>,
+}
+
+// Nothing is pinned structurally.
+impl<W, const ALIGN: usize, const N: usize> Unpin for ReceiverStream<W, ALIGN, N>
+where
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+}
+
+impl<W, const ALIGN: usize> Receiver<W, ALIGN>
+where
+    W: WaitForNotify + PollWaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Turn this receiver into a [`Stream`] of messages of at most `N` bytes. Like
+    /// [`poll_recv`][Self::poll_recv], this needs a waiter that can be polled.
+    pub fn into_stream<const N: usize>(self) -> ReceiverStream<W, ALIGN, N> {
+        ReceiverStream { receiver: self }
+    }
+}
+
+impl<W, const ALIGN: usize, const N: usize> ReceiverStream<W, ALIGN, N>
+where
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Get the receiver back.
+    pub fn into_inner(self) -> Receiver<W, ALIGN> {
+        self.receiver
+    }
+}
+
+impl<W, const ALIGN: usize, const N: usize> Stream for ReceiverStream<W, ALIGN, N>
+where
+    W: WaitForNotify + PollWaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    type Item = Result<heapless::Vec<u8, N>, RecvError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let receiver = &mut self.get_mut().receiver;
+        let mut buf = [0; N];
+        let item = match receiver.poll_recv(cx, &mut buf) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(RecvError::PeerClosed)) => return Poll::Ready(None),
+            Poll::Ready(Err(e @ RecvError::MessageTooBig { .. })) => {
+                // Otherwise the message stays at the head of the ring and every poll fails.
+                if let Err(e) = receiver.discard_next() {
+                    return Poll::Ready(Some(Err(e)));
+                }
+                Err(e)
+            }
+            Poll::Ready(r) => r.map(|n| {
+                let mut msg = heapless::Vec::new();
+                // `n` can't be bigger than the buffer.
+                let _ = msg.extend_from_slice(&buf[..n]);
+                msg
+            }),
+        };
+        Poll::Ready(Some(item))
+    }
+}
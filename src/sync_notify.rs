@@ -0,0 +1,126 @@
+//! An in-process [`Notifier`] and [`WaitForNotify`] for running both sides of a channel on the
+//! host, e.g. in tests and simulations, without depending on a particular async runtime.
+
+extern crate std;
+
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use std::boxed::Box;
+
+use atomic_waker::AtomicWaker;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    IcMsg, IcMsgBuffer, InitError, MemoryConfig, Notifier, PollWaitForNotify, WaitForNotify,
+};
+
+/// A one-way notification channel. One side notifies through `&Channel` as a [`Notifier`], the
+/// other side waits through `&Channel` as a [`WaitForNotify`].
+///
+/// Notifications latch: a notification sent while nobody is waiting completes the next wait
+/// immediately. Several notifications before a wait are merged into one.
+#[derive(Debug, Default)]
+pub struct Channel {
+    waker: AtomicWaker,
+    notified: AtomicBool,
+}
+
+impl Channel {
+    pub const fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            notified: AtomicBool::new(false),
+        }
+    }
+
+    /// Notify the waiting side, or the next one to wait if nobody is waiting.
+    pub fn notify(&self) {
+        self.notified.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Wait for a notification.
+    pub async fn wait(&self) {
+        poll_fn(|cx| self.poll_wait(cx)).await
+    }
+
+    /// Poll for a notification, registering `cx`'s waker if there is none.
+    pub fn poll_wait(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.notified.swap(false, Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        self.waker.register(cx.waker());
+        // Check again in case `notify` was called before the waker was registered.
+        if self.notified.swap(false, Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Notifier for &'_ Channel {
+    fn notify(&mut self) {
+        Channel::notify(self)
+    }
+}
+
+impl WaitForNotify for &'_ Channel {
+    fn wait_for_notify(&mut self) -> impl Future<Output = ()> {
+        self.wait()
+    }
+}
+
+impl PollWaitForNotify for &'_ Channel {
+    fn poll_wait_for_notify(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.poll_wait(cx)
+    }
+}
+
+/// One side of a channel created by [`pair`].
+pub type LocalIcMsg<const ALIGN: usize> = IcMsg<&'static Channel, &'static Channel, ALIGN>;
+
+/// Create two bonded [`IcMsg`] channels connected to each other through in-memory regions with
+/// `DATA` bytes of data each.
+///
+/// The regions and notification channels are leaked, so this is meant for tests and simulations
+/// rather than for long-running programs that create many pairs.
+pub async fn pair<const DATA: usize, const ALIGN: usize>()
+-> Result<(LocalIcMsg<ALIGN>, LocalIcMsg<ALIGN>), InitError>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    let region_1 = Box::leak(Box::new(IcMsgBuffer::<DATA, ALIGN>::new()));
+    let region_2 = Box::leak(Box::new(IcMsgBuffer::<DATA, ALIGN>::new()));
+    let notify_1: &'static Channel = Box::leak(Box::new(Channel::new()));
+    let notify_2: &'static Channel = Box::leak(Box::new(Channel::new()));
+
+    let config_1 = MemoryConfig::from_buffers(region_1, region_2);
+    let config_2 = MemoryConfig {
+        send_region: config_1.config().recv_region,
+        recv_region: config_1.config().send_region,
+        ..config_1.config()
+    };
+
+    // Notifications latch, so bonding never has to retry and the delay never has to complete.
+    let (icmsg_1, icmsg_2) = embassy_futures::join::join(
+        IcMsg::init_checked(config_1, notify_1, notify_2, NeverDelay),
+        // SAFETY: `config_2` is `config_1` with the regions swapped, so it is as valid as
+        // `config_1`, and the other side has the matching view of the same two regions.
+        async { unsafe { IcMsg::init(config_2, notify_2, notify_1, NeverDelay).await } },
+    )
+    .await;
+    Ok((icmsg_1?, icmsg_2?))
+}
+
+/// A delay that never completes.
+pub(crate) struct NeverDelay;
+
+impl DelayNs for NeverDelay {
+    async fn delay_ns(&mut self, _ns: u32) {
+        core::future::pending().await
+    }
+}
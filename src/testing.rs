@@ -0,0 +1,135 @@
+//! Connected channels in process memory, for unit testing protocols built on top of ICMsg
+//! without any pointer math.
+//!
+//! Unlike [`sync_notify::pair`][crate::sync_notify::pair], which leaks its regions so that the
+//! channels can be `'static`, the regions of a [`Loopback`] are freed when it is dropped, and the
+//! channels borrow it.
+
+extern crate std;
+
+use core::ptr::NonNull;
+use std::boxed::Box;
+
+use crate::{
+    BondingConfig, IcMsg, IcMsgBuffer, InitError, MemoryConfig,
+    sync_notify::{Channel, NeverDelay},
+};
+
+/// One side of a channel created by [`Loopback::connect`].
+pub type LoopbackIcMsg<'a, const ALIGN: usize> = IcMsg<&'a Channel, &'a Channel, ALIGN>;
+
+/// Two regions with `DATA` bytes of data each and the notification channels between the two
+/// sides. Created by [`loopback`].
+pub struct Loopback<const DATA: usize, const ALIGN: usize>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    // Owned, and freed on drop. Kept as pointers since both sides of the channel alias them.
+    regions: [NonNull<IcMsgBuffer<DATA, ALIGN>>; 2],
+    notify: [Channel; 2],
+}
+
+/// Allocate the regions for a pair of channels. Call [`Loopback::connect`] to create them.
+pub fn loopback<const DATA: usize, const ALIGN: usize>() -> Loopback<DATA, ALIGN>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    let region = || NonNull::from(Box::leak(Box::new(IcMsgBuffer::<DATA, ALIGN>::new())));
+    Loopback {
+        regions: [region(), region()],
+        notify: [Channel::new(), Channel::new()],
+    }
+}
+
+impl<const DATA: usize, const ALIGN: usize> Loopback<DATA, ALIGN>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Create two bonded channels connected to each other, one for each side.
+    ///
+    /// They borrow the loopback mutably, so only one pair uses the regions at a time, and the
+    /// regions outlive it. Calling this again after the previous pair is dropped starts over.
+    pub fn connect(
+        &mut self,
+    ) -> Result<(LoopbackIcMsg<'_, ALIGN>, LoopbackIcMsg<'_, ALIGN>), InitError> {
+        let bonding_config = BondingConfig::default();
+        let (icmsg_1, icmsg_2) = self.connect_with(bonding_config, bonding_config);
+        Ok((icmsg_1?, icmsg_2?))
+    }
+
+    /// Like [`connect`][Self::connect], but each side bonds with its own [`BondingConfig`], and
+    /// each side's result is returned, e.g. to test how bonding fails.
+    ///
+    /// Neither side times out or retries, so a side that fails before it has sent its bonding
+    /// message, e.g. because the message does not fit, leaves the other side waiting forever.
+    pub fn connect_with(
+        &mut self,
+        bonding_config_1: BondingConfig,
+        bonding_config_2: BondingConfig,
+    ) -> (
+        Result<LoopbackIcMsg<'_, ALIGN>, InitError>,
+        Result<LoopbackIcMsg<'_, ALIGN>, InitError>,
+    ) {
+        // Start from scratch, since a previous pair may have left messages in the regions and
+        // notifications in the channels, e.g. from closing on drop. Otherwise one side could see
+        // them before the other side has reset its region.
+        for region in self.regions {
+            // SAFETY: The regions are valid and no channel uses them, and all zeroes is a valid
+            // `IcMsgBuffer`.
+            unsafe { region.write_bytes(0, 1) };
+        }
+        self.notify = [Channel::new(), Channel::new()];
+        let (config_1, config_2) = self.memory_configs();
+        let [notify_1, notify_2] = &self.notify;
+
+        // Notifications latch, so bonding never has to retry and the delay never has to complete.
+        // SAFETY: The regions are aligned, have `DATA` bytes of data and are only freed when the
+        // loopback is dropped, which the borrow of `self` prevents while the channels exist. Each
+        // side has the matching view of the two regions.
+        embassy_futures::block_on(embassy_futures::join::join(
+            unsafe {
+                IcMsg::init_with_bonding_config(
+                    config_1,
+                    bonding_config_1,
+                    notify_1,
+                    notify_2,
+                    NeverDelay,
+                )
+            },
+            unsafe {
+                IcMsg::init_with_bonding_config(
+                    config_2,
+                    bonding_config_2,
+                    notify_2,
+                    notify_1,
+                    NeverDelay,
+                )
+            },
+        ))
+    }
+
+    /// The memory configs of the two sides, for creating the channels by other means than
+    /// [`connect`][Self::connect], e.g. with other notifiers or as low-level transports.
+    ///
+    /// The regions are valid until the loopback is dropped, but nothing keeps the channels from
+    /// outliving it, and each [`connect`][Self::connect] clears the regions.
+    pub fn memory_configs(&self) -> (MemoryConfig, MemoryConfig) {
+        let [region_1, region_2] = self.regions.map(NonNull::cast);
+        let config = |send_region, recv_region| {
+            MemoryConfig::new(send_region, recv_region, DATA as u32, DATA as u32)
+        };
+        (config(region_1, region_2), config(region_2, region_1))
+    }
+}
+
+impl<const DATA: usize, const ALIGN: usize> Drop for Loopback<DATA, ALIGN>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    fn drop(&mut self) {
+        for region in self.regions {
+            // SAFETY: Allocated by `loopback`, and no channel borrows the loopback anymore.
+            drop(unsafe { Box::from_raw(region.as_ptr()) });
+        }
+    }
+}
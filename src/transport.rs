@@ -3,22 +3,36 @@
 //! This provides low-level send and receive primitives and does not include the initial [bonding][1].
 //!
 //! [1]: https://docs.zephyrproject.org/latest/services/ipc/ipc_service/backends/ipc_service_icmsg.html#bonding
+//!
+//! The shared `rd_idx` and `wr_idx` are accessed with `Acquire`/`Release` ordering. With the
+//! `strict-ordering` feature, every access is `SeqCst` instead. This is meant for ruling out
+//! ordering as the cause when debugging problems between cores, at the cost of performance, and
+//! should be off in production builds.
 
-use core::{mem::MaybeUninit, sync::atomic::Ordering};
+use core::{mem::MaybeUninit, ops::ControlFlow, pin::pin, ptr::NonNull, sync::atomic::Ordering};
 
+use crate::WaitForNotify;
+use crate::protocol::{HEADER_SIZE, capacity, padded_len_with};
 use integer::{BeU16, LeAtomicU32};
 
 /// The low-level ICMsg transport.
-pub struct IcMsgTransport<M, const ALIGN: usize>
+///
+/// `PAD` is the granularity messages are padded to, and so the step between the positions of
+/// packets in the ring. It must be 1, 2 or 4. The reference implementation uses 4, and a smaller
+/// value wastes less space on streams of tiny messages. Like `ALIGN`, it is part of the format of
+/// the shared memory, so both cores must agree on it. The types in the crate root, including
+/// bonding, always use 4.
+pub struct IcMsgTransport<M, const ALIGN: usize, B = NoBarrier, const PAD: usize = 4>
 where
     M: Notifier,
+    B: Barrier,
     elain::Align<ALIGN>: elain::Alignment,
 {
-    sender: Sender<M, ALIGN>,
-    receiver: Receiver<ALIGN>,
+    sender: Sender<M, ALIGN, NoObserver, B, PAD>,
+    receiver: Receiver<ALIGN, NoObserver, B, PAD>,
 }
 
-impl<M, const ALIGN: usize> IcMsgTransport<M, ALIGN>
+impl<M, const ALIGN: usize, const PAD: usize> IcMsgTransport<M, ALIGN, NoBarrier, PAD>
 where
     M: Notifier,
     elain::Align<ALIGN>: elain::Alignment,
@@ -34,36 +48,104 @@ where
     ///
     /// The parameters must follow the requirements detailed in [`MemoryConfig`][`super::MemoryConfig`].
     pub unsafe fn new(
-        send_region: *mut (),
-        recv_region: *mut (),
+        send_region: NonNull<()>,
+        recv_region: NonNull<()>,
         send_buffer_len: u32,
         recv_buffer_len: u32,
         mbox: M,
     ) -> Self {
+        let sender = unsafe { Sender::new(send_region, send_buffer_len, mbox) };
+        let receiver = unsafe { Receiver::new(recv_region, recv_buffer_len) };
+        Self { sender, receiver }
+    }
+
+    /// Create a new `IcMsgTransport` that resumes from the indices already in shared memory
+    /// instead of resetting them. This is for when this core was reset while the other side kept
+    /// running: the other side never notices, and messages that were in flight in either
+    /// direction are kept.
+    ///
+    /// Returns `None`, without writing to either region, if the send region's `wr_idx` or the
+    /// receive region's `rd_idx` is not a multiple of `PAD` less than the buffer length, so it
+    /// can't be where this end left off.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for [`new`][Self::new] apply. In addition, both regions must still
+    /// hold the state of a channel that was in use before the reset, with the same parameters.
+    pub unsafe fn new_preserve(
+        send_region: NonNull<()>,
+        recv_region: NonNull<()>,
+        send_buffer_len: u32,
+        recv_buffer_len: u32,
+        mbox: M,
+    ) -> Option<Self> {
+        const { assert!(PAD == 1 || PAD == 2 || PAD == 4, "PAD must be 1, 2 or 4") }
         let send_region = send_region.cast::<SharedMemoryRegionHeader<ALIGN>>();
         let recv_region = recv_region.cast::<SharedMemoryRegionHeader<ALIGN>>();
-        debug_assert!(send_buffer_len % 4 == 0);
-        debug_assert!(recv_buffer_len % 4 == 0);
+        debug_assert!(send_buffer_len.is_multiple_of(4));
+        debug_assert!(recv_buffer_len.is_multiple_of(4));
         debug_assert!(send_region.is_aligned());
         debug_assert!(recv_region.is_aligned());
 
-        unsafe {
-            (&raw mut (*send_region).wr_idx.value).write(LeAtomicU32::new(0));
-            (&raw mut (*send_region).rd_idx.value).write(LeAtomicU32::new(0));
+        let send_wr_idx = unsafe { SharedMemoryRegionHeader::wr_idx(send_region.as_ptr()) }
+            .load(Ordering::Acquire);
+        let recv_rd_idx = unsafe { SharedMemoryRegionHeader::rd_idx(recv_region.as_ptr()) }
+            .load(Ordering::Acquire);
+        if send_wr_idx >= send_buffer_len
+            || !send_wr_idx.is_multiple_of(PAD as u32)
+            || recv_rd_idx >= recv_buffer_len
+            || !recv_rd_idx.is_multiple_of(PAD as u32)
+        {
+            return None;
         }
 
         let sender = Sender {
             send_region,
             send_buffer_len,
             mbox,
-            send_wr_idx: 0,
+            send_wr_idx,
+            crc: false,
+            seq: None,
+            #[cfg(feature = "stats")]
+            stats: Stats::default(),
+            observer: NoObserver,
+            barrier: NoBarrier,
         };
         let receiver = Receiver {
             recv_region,
             recv_buffer_len,
-            recv_rd_idx: 0,
+            recv_rd_idx,
+            recv_published_rd_idx: recv_rd_idx,
+            // Nothing has been seen as unread yet, so any wr_idx counts as moving forward.
+            recv_last_wr_idx: recv_rd_idx,
+            desync: false,
+            crc: false,
+            sequence: false,
+            expected_seq: None,
+            #[cfg(feature = "stats")]
+            stats: Stats::default(),
+            observer: NoObserver,
+            barrier: NoBarrier,
         };
-        Self { sender, receiver }
+        Some(Self { sender, receiver })
+    }
+}
+
+impl<M, const ALIGN: usize, B, const PAD: usize> IcMsgTransport<M, ALIGN, B, PAD>
+where
+    M: Notifier,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Replace the [`Barrier`] of both halves. See [`Sender::with_barrier`].
+    pub fn with_barrier<B2>(self, barrier: B2) -> IcMsgTransport<M, ALIGN, B2, PAD>
+    where
+        B2: Barrier + Clone,
+    {
+        IcMsgTransport {
+            sender: self.sender.with_barrier(barrier.clone()),
+            receiver: self.receiver.with_barrier(barrier),
+        }
     }
 
     /// Notify the other end.
@@ -71,106 +153,806 @@ where
         self.sender.notify()
     }
 
+    /// Enable or disable CRC checking in both directions. See [`Sender::set_crc`].
+    pub fn set_crc(&mut self, enabled: bool) {
+        self.sender.set_crc(enabled);
+        self.receiver.set_crc(enabled);
+    }
+
+    /// Enable or disable sequence numbers in both directions. See [`Sender::set_sequence`].
+    pub fn set_sequence(&mut self, enabled: bool) {
+        self.sender.set_sequence(enabled);
+        self.receiver.set_sequence(enabled);
+    }
+
     pub fn send(&mut self, msg: &[u8]) -> Result<(), SendError> {
         self.sender.send(msg)
     }
 
+    pub fn send_vectored(&mut self, parts: &[&[u8]]) -> Result<(), SendError> {
+        self.sender.send_vectored(parts)
+    }
+
     pub fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, RecvError> {
         self.receiver.try_recv(msg)
     }
 
-    pub fn split(self) -> (Sender<M, ALIGN>, Receiver<ALIGN>) {
+    pub fn split(
+        self,
+    ) -> (
+        Sender<M, ALIGN, NoObserver, B, PAD>,
+        Receiver<ALIGN, NoObserver, B, PAD>,
+    ) {
         (self.sender, self.receiver)
     }
 
-    pub fn split_mut(&mut self) -> (&mut Sender<M, ALIGN>, &mut Receiver<ALIGN>) {
+    pub fn split_mut(
+        &mut self,
+    ) -> (
+        &mut Sender<M, ALIGN, NoObserver, B, PAD>,
+        &mut Receiver<ALIGN, NoObserver, B, PAD>,
+    ) {
         (&mut self.sender, &mut self.receiver)
     }
 }
 
-/// The receiving half of the low-level ICMsg transport.
-pub struct Receiver<const ALIGN: usize>
+/// The receiving half of the low-level ICMsg transport. See [`IcMsgTransport`] for `PAD`.
+pub struct Receiver<const ALIGN: usize, O = NoObserver, B = NoBarrier, const PAD: usize = 4>
 where
+    O: Observer,
+    B: Barrier,
     elain::Align<ALIGN>: elain::Alignment,
 {
-    recv_region: *mut SharedMemoryRegionHeader<ALIGN>,
+    recv_region: NonNull<SharedMemoryRegionHeader<ALIGN>>,
 
     // size of the data field, not including the header. must be a multiple of 4
     recv_buffer_len: u32,
 
     // local copies to prevent the other side from interfering
     recv_rd_idx: u32,
+    // rd_idx as last published to the other side, behind recv_rd_idx during try_recv_many
+    recv_published_rd_idx: u32,
+    // wr_idx as of the last call to try_recv, used to detect the other side going backwards
+    recv_last_wr_idx: u32,
+    // set once the indices are found to be inconsistent, cleared by reset
+    desync: bool,
+    // whether packets are followed by a CRC trailer
+    crc: bool,
+    // whether packet headers carry a sequence number
+    sequence: bool,
+    // the sequence number of the next packet, unknown until one has been received
+    expected_seq: Option<u8>,
+    #[cfg(feature = "stats")]
+    stats: Stats,
+    observer: O,
+    barrier: B,
+}
+
+// SAFETY: The shared memory region is designed to be accessed from a different execution context
+// than the one that created it, and the receiver is the only one on this side that touches the
+// receive region. Everything it accesses through the pointer is either an atomic or data that the
+// other side does not write until it is released by updating `rd_idx`.
+unsafe impl<const ALIGN: usize, O, B, const PAD: usize> Send for Receiver<ALIGN, O, B, PAD>
+where
+    O: Observer + Send,
+    B: Barrier + Send,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+}
+
+impl<const ALIGN: usize, const PAD: usize> Receiver<ALIGN, NoObserver, NoBarrier, PAD>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Create a receiver on its own, for a core that only receives. Nothing in shared memory is
+    /// written; the other side's sender initializes the indices of this region.
+    ///
+    /// # Safety
+    ///
+    /// `recv_region` and `recv_buffer_len` must follow the requirements detailed in
+    /// [`MemoryConfig`][`super::MemoryConfig`].
+    pub unsafe fn new(recv_region: NonNull<()>, recv_buffer_len: u32) -> Self {
+        const { assert!(PAD == 1 || PAD == 2 || PAD == 4, "PAD must be 1, 2 or 4") }
+        let recv_region = recv_region.cast::<SharedMemoryRegionHeader<ALIGN>>();
+        debug_assert!(recv_buffer_len.is_multiple_of(4));
+        debug_assert!(recv_region.is_aligned());
+
+        Receiver {
+            recv_region,
+            recv_buffer_len,
+            recv_rd_idx: 0,
+            recv_published_rd_idx: 0,
+            recv_last_wr_idx: 0,
+            desync: false,
+            crc: false,
+            sequence: false,
+            expected_seq: None,
+            #[cfg(feature = "stats")]
+            stats: Stats::default(),
+            observer: NoObserver,
+            barrier: NoBarrier,
+        }
+    }
 }
 
-impl<const ALIGN: usize> Receiver<ALIGN>
+impl<const ALIGN: usize, O, B, const PAD: usize> Receiver<ALIGN, O, B, PAD>
 where
+    O: Observer,
+    B: Barrier,
     elain::Align<ALIGN>: elain::Alignment,
 {
+    /// Reset the receive ring to empty, as if newly created. Used when bonding again, and to recover
+    /// from [`RecvError::Desync`].
+    ///
+    /// The other side clears its send ring when it bonds again, so any unread messages are
+    /// discarded.
+    pub fn reset(&mut self) {
+        self.recv_rd_idx = 0;
+        self.recv_published_rd_idx = 0;
+        self.recv_last_wr_idx = 0;
+        self.desync = false;
+        self.expected_seq = None;
+        self.shared_rd_idx().store(0, Ordering::Release);
+    }
+
+    /// Check whether the other side has re-initialized the shared memory region, and if so, start
+    /// receiving from the beginning of the buffer again. Returns `true` if a reset was detected.
+    ///
+    /// The other side zeroes both indices of its send region when it creates its transport. Since
+    /// this end is the only other writer of `rd_idx`, a shared `rd_idx` that differs from our local
+    /// copy means the other side has restarted.
+    pub fn detect_peer_reset(&mut self) -> bool {
+        let rd_idx = self.shared_rd_idx().load(Ordering::Acquire);
+        if rd_idx == self.recv_rd_idx {
+            return false;
+        }
+        self.recv_rd_idx = 0;
+        self.recv_published_rd_idx = 0;
+        self.recv_last_wr_idx = 0;
+        self.desync = false;
+        self.expected_seq = None;
+        true
+    }
+
+    /// Expect a CRC trailer after each packet. See [`Sender::set_crc`].
+    ///
+    /// A packet whose CRC does not match fails with [`RecvError::CrcMismatch`] and is left in the
+    /// ring, since a corrupted length means the start of the next packet is not known either.
+    /// Receiving it again retries the check, which helps if the corruption happened while
+    /// reading. Otherwise, [`clear`][Self::clear] drops everything that is pending.
+    pub fn set_crc(&mut self, enabled: bool) {
+        self.crc = enabled;
+    }
+
+    /// Check the sequence number of each packet. See [`Sender::set_sequence`].
+    ///
+    /// There is no expectation for the first packet received after enabling this or after a
+    /// reset. After that, a packet with an unexpected sequence number fails once with
+    /// [`RecvError::SequenceGap`], and receiving again delivers it and continues from its
+    /// sequence number.
+    pub fn set_sequence(&mut self, enabled: bool) {
+        self.sequence = enabled;
+        self.expected_seq = None;
+    }
+
     /// Receive a message. On success, returns the size of the message.
     pub fn try_recv(&mut self, msg: &mut [u8]) -> Result<usize, RecvError> {
+        self.try_recv_uninit(as_uninit(msg))
+    }
+
+    /// Wait for and receive a message, waiting on `waiter` for notifications from the other side.
+    /// On success, returns the size of the message.
+    ///
+    /// This is the loop behind [`crate::Receiver::recv`], for when the transport is used directly
+    /// and the waiter is kept elsewhere. It is cancel safe in the same way.
+    pub async fn recv(
+        &mut self,
+        msg: &mut [u8],
+        waiter: &mut impl WaitForNotify,
+    ) -> Result<usize, RecvError> {
+        recv_with(waiter, || self.try_recv(msg)).await
+    }
+
+    /// Receive a message into a buffer that may be uninitialized, which saves zeroing a large
+    /// buffer up front. On success, returns the size `n` of the message, and the first `n` bytes
+    /// of `msg` are initialized. The rest of `msg` is left untouched, and so is all of it on error.
+    pub fn try_recv_uninit(&mut self, msg: &mut [MaybeUninit<u8>]) -> Result<usize, RecvError> {
+        self.recv_into(msg).map(|(n, _)| n)
+    }
+
+    /// Receive a message along with the flags it was sent with by [`Sender::send_typed`]. On
+    /// success, returns the size of the message and the flags.
+    ///
+    /// The flags live in a header byte that the reference implementation leaves unspecified, so
+    /// they are only meaningful if the other side sets them, and both sides have to agree on what
+    /// they mean.
+    pub fn try_recv_typed(&mut self, msg: &mut [u8]) -> Result<(usize, u8), RecvError> {
+        self.recv_into(as_uninit(msg))
+    }
+
+    fn recv_into(&mut self, msg: &mut [MaybeUninit<u8>]) -> Result<(usize, u8), RecvError> {
+        let packet = self.next_packet()?;
+        if packet.len > msg.len() {
+            return Err(RecvError::MessageTooBig {
+                required: packet.len,
+            });
+        }
+        self.copy_packet_uninit(&packet, 0, &mut msg[..packet.len]);
+        self.consume_packet(&packet);
+        Ok((packet.len, packet.flags))
+    }
+
+    /// Receive all messages that are currently queued, passing each one to `on_msg` after
+    /// copying it into `scratch`. Returns how many were received.
+    ///
+    /// `rd_idx` is only published once, after the last message, so the other side sees the space
+    /// freed all at once. If `on_msg` returns [`ControlFlow::Break`], the remaining messages are
+    /// left queued. If the ring is empty to begin with, this fails with [`RecvError::Empty`].
+    ///
+    /// If a message doesn't fit in `scratch`, or receiving fails partway through, the messages
+    /// already passed to `on_msg` stay received and the error is returned. The message that
+    /// failed is left queued, as with [`try_recv`][Self::try_recv].
+    pub fn try_recv_many(
+        &mut self,
+        scratch: &mut [u8],
+        on_msg: impl FnMut(&[u8]) -> ControlFlow<()>,
+    ) -> Result<usize, RecvError> {
+        self.try_recv_many_until(scratch, |_, _| false, on_msg)
+    }
+
+    /// Like [`try_recv_many`][Self::try_recv_many], but stops before the first message for which
+    /// `stop` returns `true` given its contents and flags, leaving it queued.
+    pub(crate) fn try_recv_many_until(
+        &mut self,
+        scratch: &mut [u8],
+        mut stop: impl FnMut(&[u8], u8) -> bool,
+        mut on_msg: impl FnMut(&[u8]) -> ControlFlow<()>,
+    ) -> Result<usize, RecvError> {
+        let mut count = 0;
+        let r = loop {
+            let packet = match self.next_packet() {
+                Ok(packet) => packet,
+                Err(RecvError::Empty) if count > 0 => break Ok(count),
+                Err(e) => break Err(e),
+            };
+            if packet.len > scratch.len() {
+                break Err(RecvError::MessageTooBig {
+                    required: packet.len,
+                });
+            }
+            let msg = &mut scratch[..packet.len];
+            self.copy_packet(&packet, 0, msg);
+            if stop(msg, packet.flags) {
+                break Ok(count);
+            }
+            self.advance_packet(&packet);
+            count += 1;
+            if on_msg(msg).is_break() {
+                break Ok(count);
+            }
+        };
+        if count > 0 {
+            self.publish_rd_idx();
+        }
+        r
+    }
+
+    /// Receive all messages that were queued when this was called, passing each one to `on_msg`.
+    /// Returns how many were received.
+    ///
+    /// Unlike [`try_recv_many`][Self::try_recv_many], `wr_idx` is loaded and synchronized once for
+    /// the whole batch rather than once per message, and `rd_idx` is published once at the end, so
+    /// the cost of both is shared by a burst of messages. Messages that are contiguous in the ring
+    /// are passed in place, only one that wraps around is copied into `scratch` first. Still, every
+    /// message has to fit in `scratch`, and one that doesn't fails with
+    /// [`RecvError::MessageTooBig`]. If the ring is empty to begin with, this fails with
+    /// [`RecvError::Empty`].
+    ///
+    /// If receiving fails partway through, or `on_msg` panics, the messages already passed to
+    /// `on_msg` stay received and `rd_idx` is published for them. The message that failed, or
+    /// that `on_msg` panicked on, is left queued.
+    pub fn try_recv_batch(
+        &mut self,
+        scratch: &mut [u8],
+        mut on_msg: impl FnMut(&[u8]),
+    ) -> Result<usize, RecvError> {
+        /// Publishes `rd_idx` when dropped, including when unwinding out of `on_msg`.
+        struct Batch<'a, const ALIGN: usize, O: Observer, B: Barrier, const PAD: usize>
+        where
+            elain::Align<ALIGN>: elain::Alignment,
+        {
+            receiver: &'a mut Receiver<ALIGN, O, B, PAD>,
+            count: usize,
+        }
+
+        impl<const ALIGN: usize, O: Observer, B: Barrier, const PAD: usize> Drop
+            for Batch<'_, ALIGN, O, B, PAD>
+        where
+            elain::Align<ALIGN>: elain::Alignment,
+        {
+            fn drop(&mut self) {
+                if self.count > 0 {
+                    self.receiver.publish_rd_idx();
+                }
+            }
+        }
+
+        let wr_idx = self.load_wr_idx()?;
+        let mut batch = Batch {
+            receiver: self,
+            count: 0,
+        };
+        loop {
+            let receiver = &mut *batch.receiver;
+            let packet = match receiver.packet_before(wr_idx) {
+                Ok(packet) => packet,
+                Err(RecvError::Empty) if batch.count > 0 => return Ok(batch.count),
+                Err(e) => return Err(e),
+            };
+            if packet.len > scratch.len() {
+                return Err(RecvError::MessageTooBig {
+                    required: packet.len,
+                });
+            }
+            let tail_size = (receiver.recv_buffer_len - packet.data_idx) as usize;
+            let msg = if packet.len <= tail_size {
+                // SAFETY: The packet is unread, so the other side does not write to it until
+                // rd_idx is published past it.
+                unsafe {
+                    core::slice::from_raw_parts(
+                        receiver.data_ptr().add(packet.data_idx as usize),
+                        packet.len,
+                    )
+                }
+            } else {
+                let msg = &mut scratch[..packet.len];
+                receiver.copy_packet(&packet, 0, msg);
+                msg
+            };
+            on_msg(msg);
+            receiver.advance_packet(&packet);
+            batch.count += 1;
+        }
+    }
+
+    /// Receive a message into the concatenation of `bufs`, filling each slice before moving on to
+    /// the next. On success, returns the size of the message.
+    ///
+    /// This fails with [`RecvError::MessageTooBig`] only if the message doesn't fit in all of
+    /// `bufs` together.
+    pub fn try_recv_vectored(&mut self, bufs: &mut [&mut [u8]]) -> Result<usize, RecvError> {
+        let packet = self.next_packet()?;
+        let capacity = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        if packet.len > capacity {
+            return Err(RecvError::MessageTooBig {
+                required: packet.len,
+            });
+        }
+        let mut offset = 0;
+        for buf in bufs {
+            let n = buf.len().min(packet.len - offset);
+            self.copy_packet(&packet, offset, &mut buf[..n]);
+            offset += n;
+        }
+        self.consume_packet(&packet);
+        Ok(packet.len)
+    }
+
+    /// Receive a message sent by [`Sender::send_fragmented`], reassembling its fragments into
+    /// `msg`. On success, returns the size of the whole message.
+    ///
+    /// `received` tracks how much of the message has been reassembled so far, and must start at 0
+    /// for each message. If the rest of the fragments have not arrived yet, this returns
+    /// [`RecvError::Empty`]; call it again with the same `msg` and `received` once more data has
+    /// arrived. Messages sent without fragmentation are received as a single fragment.
+    ///
+    /// If a fragment does not fit in the rest of `msg`, it stays queued and this returns
+    /// [`RecvError::MessageTooBig`]. Since the total size is not known until the last fragment,
+    /// `required` is only a lower bound while more fragments follow.
+    ///
+    /// The other side has to use this crate too. The reference implementation does not write the
+    /// flags byte of the header, so a stale [`MORE_FRAGMENTS`] bit left there by an earlier
+    /// message would merge unrelated messages. See [`try_recv_typed`][Self::try_recv_typed].
+    pub fn try_recv_reassembled(
+        &mut self,
+        msg: &mut [u8],
+        received: &mut usize,
+    ) -> Result<usize, RecvError> {
+        loop {
+            let packet = self.next_packet()?;
+            let end = *received + packet.len;
+            if end > msg.len() {
+                return Err(RecvError::MessageTooBig { required: end });
+            }
+            self.copy_packet(&packet, 0, &mut msg[*received..end]);
+            self.consume_packet(&packet);
+            *received = end;
+            if packet.flags & MORE_FRAGMENTS == 0 {
+                return Ok(end);
+            }
+        }
+    }
+
+    /// Receive a message, truncating it if it doesn't fit in `msg` instead of failing with
+    /// [`RecvError::MessageTooBig`]. The whole message is consumed either way.
+    ///
+    /// On success, returns the number of bytes copied into `msg` and the number of bytes of the
+    /// message that were dropped.
+    pub fn try_recv_partial(&mut self, msg: &mut [u8]) -> Result<(usize, usize), RecvError> {
+        let packet = self.next_packet()?;
+        let n = packet.len.min(msg.len());
+        self.copy_packet(&packet, 0, &mut msg[..n]);
+        self.consume_packet(&packet);
+        Ok((n, packet.len - n))
+    }
+
+    /// Return the size of the next message without receiving it.
+    ///
+    /// A following [`try_recv`][Self::try_recv] receives the same message.
+    pub fn peek_len(&mut self) -> Result<usize, RecvError> {
+        self.next_packet().map(|packet| packet.len)
+    }
+
+    /// Skip the next message without copying it, e.g. after [`RecvError::MessageTooBig`]. On
+    /// success, returns the size of the skipped message.
+    pub fn discard_next(&mut self) -> Result<usize, RecvError> {
+        let packet = self.next_packet()?;
+        self.consume_packet(&packet);
+        Ok(packet.len)
+    }
+
+    /// Discard all messages waiting to be received without copying them. Returns the number of
+    /// messages discarded.
+    ///
+    /// If the other side has published an invalid `wr_idx`, or the receiver is in the
+    /// [`RecvError::Desync`] state, nothing is discarded and this returns 0.
+    pub fn clear(&mut self) -> usize {
         // TODO invalidate dcache
-        let wr_idx = unsafe { (*self.recv_region).wr_idx.value.load(Ordering::Acquire) };
+        let wr_idx = self.shared_wr_idx().load(Ordering::Acquire);
+        self.barrier.sync();
+        if self.desync || wr_idx >= self.recv_buffer_len || !wr_idx.is_multiple_of(PAD as u32) {
+            return 0;
+        }
+
+        let mut count = 0;
+        let mut rd_idx = self.recv_rd_idx;
+        while rd_idx != wr_idx {
+            let header = self.read_header(rd_idx);
+            let len = header.len.value() as u32;
+            let packet_len = (padded_len_with(len as usize, PAD)
+                + HEADER_SIZE
+                + self.trailer_len(header.flags)) as u32;
+            let unread = ring_distance(rd_idx, wr_idx, self.recv_buffer_len);
+            if packet_len > unread {
+                // An invalid packet, the rest is discarded without being counted.
+                break;
+            }
+            rd_idx = ring_add(rd_idx, packet_len, self.recv_buffer_len);
+            count += 1;
+            #[cfg(feature = "stats")]
+            {
+                self.stats.messages_recv = self.stats.messages_recv.wrapping_add(1);
+                self.stats.bytes_recv = self.stats.bytes_recv.wrapping_add(len);
+            }
+            self.observer.on_recv(len as usize);
+        }
+
+        self.recv_rd_idx = wr_idx;
+        self.recv_published_rd_idx = wr_idx;
+        self.recv_last_wr_idx = wr_idx;
+        self.shared_rd_idx().store(wr_idx, Ordering::Release);
+        count
+    }
+
+    /// Whether there are no messages waiting to be received.
+    pub fn is_empty(&self) -> bool {
+        self.pending_bytes() == 0
+    }
+
+    /// The number of bytes waiting to be received, including packet headers and padding.
+    ///
+    /// If the other side has published an invalid `wr_idx`, this returns 0.
+    pub fn pending_bytes(&self) -> usize {
+        let wr_idx = self.shared_wr_idx().load(Ordering::Acquire);
+        if wr_idx >= self.recv_buffer_len || !wr_idx.is_multiple_of(PAD as u32) {
+            return 0;
+        }
+        self.unread(wr_idx) as usize
+    }
+
+    /// Counters of what has been received so far.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// The most bytes, including packet headers and padding, that were waiting in the ring when
+    /// this side checked it for messages.
+    #[cfg(feature = "stats")]
+    pub fn high_water_mark(&self) -> usize {
+        self.stats.high_water_mark as usize
+    }
+
+    /// [`high_water_mark`][Self::high_water_mark] as a percentage of the capacity of the ring.
+    #[cfg(feature = "stats")]
+    pub fn high_water_mark_percent(&self) -> u8 {
+        percent(
+            self.high_water_mark(),
+            capacity(self.recv_buffer_len as usize),
+        )
+    }
+
+    /// The start of the receive region and the length of its data field, as passed to
+    /// [`IcMsgTransport::new`].
+    pub fn region(&self) -> (NonNull<()>, u32) {
+        (self.recv_region.cast(), self.recv_buffer_len)
+    }
+
+    /// Replace the [`Observer`] that is called on receive events.
+    pub fn with_observer<O2: Observer>(self, observer: O2) -> Receiver<ALIGN, O2, B, PAD> {
+        Receiver {
+            recv_region: self.recv_region,
+            recv_buffer_len: self.recv_buffer_len,
+            recv_rd_idx: self.recv_rd_idx,
+            recv_published_rd_idx: self.recv_published_rd_idx,
+            recv_last_wr_idx: self.recv_last_wr_idx,
+            desync: self.desync,
+            crc: self.crc,
+            sequence: self.sequence,
+            expected_seq: self.expected_seq,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+            observer,
+            barrier: self.barrier,
+        }
+    }
+
+    /// Replace the [`Barrier`] that is run after `wr_idx` is loaded. See [`Sender::with_barrier`].
+    pub fn with_barrier<B2: Barrier>(self, barrier: B2) -> Receiver<ALIGN, O, B2, PAD> {
+        Receiver {
+            recv_region: self.recv_region,
+            recv_buffer_len: self.recv_buffer_len,
+            recv_rd_idx: self.recv_rd_idx,
+            recv_published_rd_idx: self.recv_published_rd_idx,
+            recv_last_wr_idx: self.recv_last_wr_idx,
+            desync: self.desync,
+            crc: self.crc,
+            sequence: self.sequence,
+            expected_seq: self.expected_seq,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+            observer: self.observer,
+            barrier,
+        }
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Find the next unread packet without consuming it.
+    pub(crate) fn next_packet(&mut self) -> Result<Packet, RecvError> {
+        let wr_idx = self.load_wr_idx()?;
+        self.packet_before(wr_idx)
+    }
+
+    /// Load and check the `wr_idx` published by the other side.
+    fn load_wr_idx(&mut self) -> Result<u32, RecvError> {
+        // TODO invalidate dcache, from rd_idx to wr_idx, which may wrap around
+        if self.desync {
+            return Err(RecvError::Desync);
+        }
+        let wr_idx = self.shared_wr_idx().load(Ordering::Acquire);
+        self.barrier.sync();
+        if wr_idx >= self.recv_buffer_len || !wr_idx.is_multiple_of(PAD as u32) {
+            return Err(self.invalid(RecvError::InvalidState));
+        }
+        let shared_rd_idx = self.shared_rd_idx().load(Ordering::Relaxed);
+        // The other side may only ever add data, so the amount of unread data can't shrink unless
+        // it has restarted. It also zeroes rd_idx, which only we write otherwise, when it restarts.
+        if shared_rd_idx != self.recv_published_rd_idx
+            || self.unread(wr_idx) < self.unread(self.recv_last_wr_idx)
+        {
+            self.desync = true;
+            return Err(self.invalid(RecvError::Desync));
+        }
+        self.recv_last_wr_idx = wr_idx;
+        #[cfg(feature = "stats")]
+        {
+            self.stats.high_water_mark = self.stats.high_water_mark.max(self.unread(wr_idx));
+        }
+        Ok(wr_idx)
+    }
+
+    /// Find the next unread packet before `wr_idx`, as returned by
+    /// [`load_wr_idx`][Self::load_wr_idx].
+    fn packet_before(&mut self, wr_idx: u32) -> Result<Packet, RecvError> {
         let mut rd_idx = self.recv_rd_idx;
         if wr_idx == rd_idx {
             return Err(RecvError::Empty);
         }
 
-        unsafe {
-            let data_ptr = self
-                .recv_region
-                .cast::<u8>()
-                .add(size_of::<SharedMemoryRegionHeader<ALIGN>>());
-            // Packets are always padded to 4 bytes, and the recv buffer length is a multiple of 4,
-            // therefore it is always valid to read 4 bytes at rd_idx.
-            let header = data_ptr.add(rd_idx as usize).cast::<PacketHeader>().read();
-            rd_idx += 4;
-            if rd_idx >= self.recv_buffer_len {
-                rd_idx = 0;
-            }
+        let header = self.read_header(rd_idx);
+        rd_idx = ring_add(rd_idx, HEADER_SIZE as u32, self.recv_buffer_len);
 
-            let msg_len = header.len.value() as usize;
-            if msg_len > msg.len() {
-                return Err(RecvError::MessageTooBig);
+        let len = header.len.value() as usize;
+        let padded_len = padded_len_with(len, PAD);
+        if (padded_len + HEADER_SIZE + self.trailer_len(header.flags)) as u32 > self.unread(wr_idx)
+        {
+            return Err(self.invalid(RecvError::InvalidMessage));
+        }
+        let control = header.flags & CONTROL != 0;
+        if self.crc && !control {
+            let trailer_idx = ring_add(rd_idx, padded_len as u32, self.recv_buffer_len);
+            // SAFETY: The trailer is part of the unread packet.
+            let trailer =
+                unsafe { ring_read::<u32>(self.data_ptr(), self.recv_buffer_len, trailer_idx) };
+            let data_ptr = self.data_ptr();
+            // SAFETY: The other side always writes the sequence byte if CRC checking is enabled.
+            let seq = unsafe { header.seq.assume_init() };
+            let crc = packet_crc(
+                header.flags,
+                seq,
+                data_ptr,
+                self.recv_buffer_len,
+                rd_idx,
+                len,
+            );
+            if u32::from_le(trailer) != crc {
+                return Err(self.invalid(RecvError::CrcMismatch));
             }
-            if msg_len as u32 > self.recv_buffer_len {
-                return Err(RecvError::InvalidMessage);
+        }
+
+        let mut seq = 0;
+        if self.sequence && !control {
+            // SAFETY: The other side writes the sequence number if both sides enabled them.
+            seq = unsafe { header.seq.assume_init() };
+            if let Some(expected) = self.expected_seq
+                && seq != expected
+            {
+                self.expected_seq = Some(seq);
+                return Err(RecvError::SequenceGap { expected, got: seq });
             }
+        }
 
-            let tail_size = (self.recv_buffer_len - rd_idx) as usize;
-            if msg_len > tail_size {
-                let (p1, p2) = msg[..msg_len].split_at_mut(tail_size);
+        Ok(Packet {
+            data_idx: rd_idx,
+            len,
+            flags: header.flags,
+            seq,
+        })
+    }
+
+    /// Copy `dst.len()` bytes of the packet's payload starting at `offset` into `dst`.
+    pub(crate) fn copy_packet(&self, packet: &Packet, offset: usize, dst: &mut [u8]) {
+        self.copy_packet_uninit(packet, offset, as_uninit(dst));
+    }
+
+    /// Like [`copy_packet`][Self::copy_packet], initializing all of `dst`.
+    fn copy_packet_uninit(&self, packet: &Packet, offset: usize, dst: &mut [MaybeUninit<u8>]) {
+        debug_assert!(offset + dst.len() <= packet.len);
+        let idx = ring_add(packet.data_idx, offset as u32, self.recv_buffer_len) as usize;
+
+        unsafe {
+            let data_ptr = self.data_ptr();
+            let tail_size = self.recv_buffer_len as usize - idx;
+            if dst.len() > tail_size {
+                let (p1, p2) = dst.split_at_mut(tail_size);
                 data_ptr
-                    .add(rd_idx as usize)
-                    .copy_to_nonoverlapping(p1.as_mut_ptr(), p1.len());
-                data_ptr.copy_to_nonoverlapping(p2.as_mut_ptr(), p2.len());
+                    .add(idx)
+                    .copy_to_nonoverlapping(p1.as_mut_ptr().cast(), p1.len());
+                data_ptr.copy_to_nonoverlapping(p2.as_mut_ptr().cast(), p2.len());
             } else {
                 data_ptr
-                    .add(rd_idx as usize)
-                    .copy_to_nonoverlapping(msg.as_mut_ptr(), msg_len);
+                    .add(idx)
+                    .copy_to_nonoverlapping(dst.as_mut_ptr().cast(), dst.len());
             }
+        }
+    }
 
-            let padded_msg_len = msg_len + (4 - msg_len % 4) % 4;
-            rd_idx += padded_msg_len as u32;
-            if rd_idx >= self.recv_buffer_len {
-                rd_idx -= self.recv_buffer_len;
-            }
-            self.recv_rd_idx = rd_idx;
-            (*self.recv_region)
-                .rd_idx
-                .value
-                .store(rd_idx, Ordering::Release);
-            Ok(msg_len)
+    /// Mark the packet as read, allowing the other side to reuse its space.
+    pub(crate) fn consume_packet(&mut self, packet: &Packet) {
+        self.advance_packet(packet);
+        self.publish_rd_idx();
+    }
+
+    /// Move the local `rd_idx` past the packet, without publishing it.
+    fn advance_packet(&mut self, packet: &Packet) {
+        let padded_len = padded_len_with(packet.len, PAD);
+        let packet_end = (padded_len + self.trailer_len(packet.flags)) as u32;
+        self.recv_rd_idx = ring_add(packet.data_idx, packet_end, self.recv_buffer_len);
+        if self.sequence && packet.flags & CONTROL == 0 {
+            self.expected_seq = Some(packet.seq.wrapping_add(1));
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.stats.messages_recv = self.stats.messages_recv.wrapping_add(1);
+            self.stats.bytes_recv = self.stats.bytes_recv.wrapping_add(packet.len as u32);
+        }
+        self.observer.on_recv(packet.len);
+    }
+
+    /// Let the other side reuse the space of everything read up to `recv_rd_idx`.
+    fn publish_rd_idx(&mut self) {
+        self.recv_published_rd_idx = self.recv_rd_idx;
+        self.shared_rd_idx()
+            .store(self.recv_rd_idx, Ordering::Release);
+    }
+
+    /// Count an error caused by the other side's state in the stats.
+    fn invalid(&mut self, e: RecvError) -> RecvError {
+        #[cfg(feature = "stats")]
+        {
+            self.stats.recv_invalid = self.stats.recv_invalid.wrapping_add(1);
+        }
+        e
+    }
+
+    /// The size of the trailer after a packet sent with `flags`.
+    fn trailer_len(&self, flags: u8) -> usize {
+        if self.crc && flags & CONTROL == 0 {
+            size_of::<u32>()
+        } else {
+            0
+        }
+    }
+
+    /// Read the header of the unread packet at `rd_idx`.
+    fn read_header(&self, rd_idx: u32) -> PacketHeader {
+        // SAFETY: The other side does not write to unread packets. The length is always written.
+        // The reference implementation does not write the flags, which then hold whatever an
+        // earlier lap of the ring left there, but any byte is a valid `u8`. The sequence number is
+        // `MaybeUninit`.
+        unsafe { ring_read(self.data_ptr(), self.recv_buffer_len, rd_idx) }
+    }
+
+    fn shared_rd_idx(&self) -> &LeAtomicU32 {
+        // SAFETY: The region was initialized by the constructor and outlives the receiver.
+        unsafe { SharedMemoryRegionHeader::rd_idx(self.recv_region.as_ptr()) }
+    }
+
+    fn shared_wr_idx(&self) -> &LeAtomicU32 {
+        // SAFETY: See `shared_rd_idx`.
+        unsafe { SharedMemoryRegionHeader::wr_idx(self.recv_region.as_ptr()) }
+    }
+
+    fn data_ptr(&self) -> *mut u8 {
+        unsafe {
+            self.recv_region
+                .cast::<u8>()
+                .as_ptr()
+                .add(size_of::<SharedMemoryRegionHeader<ALIGN>>())
         }
     }
+
+    /// The number of unread bytes in the ring if the other side's write index is `wr_idx`.
+    fn unread(&self, wr_idx: u32) -> u32 {
+        ring_distance(self.recv_rd_idx, wr_idx, self.recv_buffer_len)
+    }
+}
+
+/// The location of an unread packet in the receive ring.
+pub(crate) struct Packet {
+    // index of the first byte of the payload
+    data_idx: u32,
+    pub(crate) len: usize,
+    pub(crate) flags: u8,
+    seq: u8,
 }
 
-/// The sending half of the low-level ICMsg transport.
-pub struct Sender<M, const ALIGN: usize>
+/// The sending half of the low-level ICMsg transport. See [`IcMsgTransport`] for `PAD`.
+pub struct Sender<M, const ALIGN: usize, O = NoObserver, B = NoBarrier, const PAD: usize = 4>
 where
     M: Notifier,
+    O: Observer,
+    B: Barrier,
     elain::Align<ALIGN>: elain::Alignment,
 {
-    send_region: *mut SharedMemoryRegionHeader<ALIGN>,
+    send_region: NonNull<SharedMemoryRegionHeader<ALIGN>>,
 
     // size of the data field, not including the header. must be a multiple of 4
     send_buffer_len: u32,
@@ -178,236 +960,2814 @@ where
 
     // local copies to prevent the other side from interfering
     send_wr_idx: u32,
+    // whether packets are followed by a CRC trailer
+    crc: bool,
+    // the sequence number of the next packet, if sequence numbers are enabled
+    seq: Option<u8>,
+    #[cfg(feature = "stats")]
+    stats: Stats,
+    observer: O,
+    barrier: B,
 }
 
-impl<M, const ALIGN: usize> Sender<M, ALIGN>
+// SAFETY: See the impl for `Receiver`. The sender is the only one on this side that touches the
+// send region, and the other side does not read data until it is published by updating `wr_idx`.
+unsafe impl<M, const ALIGN: usize, O, B, const PAD: usize> Send for Sender<M, ALIGN, O, B, PAD>
 where
-    M: Notifier,
+    M: Notifier + Send,
+    O: Observer + Send,
+    B: Barrier + Send,
     elain::Align<ALIGN>: elain::Alignment,
 {
-    /// Send a message.
-    pub fn send(&mut self, msg: &[u8]) -> Result<(), SendError> {
-        let mut wr_idx = self.send_wr_idx;
-        let rd_idx = unsafe { (*self.send_region).rd_idx.value.load(Ordering::Acquire) };
+}
 
-        // The FIFO has one byte less capacity than the data buffer length.
-        let free_space = if rd_idx > wr_idx {
-            rd_idx - wr_idx - 1
-        } else {
-            rd_idx + self.send_buffer_len - wr_idx - 1
-        };
+// Neither half is `Sync`. Everything that touches the ring takes `&mut self`, so sharing a half
+// between threads gains nothing over wrapping it in a mutex, and the single reader and single
+// writer of each index would then have to be argued for every `&self` method as well.
 
-        let padded_msg_len = msg.len() + (4 - msg.len() % 4) % 4;
-        if (free_space as usize) < padded_msg_len + size_of::<PacketHeader>() {
-            return Err(SendError::InsufficientCapacity);
-        }
+impl<M, const ALIGN: usize, const PAD: usize> Sender<M, ALIGN, NoObserver, NoBarrier, PAD>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Create a sender on its own, for a core that only sends. Both indices of the send region
+    /// are zeroed, like [`IcMsgTransport::new`] does.
+    ///
+    /// # Safety
+    ///
+    /// `send_region` and `send_buffer_len` must follow the requirements detailed in
+    /// [`MemoryConfig`][`super::MemoryConfig`].
+    pub unsafe fn new(send_region: NonNull<()>, send_buffer_len: u32, mbox: M) -> Self {
+        const { assert!(PAD == 1 || PAD == 2 || PAD == 4, "PAD must be 1, 2 or 4") }
+        let send_region = send_region.cast::<SharedMemoryRegionHeader<ALIGN>>();
+        debug_assert!(send_buffer_len.is_multiple_of(4));
+        debug_assert!(send_region.is_aligned());
 
         unsafe {
-            let data_ptr = self
-                .send_region
-                .cast::<u8>()
-                .add(size_of::<SharedMemoryRegionHeader<ALIGN>>());
-
-            // Packets are always padded to 4 bytes, and the send buffer length is a multiple of 4,
-            // therefore it is always valid to write 4 bytes at wr_idx.
-            let header = PacketHeader::new(msg.len() as u16);
-            data_ptr
-                .add(wr_idx as usize)
-                .cast::<PacketHeader>()
-                .write(header);
-            wr_idx += 4;
-            if wr_idx >= self.send_buffer_len {
-                wr_idx = 0;
-            }
-
-            let tail_size = (self.send_buffer_len - wr_idx) as usize;
-            if msg.len() > tail_size {
-                // Wrap around
-                let (p1, p2) = msg.split_at(tail_size);
-                data_ptr
-                    .add(wr_idx as usize)
-                    .copy_from_nonoverlapping(p1.as_ptr(), p1.len());
-                data_ptr.copy_from_nonoverlapping(p2.as_ptr(), p2.len());
-            } else {
-                data_ptr
-                    .add(wr_idx as usize)
-                    .copy_from_nonoverlapping(msg.as_ptr(), msg.len());
-            }
+            (&raw mut (*send_region.as_ptr()).wr_idx.value).write(LeAtomicU32::new(0));
+            (&raw mut (*send_region.as_ptr()).rd_idx.value).write(LeAtomicU32::new(0));
+        }
 
-            wr_idx += padded_msg_len as u32;
-            if wr_idx >= self.send_buffer_len {
-                wr_idx -= self.send_buffer_len;
-            }
-            self.send_wr_idx = wr_idx;
-            (*self.send_region)
-                .wr_idx
-                .value
-                .store(wr_idx, Ordering::Release);
-            // TODO writeback dcache
-            self.notify();
-            Ok(())
+        Sender {
+            send_region,
+            send_buffer_len,
+            mbox,
+            send_wr_idx: 0,
+            crc: false,
+            seq: None,
+            #[cfg(feature = "stats")]
+            stats: Stats::default(),
+            observer: NoObserver,
+            barrier: NoBarrier,
         }
     }
+}
 
-    /// Notify the other end.
-    pub fn notify(&mut self) {
-        self.mbox.notify()
+impl<M, const ALIGN: usize, O, B, const PAD: usize> Sender<M, ALIGN, O, B, PAD>
+where
+    M: Notifier,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Send a message.
+    pub fn send(&mut self, msg: &[u8]) -> Result<(), SendError> {
+        self.send_vectored(&[msg])
     }
-}
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum SendError {
-    /// There was not enough space in the buffer to send the message.
-    InsufficientCapacity,
-    /// The rd_idx of the sending region contained an invalid value. This is a fatal error, likely
-    /// caused by a bug in the channel implementation.
-    InvalidState,
-}
+    /// Send a single message made up of the concatenation of `parts`, without copying them into
+    /// an intermediate buffer first.
+    pub fn send_vectored(&mut self, parts: &[&[u8]]) -> Result<(), SendError> {
+        self.reserve_filled(parts, 0)?.commit();
+        Ok(())
+    }
+
+    /// Append a CRC-32 of the packet header and payload after each packet, so that corruption of
+    /// the shared memory is detected by the other side instead of being received as a valid
+    /// message, or wedging the ring if a length is hit.
+    ///
+    /// This changes the format of the ring, so the other side has to enable it as well with
+    /// [`Receiver::set_crc`]. It is not part of the reference implementation, so it must not be
+    /// enabled when talking to it. The trailer takes 4 bytes of ring space per message. The
+    /// reserved header byte is covered as well, and is sent as 0 if sequence numbers are disabled.
+    /// Packets with the [`CONTROL`] flag are sent without a trailer.
+    pub fn set_crc(&mut self, enabled: bool) {
+        self.crc = enabled;
+    }
+
+    /// Put a sequence number in the reserved byte of each packet header, incremented with every
+    /// message and wrapping around after 255, so that the other side can tell if messages were
+    /// lost or the sender restarted. Numbering starts at 0 when this is enabled and after a
+    /// [`reset`][Self::reset].
+    ///
+    /// The other side checks them if it enables [`Receiver::set_sequence`]. The reference
+    /// implementation leaves the byte unspecified, so it must not be enabled on the receiving side
+    /// when talking to it.
+    ///
+    /// The number is 8 bits wide, since the other reserved byte of the header carries the flags,
+    /// so a gap of a multiple of 256 messages goes unnoticed.
+    pub fn set_sequence(&mut self, enabled: bool) {
+        self.seq = enabled.then_some(0);
+    }
+
+    /// Send a message with `flags` in the packet header, e.g. to tell control messages from data.
+    /// The other side can read them with [`Receiver::try_recv_typed`].
+    ///
+    /// Other methods send flags of 0. See [`Receiver::try_recv_typed`] for compatibility with the
+    /// reference implementation. The [`MORE_FRAGMENTS`] bit is used by
+    /// [`send_fragmented`][Self::send_fragmented], so it should be left clear if the other side
+    /// reassembles fragmented messages. A message with the [`CONTROL`] bit is sent without a CRC
+    /// trailer or sequence number.
+    pub fn send_typed(&mut self, msg: &[u8], flags: u8) -> Result<(), SendError> {
+        self.reserve_filled(&[msg], flags)?.commit();
+        Ok(())
+    }
+
+    /// Send a message that may be larger than the ring, split into fragments that the other side
+    /// reassembles with [`Receiver::try_recv_reassembled`].
+    ///
+    /// `sent` tracks how much of the message has been sent so far, and must start at 0 for each
+    /// message. Each call sends as many fragments as currently fit. If the ring fills up before the
+    /// whole message is sent, this returns [`SendError::InsufficientCapacity`]; call it again with
+    /// the same `msg` and `sent` once the other side has made room.
+    ///
+    /// All fragments but the last are sent with the [`MORE_FRAGMENTS`] flag set, so the other side
+    /// has to use this crate too, since the reference implementation ignores the flags.
+    pub fn send_fragmented(&mut self, msg: &[u8], sent: &mut usize) -> Result<(), SendError> {
+        let start = *sent;
+        let r = self.send_fragments(msg, sent);
+        if *sent != start {
+            self.notify();
+        }
+        r
+    }
+
+    fn send_fragments(&mut self, msg: &[u8], sent: &mut usize) -> Result<(), SendError> {
+        loop {
+            let rest = &msg[*sent..];
+            let rd_idx = self.remote_rd_idx().ok_or(SendError::InvalidState)?;
+            let room = (self.free_space_with(rd_idx) & !(PAD - 1))
+                .saturating_sub(HEADER_SIZE + self.trailer_len())
+                .min(self.max_message_len());
+            // If there is no room at all, try to send a single byte to fail the usual way.
+            let n = rest.len().min(room.max(1));
+            let flags = if n < rest.len() { MORE_FRAGMENTS } else { 0 };
+            self.reserve_filled(&[&rest[..n]], flags)?
+                .commit_no_notify();
+            *sent += n;
+            if flags == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Send a message without notifying the other side.
+    ///
+    /// The other side is only guaranteed to wake up and receive the message after a later call to
+    /// [`notify`][Self::notify], or to a method that notifies such as [`send`][Self::send]. This
+    /// allows sending a batch of messages with a single notification.
+    pub fn send_no_notify(&mut self, msg: &[u8]) -> Result<(), SendError> {
+        self.reserve_filled(&[msg], 0)?.commit_no_notify();
+        Ok(())
+    }
+
+    /// Send as many of `msgs` as fit, in order, and notify the other side once. Returns how many
+    /// were sent.
+    ///
+    /// `wr_idx` is only published after the last message is written, so the other side sees the
+    /// whole batch at once. If a message can't be sent, the batch stops there. That is only
+    /// reported as an error if it was the first message; otherwise the count tells the caller
+    /// where to continue from, and the error is returned when it does.
+    pub fn send_all<'a>(
+        &mut self,
+        msgs: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<usize, SendError> {
+        let mut sent = 0;
+        for msg in msgs {
+            match self.reserve_filled(&[msg], 0) {
+                Ok(slot) => {
+                    slot.advance();
+                    sent += 1;
+                }
+                Err(e) if sent == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        if sent > 0 {
+            self.publish();
+            self.notify();
+        }
+        Ok(sent)
+    }
+
+    fn reserve_filled(
+        &mut self,
+        parts: &[&[u8]],
+        flags: u8,
+    ) -> Result<SendSlot<'_, M, ALIGN, O, B, PAD>, SendError> {
+        let msg_len = parts.iter().map(|part| part.len()).sum();
+        let mut slot = self.reserve_with_flags(msg_len, flags)?;
+
+        let (mut first, mut second) = slot.as_mut_slices();
+        for part in parts {
+            let (p1, p2) = part.split_at(part.len().min(first.len()));
+            let (dst, rest) = core::mem::take(&mut first).split_at_mut(p1.len());
+            dst.write_copy_of_slice(p1);
+            first = rest;
+            let (dst, rest) = core::mem::take(&mut second).split_at_mut(p2.len());
+            dst.write_copy_of_slice(p2);
+            second = rest;
+        }
+
+        Ok(slot)
+    }
+
+    /// Reserve space for a message of `len` bytes, to be written in place in the ring buffer.
+    ///
+    /// The message is sent when [`SendSlot::commit`] is called. If the slot is dropped instead,
+    /// nothing is sent.
+    pub fn reserve(&mut self, len: usize) -> Result<SendSlot<'_, M, ALIGN, O, B, PAD>, SendError> {
+        self.reserve_with_flags(len, 0)
+    }
+
+    fn reserve_with_flags(
+        &mut self,
+        len: usize,
+        flags: u8,
+    ) -> Result<SendSlot<'_, M, ALIGN, O, B, PAD>, SendError> {
+        if len > self.max_message_len() {
+            return Err(SendError::MessageTooLarge);
+        }
+        let padded_len = padded_len_with(len, PAD);
+        let rd_idx = self.remote_rd_idx().ok_or(SendError::InvalidState)?;
+        if self.free_space_with(rd_idx) < padded_len + HEADER_SIZE + self.trailer_len_for(flags) {
+            #[cfg(feature = "stats")]
+            {
+                self.stats.send_full_rejections = self.stats.send_full_rejections.wrapping_add(1);
+            }
+            self.observer.on_full();
+            return Err(SendError::InsufficientCapacity);
+        }
+
+        let wr_idx = self.send_wr_idx;
+        // The CRC covers the sequence byte, so it is 0 instead of unspecified without sequence
+        // numbers. Control packets have neither.
+        let seq = if flags & CONTROL != 0 {
+            None
+        } else {
+            self.seq.or(self.crc.then_some(0))
+        };
+        let header = PacketHeader::new(len as u16, flags, seq);
+        // SAFETY: The other side does not read past wr_idx, and there is room for the packet.
+        unsafe { ring_write(self.data_ptr(), self.send_buffer_len, wr_idx, header) };
+        let data_idx = ring_add(wr_idx, HEADER_SIZE as u32, self.send_buffer_len);
+
+        Ok(SendSlot {
+            sender: self,
+            data_idx,
+            len,
+            control: flags & CONTROL != 0,
+        })
+    }
+
+    /// Notify the other end.
+    pub fn notify(&mut self) {
+        self.observer.on_notify();
+        self.mbox.notify()
+    }
+
+    /// Make everything written up to `send_wr_idx` visible to the other side.
+    fn publish(&mut self) {
+        self.barrier.sync();
+        self.shared_wr_idx()
+            .store(self.send_wr_idx, Ordering::Release);
+    }
+
+    /// The number of bytes the ring can hold, including packet headers and padding. This is what
+    /// [`free_space`][Self::free_space] returns when the ring is empty.
+    pub fn capacity(&self) -> usize {
+        capacity(self.send_buffer_len as usize)
+    }
+
+    /// The size of the largest message that can be sent, limited by both the ring capacity and
+    /// the 16-bit length field of the packet header. Larger messages fail with
+    /// [`SendError::MessageTooLarge`].
+    pub fn max_message_len(&self) -> usize {
+        max_message_len(self.send_buffer_len as usize, PAD).saturating_sub(self.trailer_len())
+    }
+
+    /// The number of bytes currently free in the ring, including space needed for packet headers
+    /// and padding.
+    ///
+    /// If the other side has published an invalid `rd_idx`, this returns 0.
+    pub fn free_space(&self) -> usize {
+        self.remote_rd_idx()
+            .map_or(0, |rd_idx| self.free_space_with(rd_idx))
+    }
+
+    /// Load the `rd_idx` published by the other side, or `None` if it is out of range or
+    /// misaligned.
+    fn remote_rd_idx(&self) -> Option<u32> {
+        let rd_idx = self.shared_rd_idx().load(Ordering::Acquire);
+        (rd_idx < self.send_buffer_len && rd_idx.is_multiple_of(PAD as u32)).then_some(rd_idx)
+    }
+
+    fn free_space_with(&self, rd_idx: u32) -> usize {
+        let used = ring_distance(rd_idx, self.send_wr_idx, self.send_buffer_len);
+        capacity(self.send_buffer_len as usize) - used as usize
+    }
+
+    /// The position in the ring after the last message sent so far, for
+    /// [`is_read_up_to`][Self::is_read_up_to].
+    pub fn position(&self) -> u32 {
+        self.send_wr_idx
+    }
+
+    /// Whether the other side has read every message sent before `position` was taken with
+    /// [`position`][Self::position].
+    ///
+    /// This is only meaningful while less than the buffer length has been sent since. Once more
+    /// has been sent, the other side must have read past `position` to make room for it.
+    pub fn is_read_up_to(&self, position: u32) -> Result<bool, SendError> {
+        let rd_idx = self.remote_rd_idx().ok_or(SendError::InvalidState)?;
+        // Comparing rd_idx and position directly goes wrong when one of them has wrapped around
+        // the end of the ring and the other hasn't, so compare what is still unread with what was
+        // sent after `position` instead.
+        let unread = ring_distance(rd_idx, self.send_wr_idx, self.send_buffer_len);
+        Ok(unread <= ring_distance(position, self.send_wr_idx, self.send_buffer_len))
+    }
+
+    /// Whether a message of `len` bytes currently fits in the ring.
+    pub fn can_send(&self, len: usize) -> bool {
+        if len > self.max_message_len() {
+            return false;
+        }
+        let padded_len = padded_len_with(len, PAD);
+        padded_len + HEADER_SIZE + self.trailer_len() <= self.free_space()
+    }
+
+    /// Reset the send ring to empty, as if newly created. Used when bonding again.
+    ///
+    /// Any messages the other side has not read yet are discarded.
+    pub fn reset(&mut self) {
+        self.send_wr_idx = 0;
+        if self.seq.is_some() {
+            self.seq = Some(0);
+        }
+        self.shared_wr_idx().store(0, Ordering::Release);
+        self.shared_rd_idx().store(0, Ordering::Release);
+    }
+
+    /// Counters of what has been sent so far.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// The most bytes, including packet headers and padding, that were in the ring right after a
+    /// message was sent.
+    ///
+    /// The other side may have read some of them by the time the sender looks at its `rd_idx`,
+    /// so this is a lower bound of the real high water mark.
+    #[cfg(feature = "stats")]
+    pub fn high_water_mark(&self) -> usize {
+        self.stats.high_water_mark as usize
+    }
+
+    /// [`high_water_mark`][Self::high_water_mark] as a percentage of the
+    /// [`capacity`][Self::capacity].
+    #[cfg(feature = "stats")]
+    pub fn high_water_mark_percent(&self) -> u8 {
+        percent(self.high_water_mark(), self.capacity())
+    }
+
+    /// The start of the send region and the length of its data field, as passed to
+    /// [`IcMsgTransport::new`].
+    pub fn region(&self) -> (NonNull<()>, u32) {
+        (self.send_region.cast(), self.send_buffer_len)
+    }
+
+    /// Replace the [`Observer`] that is called on send events.
+    pub fn with_observer<O2: Observer>(self, observer: O2) -> Sender<M, ALIGN, O2, B, PAD> {
+        Sender {
+            send_region: self.send_region,
+            send_buffer_len: self.send_buffer_len,
+            mbox: self.mbox,
+            send_wr_idx: self.send_wr_idx,
+            crc: self.crc,
+            seq: self.seq,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+            observer,
+            barrier: self.barrier,
+        }
+    }
+
+    /// Replace the [`Barrier`] that is run before `wr_idx` is published. The other side's
+    /// receiver needs one as well, see [`Receiver::with_barrier`].
+    pub fn with_barrier<B2: Barrier>(self, barrier: B2) -> Sender<M, ALIGN, O, B2, PAD> {
+        Sender {
+            send_region: self.send_region,
+            send_buffer_len: self.send_buffer_len,
+            mbox: self.mbox,
+            send_wr_idx: self.send_wr_idx,
+            crc: self.crc,
+            seq: self.seq,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+            observer: self.observer,
+            barrier,
+        }
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    fn trailer_len(&self) -> usize {
+        self.trailer_len_for(0)
+    }
+
+    /// The size of the trailer after a packet sent with `flags`.
+    fn trailer_len_for(&self, flags: u8) -> usize {
+        if self.crc && flags & CONTROL == 0 {
+            size_of::<u32>()
+        } else {
+            0
+        }
+    }
+
+    fn shared_rd_idx(&self) -> &LeAtomicU32 {
+        // SAFETY: The region was initialized by the constructor and outlives the sender.
+        unsafe { SharedMemoryRegionHeader::rd_idx(self.send_region.as_ptr()) }
+    }
+
+    fn shared_wr_idx(&self) -> &LeAtomicU32 {
+        // SAFETY: See `shared_rd_idx`.
+        unsafe { SharedMemoryRegionHeader::wr_idx(self.send_region.as_ptr()) }
+    }
+
+    fn data_ptr(&self) -> *mut u8 {
+        unsafe {
+            self.send_region
+                .cast::<u8>()
+                .as_ptr()
+                .add(size_of::<SharedMemoryRegionHeader<ALIGN>>())
+        }
+    }
+}
+
+/// Space for a message in the send ring, returned by [`Sender::reserve`].
+///
+/// The space may wrap around the end of the ring, so it is exposed as two slices by
+/// [`as_mut_slices`][Self::as_mut_slices]. The message is sent by [`commit`][Self::commit];
+/// dropping the slot without committing leaves the ring unchanged.
+pub struct SendSlot<'a, M, const ALIGN: usize, O = NoObserver, B = NoBarrier, const PAD: usize = 4>
+where
+    M: Notifier,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    sender: &'a mut Sender<M, ALIGN, O, B, PAD>,
+    // index of the first byte of the payload
+    data_idx: u32,
+    len: usize,
+    // control packets have no trailer or sequence number
+    control: bool,
+}
+
+impl<'a, M, const ALIGN: usize, O, B, const PAD: usize> SendSlot<'a, M, ALIGN, O, B, PAD>
+where
+    M: Notifier,
+    O: Observer,
+    B: Barrier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// The length of the message.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the message is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The message contents, split where the ring buffer wraps around. The second slice is empty
+    /// if the message does not wrap.
+    ///
+    /// The contents are initially unspecified, possibly never written, so they can only be
+    /// written, e.g. with `write_copy_of_slice`. Bytes left unwritten are sent as they are.
+    pub fn as_mut_slices(&mut self) -> (&mut [MaybeUninit<u8>], &mut [MaybeUninit<u8>]) {
+        let tail_size = (self.sender.send_buffer_len - self.data_idx) as usize;
+        let first_len = self.len.min(tail_size);
+        let data_ptr = self.sender.data_ptr();
+        // The other side does not read past wr_idx, so this end has exclusive access to the
+        // reserved space until it is committed.
+        unsafe {
+            (
+                core::slice::from_raw_parts_mut(
+                    data_ptr.add(self.data_idx as usize).cast(),
+                    first_len,
+                ),
+                core::slice::from_raw_parts_mut(data_ptr.cast(), self.len - first_len),
+            )
+        }
+    }
+
+    /// Send the message and notify the other side.
+    pub fn commit(self) {
+        let sender = self.commit_no_notify();
+        sender.notify();
+    }
+
+    /// Send the message without notifying the other side. See [`Sender::send_no_notify`].
+    pub fn commit_no_notify(self) -> &'a mut Sender<M, ALIGN, O, B, PAD> {
+        let sender = self.advance();
+        sender.publish();
+        // TODO writeback dcache
+        sender
+    }
+
+    /// Write the trailer and move the local `wr_idx` past the message, without publishing it.
+    fn advance(self) -> &'a mut Sender<M, ALIGN, O, B, PAD> {
+        let padded_len = padded_len_with(self.len, PAD);
+        let buffer_len = self.sender.send_buffer_len;
+        let mut wr_idx = ring_add(self.data_idx, padded_len as u32, buffer_len);
+        if self.sender.crc && !self.control {
+            let data_ptr = self.sender.data_ptr();
+            let header_idx = ring_add(self.data_idx, buffer_len - HEADER_SIZE as u32, buffer_len);
+            // SAFETY: The header was written by `reserve`, and the trailer fits in the reserved
+            // space.
+            let header: PacketHeader = unsafe { ring_read(data_ptr, buffer_len, header_idx) };
+            // SAFETY: `reserve` writes the sequence byte if CRC checking is enabled.
+            let seq = unsafe { header.seq.assume_init() };
+            let crc = packet_crc(
+                header.flags,
+                seq,
+                data_ptr,
+                buffer_len,
+                self.data_idx,
+                self.len,
+            );
+            unsafe { ring_write(data_ptr, buffer_len, wr_idx, crc.to_le()) };
+            wr_idx = ring_add(wr_idx, size_of::<u32>() as u32, buffer_len);
+        }
+        self.sender.send_wr_idx = wr_idx;
+        if let Some(seq) = &mut self.sender.seq
+            && !self.control
+        {
+            *seq = seq.wrapping_add(1);
+        }
+        #[cfg(feature = "stats")]
+        {
+            let in_flight = self.sender.remote_rd_idx().map_or(0, |rd_idx| {
+                self.sender.capacity() - self.sender.free_space_with(rd_idx)
+            });
+            let stats = &mut self.sender.stats;
+            stats.messages_sent = stats.messages_sent.wrapping_add(1);
+            stats.bytes_sent = stats.bytes_sent.wrapping_add(self.len as u32);
+            stats.high_water_mark = stats.high_water_mark.max(in_flight as u32);
+        }
+        self.sender.observer.on_send(self.len);
+        self.sender
+    }
+}
+
+/// Counters kept by a [`Sender`] or [`Receiver`], for debugging. Each half only updates its own
+/// side's counters. The counters wrap around on overflow.
+#[cfg(feature = "stats")]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    /// Messages sent, including bonding messages.
+    pub messages_sent: u32,
+    /// Payload bytes sent, not including packet headers and padding.
+    pub bytes_sent: u32,
+    /// Messages received, including bonding messages and discarded messages.
+    pub messages_recv: u32,
+    /// Payload bytes received, not including packet headers and padding.
+    pub bytes_recv: u32,
+    /// Sends that failed with [`SendError::InsufficientCapacity`].
+    pub send_full_rejections: u32,
+    /// Receives that failed because of the other side's state: [`RecvError::InvalidMessage`],
+    /// [`RecvError::InvalidState`], [`RecvError::CrcMismatch`], or the first
+    /// [`RecvError::Desync`].
+    pub recv_invalid: u32,
+    /// The most bytes, including packet headers and padding, that were seen in the ring. See
+    /// [`Sender::high_water_mark`] and [`Receiver::high_water_mark`]. This does not wrap.
+    pub high_water_mark: u32,
+}
+
+#[cfg(feature = "stats")]
+fn percent(bytes: usize, capacity: usize) -> u8 {
+    (bytes * 100 / capacity) as u8
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SendError {
+    /// There was not enough space in the buffer to send the message.
+    InsufficientCapacity,
+    /// The message is longer than [`Sender::max_message_len`] and can never be sent.
+    MessageTooLarge,
+    /// The rd_idx of the sending region contained an invalid value, either out of range or not a
+    /// multiple of 4. Nothing was sent. This is likely caused by the other side misbehaving or
+    /// restarting, and can be recovered from by bonding again.
+    InvalidState,
+}
+
+impl core::fmt::Display for SendError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SendError::InsufficientCapacity => write!(f, "insufficient capacity"),
+            SendError::MessageTooLarge => write!(f, "message too large"),
+            SendError::InvalidState => write!(f, "invalid state"),
+        }
+    }
+}
+
+impl core::error::Error for SendError {}
+
+impl embedded_io::Error for SendError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match &self {
+            Self::InsufficientCapacity => embedded_io::ErrorKind::WriteZero,
+            Self::MessageTooLarge => embedded_io::ErrorKind::InvalidInput,
+            Self::InvalidState => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecvError {
+    /// The message was bigger than the provided buffer. The message stays queued, so it can be
+    /// received again with a buffer of at least `required` bytes.
+    MessageTooBig { required: usize },
+    /// There were no messages to receive.
+    Empty,
+    /// An invalid message was received. e.g. a packet with a length greater than the shared memory
+    /// memory region. This is a fatal error, likely caused by a bug in the channel implementation.
+    InvalidMessage,
+    /// The other side bonded again with a different session ID, most likely because it restarted.
+    SessionLost,
+    /// The wr_idx of the receiving region contained an invalid value, either out of range or not a
+    /// multiple of 4. Nothing was received, and receiving can be retried once the other side has
+    /// published a valid value again.
+    InvalidState,
+    /// The indices in the shared memory region are inconsistent with what was previously observed,
+    /// most likely because the other side restarted. The receiver stays in this state until it is
+    /// [reset][Receiver::reset], e.g. by bonding again.
+    Desync,
+    /// The other side tore down the channel with [`IcMsg::deinit`][crate::IcMsg::deinit]. Nothing
+    /// more will be received until both sides bond again.
+    PeerClosed,
+    /// The CRC trailer of the next packet doesn't match its contents, which means the shared
+    /// memory was corrupted. Only returned if [CRC checking][Receiver::set_crc] is enabled.
+    CrcMismatch,
+    /// The next packet's sequence number is not the one that follows the last packet received,
+    /// meaning messages were lost or duplicated, or the other side restarted. Only returned if
+    /// [sequence numbers][Receiver::set_sequence] are enabled. The packet is delivered by the
+    /// next receive.
+    SequenceGap { expected: u8, got: u8 },
+}
+
+impl core::fmt::Display for RecvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RecvError::MessageTooBig { required } => {
+                write!(f, "message too big, {required} bytes required")
+            }
+            RecvError::Empty => write!(f, "empty"),
+            RecvError::InvalidMessage => write!(f, "invalid message"),
+            RecvError::SessionLost => write!(f, "session lost"),
+            RecvError::InvalidState => write!(f, "invalid state"),
+            RecvError::Desync => write!(f, "desynchronized"),
+            RecvError::PeerClosed => write!(f, "closed by peer"),
+            RecvError::CrcMismatch => write!(f, "CRC mismatch"),
+            RecvError::SequenceGap { expected, got } => {
+                write!(f, "sequence gap, expected {expected}, got {got}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for RecvError {}
+
+impl embedded_io::Error for RecvError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match &self {
+            Self::MessageTooBig { .. } => embedded_io::ErrorKind::OutOfMemory,
+            Self::Empty => embedded_io::ErrorKind::Interrupted,
+            Self::InvalidMessage => embedded_io::ErrorKind::Other,
+            Self::SessionLost => embedded_io::ErrorKind::ConnectionReset,
+            Self::InvalidState => embedded_io::ErrorKind::Other,
+            Self::Desync => embedded_io::ErrorKind::ConnectionReset,
+            Self::PeerClosed => embedded_io::ErrorKind::ConnectionAborted,
+            Self::CrcMismatch => embedded_io::ErrorKind::InvalidData,
+            Self::SequenceGap { .. } => embedded_io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+/// The header at the start of each shared memory region, holding the read and write indices.
+#[repr(C)]
+pub struct SharedMemoryRegionHeader<const ALIGN: usize>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    rd_idx: Index<ALIGN>,
+    wr_idx: Index<ALIGN>,
+}
+
+impl<const ALIGN: usize> SharedMemoryRegionHeader<ALIGN>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// The size of the header in bytes. The data field of the region follows immediately after.
+    pub const SIZE: usize = size_of::<Self>();
+
+    /// The offset of the data field from the start of the region, equal to [`Self::SIZE`].
+    pub const fn data_offset() -> usize {
+        Self::SIZE
+    }
+
+    /// The total size of a region with a data field of `data_len` bytes, i.e. the size the linker
+    /// script has to reserve for it.
+    pub const fn required_region_size(data_len: usize) -> usize {
+        Self::data_offset() + data_len
+    }
+
+    /// The size of the largest message that can be sent through a data field of `data_len` bytes.
+    ///
+    /// This accounts for the packet header, the padding of packets to 4 bytes, the byte that is
+    /// always left free in the ring, and the 16-bit length field of the packet header.
+    pub const fn max_message_len(data_len: usize) -> usize {
+        max_message_len(data_len, 4)
+    }
+
+    /// The read index of the header at `this`.
+    ///
+    /// Only the index itself is borrowed. The other side writes the rest of the header
+    /// concurrently, so there must never be a reference to the whole header.
+    ///
+    /// # Safety
+    ///
+    /// `this` must point to an initialized header that stays valid for `'a`.
+    unsafe fn rd_idx<'a>(this: *const Self) -> &'a LeAtomicU32 {
+        unsafe { &(*this).rd_idx.value }
+    }
+
+    /// The write index of the header at `this`. See [`rd_idx`][Self::rd_idx].
+    ///
+    /// # Safety
+    ///
+    /// `this` must point to an initialized header that stays valid for `'a`.
+    unsafe fn wr_idx<'a>(this: *const Self) -> &'a LeAtomicU32 {
+        unsafe { &(*this).wr_idx.value }
+    }
+}
+
+#[repr(C)]
+struct Index<const ALIGN: usize>
+where
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    _align: elain::Align<ALIGN>,
+    value: LeAtomicU32,
+}
+
+/// The flag set by [`Sender::send_fragmented`] on every fragment of a message except the last.
+pub const MORE_FRAGMENTS: u8 = 0x80;
+
+/// The flag of control packets, i.e. the bonding and teardown messages of
+/// [`IcMsg`][crate::IcMsg]. Control packets carry no CRC trailer and no sequence number, so that
+/// they can be told apart from data even if the two sides disagree about those, e.g. after the
+/// other side has restarted.
+pub const CONTROL: u8 = 0x40;
+
+#[repr(C)]
+struct PacketHeader {
+    len: BeU16,
+    // Reserved in the reference implementation, which leaves it unspecified.
+    flags: u8,
+    // Also reserved, used for the sequence number if enabled.
+    seq: MaybeUninit<u8>,
+}
+
+const _: () = assert!(size_of::<PacketHeader>() == HEADER_SIZE);
+
+impl PacketHeader {
+    fn new(len: u16, flags: u8, seq: Option<u8>) -> Self {
+        Self {
+            len: len.into(),
+            flags,
+            seq: seq.map_or(MaybeUninit::uninit(), MaybeUninit::new),
+        }
+    }
+}
+
+/// Call `try_recv` until it returns something other than [`RecvError::Empty`], waiting for a
+/// notification from `waiter` in between. This is the loop behind every async receive method.
+///
+/// The waiter is polled once before each attempt, so that its waker is registered before the ring
+/// is checked and a message that arrives in between is not missed.
+pub(crate) async fn recv_with<T>(
+    waiter: &mut impl WaitForNotify,
+    mut try_recv: impl FnMut() -> Result<T, RecvError>,
+) -> Result<T, RecvError> {
+    loop {
+        let mut wait_fut = pin!(waiter.wait_for_notify());
+        let r = crate::poll::poll(wait_fut.as_mut()).await;
+
+        match try_recv() {
+            Err(RecvError::Empty) => {
+                if r.is_pending() {
+                    wait_fut.await;
+                }
+            }
+            r => return r,
+        }
+    }
+}
+
+/// The size of the largest message that fits in a data field of `data_len` bytes, with packets
+/// padded to a multiple of `pad` bytes.
+const fn max_message_len(data_len: usize, pad: usize) -> usize {
+    let max_packet = capacity(data_len) & !(pad - 1);
+    let len = max_packet.saturating_sub(HEADER_SIZE);
+    if len > u16::MAX as usize {
+        u16::MAX as usize
+    } else {
+        len
+    }
+}
+
+/// Read a `T` from `idx` in a ring of `buffer_len` bytes at `data_ptr`, wrapping around the end.
+/// With a `PAD` of less than 4, packet headers and trailers can straddle the end of the ring.
+///
+/// # Safety
+///
+/// The caller must own the bytes, as for [`packet_crc`], and they must hold a valid `T`.
+unsafe fn ring_read<T>(data_ptr: *const u8, buffer_len: u32, idx: u32) -> T {
+    let mut value = MaybeUninit::<T>::uninit();
+    let dst = value.as_mut_ptr().cast::<u8>();
+    let tail_len = size_of::<T>().min((buffer_len - idx) as usize);
+    unsafe {
+        data_ptr
+            .add(idx as usize)
+            .copy_to_nonoverlapping(dst, tail_len);
+        data_ptr.copy_to_nonoverlapping(dst.add(tail_len), size_of::<T>() - tail_len);
+        value.assume_init()
+    }
+}
+
+/// Write `value` at `idx` in a ring of `buffer_len` bytes at `data_ptr`, wrapping around the end
+/// like [`ring_read`].
+///
+/// # Safety
+///
+/// The caller must own the bytes, as for [`packet_crc`].
+unsafe fn ring_write<T>(data_ptr: *mut u8, buffer_len: u32, idx: u32, value: T) {
+    let src = (&raw const value).cast::<u8>();
+    let tail_len = size_of::<T>().min((buffer_len - idx) as usize);
+    unsafe {
+        src.copy_to_nonoverlapping(data_ptr.add(idx as usize), tail_len);
+        src.add(tail_len)
+            .copy_to_nonoverlapping(data_ptr, size_of::<T>() - tail_len);
+    }
+}
+
+/// View an initialized buffer as possibly uninitialized, to pass it to a function that only writes
+/// to it.
+fn as_uninit(buf: &mut [u8]) -> &mut [MaybeUninit<u8>] {
+    // SAFETY: `MaybeUninit<u8>` has the same layout as `u8`, and the callers never write
+    // uninitialized bytes through the returned slice.
+    unsafe { &mut *(core::ptr::from_mut(buf) as *mut [MaybeUninit<u8>]) }
+}
+
+/// The index `n` bytes after `idx` in a ring of `buffer_len` bytes, for `idx < buffer_len` and
+/// `n <= buffer_len`. Unlike `(idx + n) % buffer_len`, this can't overflow.
+fn ring_add(idx: u32, n: u32, buffer_len: u32) -> u32 {
+    let to_end = buffer_len - idx;
+    if n >= to_end { n - to_end } else { idx + n }
+}
+
+/// The number of bytes from `from` up to `to` in a ring of `buffer_len` bytes, for indices less
+/// than `buffer_len`. This is the number of bytes in use if `from` is the read index and `to` the
+/// write index.
+fn ring_distance(from: u32, to: u32, buffer_len: u32) -> u32 {
+    if to >= from {
+        to - from
+    } else {
+        buffer_len - (from - to)
+    }
+}
+
+/// The CRC-32 of a packet's header, i.e. its length, flags and sequence byte, and its `len` bytes
+/// of payload starting at `data_idx` in a ring of `buffer_len` bytes at `data_ptr`.
+fn packet_crc(
+    flags: u8,
+    seq: u8,
+    data_ptr: *const u8,
+    buffer_len: u32,
+    data_idx: u32,
+    len: usize,
+) -> u32 {
+    let len_bytes = (len as u16).to_be_bytes();
+    let crc = crc32_update(!0, &[len_bytes[0], len_bytes[1], flags, seq]);
+    let tail_len = len.min((buffer_len - data_idx) as usize);
+    // SAFETY: The payload is owned by the caller, either as a reserved slot or as an unread
+    // packet, so the other side does not write to it.
+    let (p1, p2) = unsafe {
+        (
+            core::slice::from_raw_parts(data_ptr.add(data_idx as usize), tail_len),
+            core::slice::from_raw_parts(data_ptr, len - tail_len),
+        )
+    };
+    !crc32_update(crc32_update(crc, p1), p2)
+}
+
+/// Update a CRC-32 (IEEE 802.3, as used by zlib) with `data`.
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+pub trait Notifier {
+    fn notify(&mut self);
+}
+
+/// Closures can be used as notifiers directly, e.g. `|| trigger.trigger()`.
+impl<F: FnMut()> Notifier for F {
+    fn notify(&mut self) {
+        self()
+    }
+}
+
+/// Hooks that are called on protocol events, e.g. to log them, measure timing with a cycle
+/// counter, or toggle a GPIO for a scope. Set with [`Sender::with_observer`] and
+/// [`Receiver::with_observer`], or for both halves of a channel with
+/// [`IcMsg::with_observer`][crate::IcMsg::with_observer]. The default, [`NoObserver`], does
+/// nothing and compiles away.
+///
+/// The hooks are called in the middle of sending and receiving, so they should be quick.
+pub trait Observer {
+    /// A message of `len` bytes was sent, or is about to be published as part of a batch.
+    fn on_send(&mut self, len: usize) {
+        let _ = len;
+    }
+
+    /// A message of `len` bytes was received or discarded.
+    fn on_recv(&mut self, len: usize) {
+        let _ = len;
+    }
+
+    /// A send failed because there was no room in the ring.
+    fn on_full(&mut self) {}
+
+    /// The other side is about to be notified.
+    fn on_notify(&mut self) {}
+}
+
+/// An [`Observer`] that does nothing.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
+
+/// A data synchronization barrier that is run after message bytes are written and before `wr_idx`
+/// is published, and after `wr_idx` is loaded and before message bytes are read. On Cortex-M, this
+/// is typically `cortex_m::asm::dmb` or `cortex_m::asm::dsb`. Set with [`Sender::with_barrier`]
+/// and [`Receiver::with_barrier`]. The default, [`NoBarrier`], does nothing.
+///
+/// The message bytes are written and read with plain, non-atomic copies, ordered only by the
+/// `Release` store and the `Acquire` load of `wr_idx`. Those orderings are defined by the Rust
+/// memory model, which assumes a single coherent memory system, and the compiler may implement
+/// them with instructions that only order accesses within this core's shareability domain. When
+/// the other core sees shared memory through a different path, e.g. through a write buffer or a
+/// bus outside that domain, the bytes can become visible after `wr_idx` unless an explicit barrier
+/// is executed in between.
+pub trait Barrier {
+    fn sync(&mut self);
+}
+
+/// Functions and closures can be used as barriers directly, e.g. `cortex_m::asm::dmb`.
+impl<F: FnMut()> Barrier for F {
+    fn sync(&mut self) {
+        self()
+    }
+}
+
+/// A [`Barrier`] that does nothing.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NoBarrier;
+
+impl Barrier for NoBarrier {
+    fn sync(&mut self) {}
+}
+
+mod integer {
+    use crate::loom::sync::atomic::Ordering;
+
+    // Loom needs its own atomics to model the orderings, so the feature is ignored under loom.
+    #[cfg(any(loom, not(feature = "portable-atomic")))]
+    use crate::loom::sync::atomic::AtomicU32;
+    #[cfg(all(not(loom), feature = "portable-atomic"))]
+    use portable_atomic::AtomicU32;
+
+    // The shared memory layout is fixed by the protocol, whichever atomic is used.
+    #[cfg(not(loom))]
+    const _: () = assert!(size_of::<AtomicU32>() == 4 && align_of::<AtomicU32>() == 4);
+
+    /// A big-endian u16.
+    #[repr(transparent)]
+    pub struct BeU16(u16);
+
+    impl BeU16 {
+        pub fn value(self) -> u16 {
+            self.into()
+        }
+    }
+
+    impl From<u16> for BeU16 {
+        fn from(t: u16) -> Self {
+            BeU16(t.to_be())
+        }
+    }
+
+    impl From<BeU16> for u16 {
+        fn from(t: BeU16) -> Self {
+            u16::from_be(t.0)
+        }
+    }
+
+    /// An atomic little-endian u32.
+    #[repr(transparent)]
+    pub struct LeAtomicU32(AtomicU32);
+
+    impl LeAtomicU32 {
+        pub fn new(value: u32) -> Self {
+            Self(AtomicU32::new(value.to_le()))
+        }
+        pub fn load(&self, order: Ordering) -> u32 {
+            u32::from_le(self.0.load(strict(order)))
+        }
+        pub fn store(&self, val: u32, order: Ordering) {
+            self.0.store(val.to_le(), strict(order))
+        }
+    }
+
+    /// The ordering to actually use for `order`, see the `strict-ordering` feature.
+    fn strict(order: Ordering) -> Ordering {
+        if cfg!(feature = "strict-ordering") {
+            Ordering::SeqCst
+        } else {
+            order
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    extern crate std;
+
+    use super::{
+        IcMsgTransport, Notifier, Receiver, RecvError, SendError, Sender, SharedMemoryRegionHeader,
+    };
+    use core::{alloc::Layout, mem::offset_of, ptr::NonNull, sync::atomic::Ordering};
+    use crate::loom::{alloc, thread};
+    #[cfg(not(loom))]
+    use proptest::prelude::{Just, Strategy, prop_assert, prop_assert_eq};
+
+    #[test]
+    fn test_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<super::Sender<Noop, 4>>();
+        assert_send::<super::Receiver<4>>();
+        assert_send::<IcMsgTransport<Noop, 4>>();
+    }
+
+    #[test]
+    fn test_alignment() {
+        assert_eq!(offset_of!(SharedMemoryRegionHeader<128>, rd_idx), 0);
+        assert_eq!(offset_of!(SharedMemoryRegionHeader<128>, wr_idx), 128);
+
+        assert_eq!(SharedMemoryRegionHeader::<4>::data_offset(), 8);
+        assert_eq!(SharedMemoryRegionHeader::<64>::data_offset(), 128);
+        assert_eq!(
+            SharedMemoryRegionHeader::<128>::data_offset(),
+            offset_of!(SharedMemoryRegionHeader<128>, wr_idx) + 128
+        );
+        assert_eq!(
+            SharedMemoryRegionHeader::<4>::required_region_size(1024),
+            1032
+        );
+        assert_eq!(
+            SharedMemoryRegionHeader::<128>::required_region_size(1024),
+            1280
+        );
+
+        const _: () = assert!(SharedMemoryRegionHeader::<8>::required_region_size(48) == 64);
+        assert_eq!(SharedMemoryRegionHeader::<4>::max_message_len(24), 16);
+        assert_eq!(SharedMemoryRegionHeader::<4>::max_message_len(26), 20);
+        assert_eq!(SharedMemoryRegionHeader::<4>::max_message_len(4), 0);
+        assert_eq!(
+            SharedMemoryRegionHeader::<4>::max_message_len(1 << 20),
+            u16::MAX as usize
+        );
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_recv_invalid_wr_idx() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 16;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region_1 = alloc_region(shared_region_layout);
+        let shared_region_2 = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region_1,
+                shared_region_2,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+
+        let mut buf = [0; 8];
+        let recv_region = shared_region_2.cast::<Hdr>().as_ptr();
+        unsafe { (*recv_region).rd_idx.value.store(0, Ordering::Release) };
+        for bogus_wr_idx in [buf_size as u32, 1000, 2] {
+            unsafe {
+                (*recv_region)
+                    .wr_idx
+                    .value
+                    .store(bogus_wr_idx, Ordering::Release)
+            };
+            let receiver = icmsg.split_mut().1;
+            assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::InvalidState));
+            assert_eq!(receiver.peek_len(), Err(RecvError::InvalidState));
+            assert_eq!(receiver.pending_bytes(), 0);
+            assert_eq!((receiver.recv_rd_idx, receiver.recv_last_wr_idx), (0, 0));
+            let rd_idx = unsafe { (*recv_region).rd_idx.value.load(Ordering::Relaxed) };
+            assert_eq!(rd_idx, 0);
+        }
+
+        // not sticky, unlike RecvError::Desync
+        unsafe { (*recv_region).wr_idx.value.store(0, Ordering::Release) };
+        assert_eq!(icmsg.try_recv(&mut buf), Err(RecvError::Empty));
+
+        unsafe {
+            alloc::dealloc(shared_region_1.as_ptr().cast(), shared_region_layout);
+            alloc::dealloc(shared_region_2.as_ptr().cast(), shared_region_layout);
+        }
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_free_space_and_pending_bytes() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 32];
+
+        assert_eq!(sender.capacity(), 31);
+        assert_eq!(sender.free_space(), 31);
+        assert!(sender.can_send(24));
+        assert!(!sender.can_send(25));
+
+        sender.send(b"01234").unwrap();
+        assert_eq!(sender.free_space(), 19);
+        assert!(sender.can_send(12));
+        assert!(!sender.can_send(13));
+
+        assert!(!receiver.is_empty());
+        assert_eq!(receiver.pending_bytes(), 12);
+        receiver.try_recv(&mut buf).unwrap();
+        assert_eq!(sender.free_space(), 31);
+        assert!(receiver.is_empty());
+        assert_eq!(receiver.pending_bytes(), 0);
+
+        // wrap around
+        for _ in 0..2 {
+            sender.send(b"0123456789").unwrap();
+            assert_eq!(receiver.pending_bytes(), 16);
+            receiver.try_recv(&mut buf).unwrap();
+        }
+
+        // a bogus rd_idx from the other side doesn't underflow
+        let region = shared_region.cast::<Hdr>().as_ptr();
+        unsafe { (*region).rd_idx.value.store(1000, Ordering::Release) };
+        assert_eq!(sender.free_space(), 0);
+        assert!(!sender.can_send(0));
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    /// Miri has no file system to persist failures to, and is too slow for the default number of
+    /// cases.
+    #[cfg(not(loom))]
+    fn proptest_config() -> proptest::test_runner::Config {
+        let mut config = proptest::test_runner::Config::default();
+        if cfg!(miri) {
+            config.cases = 8;
+            config.failure_persistence = None;
+        }
+        config
+    }
+
+    #[cfg(not(loom))]
+    proptest::proptest! {
+        #![proptest_config(proptest_config())]
+
+        #[test]
+        fn test_ring_index_math(
+            (buffer_len, idx, n) in (1..=u32::MAX)
+                .prop_flat_map(|len| (Just(len), 0..len, 0..=len))
+        ) {
+            let end = super::ring_add(idx, n, buffer_len);
+            prop_assert!(end < buffer_len);
+            prop_assert_eq!(super::ring_distance(idx, end, buffer_len), n % buffer_len);
+            prop_assert!(super::ring_distance(end, idx, buffer_len) < buffer_len);
+        }
+
+        /// Random sends and receives of random sizes, wrapping around rings of random sizes, with
+        /// the sender's free space and the receiver's pending bytes always adding up to the
+        /// capacity.
+        #[test]
+        fn test_free_space_invariant(
+            buf_words in 4..128usize,
+            ops in proptest::collection::vec(proptest::option::of(0..64usize), 0..200),
+        ) {
+            const ALIGN: usize = 4;
+            type Hdr = SharedMemoryRegionHeader<ALIGN>;
+            let buf_size = buf_words * 4;
+            let shared_region_layout =
+                Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+            let shared_region = alloc_region(shared_region_layout);
+            let mut icmsg = unsafe {
+                IcMsgTransport::<_, ALIGN>::new(
+                    shared_region,
+                    shared_region,
+                    buf_size as u32,
+                    buf_size as u32,
+                    Noop,
+                )
+            };
+            let (sender, receiver) = icmsg.split_mut();
+            let msg = [0xa5; 64];
+            let mut buf = [0; 64];
+            let mut queued = std::collections::VecDeque::new();
+
+            for op in ops {
+                match op {
+                    Some(len) if sender.can_send(len) => {
+                        sender.send(&msg[..len]).unwrap();
+                        queued.push_back(len);
+                    }
+                    Some(len) => {
+                        prop_assert!(sender.send(&msg[..len]).is_err());
+                    }
+                    None => {
+                        let r = receiver.try_recv(&mut buf);
+                        match queued.pop_front() {
+                            Some(len) => prop_assert_eq!(r, Ok(len)),
+                            None => prop_assert_eq!(r, Err(RecvError::Empty)),
+                        }
+                    }
+                }
+                prop_assert_eq!(
+                    sender.free_space() + receiver.pending_bytes(),
+                    sender.capacity()
+                );
+            }
+
+            unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+        }
+    }
+
+    /// A core that only sends and one that only receives, each with just its half of the
+    /// transport.
+    #[cfg(not(loom))]
+    #[test]
+    fn test_standalone_halves() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 16;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let messages: &[&[u8]] = &[b"", b"0", b"0123", b"01234567", b"012", b"0123456"];
+
+        let mut sender = unsafe { Sender::<_, ALIGN>::new(shared_region, buf_size as u32, Noop) };
+        let mut receiver = unsafe { Receiver::<ALIGN>::new(shared_region, buf_size as u32) };
+        assert!(receiver.is_empty());
+
+        let recv_thread = thread::spawn(move || {
+            let mut buf = [0; 8];
+            for &expected in messages {
+                loop {
+                    match receiver.try_recv(&mut buf) {
+                        Ok(n) => {
+                            assert_eq!(&buf[..n], expected);
+                            break;
+                        }
+                        Err(RecvError::Empty) => thread::yield_now(),
+                        Err(e) => panic!("{e:?}"),
+                    }
+                }
+            }
+        });
+
+        for &msg in messages {
+            loop {
+                match sender.send(msg) {
+                    Ok(()) => break,
+                    Err(SendError::InsufficientCapacity) => thread::yield_now(),
+                    Err(e) => panic!("{e:?}"),
+                }
+            }
+        }
+        recv_thread.join().unwrap();
+        assert_eq!(sender.free_space(), sender.capacity());
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    /// The bytes the transport writes are the ones the [`protocol`][crate::protocol] module
+    /// describes.
+    #[cfg(not(loom))]
+    #[test]
+    fn test_wire_format() {
+        use crate::protocol::{HEADER_SIZE, capacity, message_len, padded_len};
+
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 64;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region_zeroed(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        assert_eq!(sender.capacity(), capacity(buf_size));
+
+        let messages: &[&[u8]] = &[b"", b"a", b"abcd", b"hello world", b"0123456"];
+        let data = unsafe { shared_region.cast::<u8>().as_ptr().add(Hdr::SIZE) };
+        let mut idx = 0;
+        for &msg in messages {
+            sender.send(msg).unwrap();
+            // The padding and the sequence number byte are unspecified, only look at the rest.
+            let header = unsafe { [*data.add(idx), *data.add(idx + 1), *data.add(idx + 2), 0] };
+            assert_eq!(message_len(header), msg.len());
+            assert_eq!(&header[..2], &(msg.len() as u16).to_be_bytes());
+            assert_eq!(header[2], 0);
+            for (i, &b) in msg.iter().enumerate() {
+                assert_eq!(unsafe { *data.add(idx + HEADER_SIZE + i) }, b);
+            }
+            idx += HEADER_SIZE + padded_len(msg.len());
+            assert_eq!(receiver.pending_bytes(), idx);
+        }
+
+        let region = shared_region.cast::<Hdr>().as_ptr();
+        let wr_idx = unsafe { (*region).wr_idx.value.load(Ordering::Acquire) };
+        assert_eq!(wr_idx as usize, idx);
+        assert_eq!(sender.free_space(), capacity(buf_size) - idx);
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_pad() {
+        use crate::protocol::{HEADER_SIZE, padded_len, padded_len_with};
+        use std::vec::Vec;
+
+        /// Send and receive messages of every length up to 9 through a ring padded to `PAD`, often
+        /// enough to wrap around at every position, and return `wr_idx` after each message.
+        fn send_recv<const PAD: usize>(crc: bool) -> Vec<usize> {
+            const ALIGN: usize = 4;
+            type Hdr = SharedMemoryRegionHeader<ALIGN>;
+            let buf_size = 36;
+            let shared_region_layout =
+                Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+            let shared_region = alloc_region(shared_region_layout);
+            let mut icmsg = unsafe {
+                IcMsgTransport::<_, ALIGN, super::NoBarrier, PAD>::new(
+                    shared_region,
+                    shared_region,
+                    buf_size as u32,
+                    buf_size as u32,
+                    Noop,
+                )
+            };
+            icmsg.set_crc(crc);
+            icmsg.set_sequence(true);
+            let (sender, receiver) = icmsg.split_mut();
+            let trailer_len = if crc { 4 } else { 0 };
+            let mut buf = [0; 16];
+
+            let mut positions = Vec::new();
+            let mut wr_idx = 0;
+            for i in 0..200 {
+                let msg = &b"0123456789"[..i % 10];
+                sender.send(msg).unwrap();
+                wr_idx += HEADER_SIZE + padded_len_with(msg.len(), PAD) + trailer_len;
+                wr_idx %= buf_size;
+                assert_eq!(sender.position() as usize, wr_idx);
+                assert_eq!(receiver.try_recv(&mut buf), Ok(msg.len()));
+                assert_eq!(&buf[..msg.len()], msg);
+                positions.push(wr_idx);
+            }
+
+            unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+            positions
+        }
+
+        for crc in [false, true] {
+            // The same layout as before `PAD` existed.
+            let mut wr_idx = 0;
+            for (i, &position) in send_recv::<4>(crc).iter().enumerate() {
+                let trailer_len = if crc { 4 } else { 0 };
+                wr_idx = (wr_idx + HEADER_SIZE + padded_len(i % 10) + trailer_len) % 36;
+                assert_eq!(position, wr_idx);
+            }
+
+            // Packets start at any multiple of `PAD`, including less than a header before the end
+            // of the ring, so that the header wraps around.
+            let positions = send_recv::<2>(crc);
+            assert!(positions.contains(&34));
+            let positions = send_recv::<1>(crc);
+            assert!([33, 34, 35].iter().all(|idx| positions.contains(idx)));
+        }
+
+        // A smaller `PAD` leaves room for longer messages in the same ring.
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 24;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let sender = unsafe { Sender::<_, ALIGN>::new(shared_region, buf_size as u32, Noop) };
+        assert_eq!(sender.max_message_len(), 16);
+        let sender = unsafe {
+            Sender::<_, ALIGN, super::NoObserver, super::NoBarrier, 2>::new(
+                shared_region,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        assert_eq!(sender.max_message_len(), 18);
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_send_message_too_large() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        for buf_size in [32, 0x10010] {
+            let shared_region_layout =
+                Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+            let shared_region = alloc_region(shared_region_layout);
+            let mut icmsg = unsafe {
+                IcMsgTransport::<_, ALIGN>::new(
+                    shared_region,
+                    shared_region,
+                    buf_size as u32,
+                    buf_size as u32,
+                    Noop,
+                )
+            };
+            let (sender, receiver) = icmsg.split_mut();
+            let msg = std::vec![0x55; buf_size];
+            let mut buf = std::vec![0; buf_size];
+
+            let max_len = sender.max_message_len();
+            assert_eq!(max_len, (buf_size - 8).min(u16::MAX as usize));
+            assert!(!sender.can_send(max_len + 1));
+            assert_eq!(
+                sender.send(&msg[..max_len + 1]),
+                Err(SendError::MessageTooLarge),
+            );
+            assert_eq!(
+                sender.send_vectored(&[&msg[..1], &msg[..max_len]]),
+                Err(SendError::MessageTooLarge),
+            );
+            assert_eq!(sender.free_space(), sender.capacity());
+
+            assert!(sender.can_send(max_len));
+            sender.send(&msg[..max_len]).unwrap();
+            assert_eq!(receiver.try_recv(&mut buf), Ok(max_len));
+            assert_eq!(buf[..max_len], msg[..max_len]);
+
+            unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+        }
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_send_invalid_rd_idx() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, _) = icmsg.split_mut();
+        let rd_idx_ptr = unsafe { &(*shared_region.cast::<Hdr>().as_ptr()).rd_idx.value };
+        let wr_idx_ptr = unsafe { &(*shared_region.cast::<Hdr>().as_ptr()).wr_idx.value };
+
+        sender.send(b"0123").unwrap();
+        for rd_idx in [buf_size as u32, 0xffff_fffc, 2] {
+            rd_idx_ptr.store(rd_idx, Ordering::Relaxed);
+            assert_eq!(sender.send(b"0123"), Err(SendError::InvalidState));
+            let mut sent = 0;
+            assert_eq!(
+                sender.send_fragmented(b"0123", &mut sent),
+                Err(SendError::InvalidState)
+            );
+            assert_eq!(sent, 0);
+            assert_eq!(sender.free_space(), 0);
+            assert_eq!(sender.send_wr_idx, 8);
+            assert_eq!(wr_idx_ptr.load(Ordering::Relaxed), 8);
+        }
+
+        rd_idx_ptr.store(8, Ordering::Relaxed);
+        sender.send(b"0123").unwrap();
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_peek_len() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 32];
+
+        assert_eq!(receiver.peek_len(), Err(RecvError::Empty));
+        sender.send(b"01234").unwrap();
+        sender.send(b"").unwrap();
+        assert_eq!(receiver.peek_len(), Ok(5));
+        assert_eq!(receiver.peek_len(), Ok(5));
+        assert_eq!(receiver.try_recv(&mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"01234");
+        assert_eq!(receiver.peek_len(), Ok(0));
+        assert_eq!(receiver.try_recv(&mut buf), Ok(0));
+        assert_eq!(receiver.peek_len(), Err(RecvError::Empty));
+
+        // a length that doesn't fit in the ring
+        sender.send(b"0").unwrap();
+        unsafe {
+            let data = shared_region.cast::<u8>().as_ptr().add(size_of::<Hdr>());
+            data.add(receiver.recv_rd_idx as usize).write(0xff);
+        }
+        assert_eq!(receiver.peek_len(), Err(RecvError::InvalidMessage));
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_recv_message_too_big() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut small_buf = [0; 4];
+        let mut buf = [0; 32];
+
+        sender.send(b"0123456789").unwrap();
+        sender.send(b"abc").unwrap();
+        assert_eq!(
+            receiver.try_recv(&mut small_buf),
+            Err(RecvError::MessageTooBig { required: 10 }),
+        );
+        assert_eq!(
+            receiver.try_recv(&mut buf[..9]),
+            Err(RecvError::MessageTooBig { required: 10 }),
+        );
+        assert_eq!(receiver.try_recv(&mut buf[..10]), Ok(10));
+        assert_eq!(&buf[..10], b"0123456789");
+        assert_eq!(receiver.try_recv(&mut small_buf), Ok(3));
+        assert_eq!(&small_buf[..3], b"abc");
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_try_recv_partial() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 4];
+
+        assert_eq!(receiver.try_recv_partial(&mut buf), Err(RecvError::Empty));
+        // make the next message wrap around
+        sender.send(&[0; 20]).unwrap();
+        receiver.try_recv(&mut [0; 20]).unwrap();
+
+        sender.send(b"0123456789").unwrap();
+        sender.send(b"abc").unwrap();
+        sender.send(b"").unwrap();
+        assert_eq!(receiver.try_recv_partial(&mut buf), Ok((4, 6)));
+        assert_eq!(&buf, b"0123");
+        assert_eq!(receiver.try_recv_partial(&mut buf), Ok((3, 0)));
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(receiver.try_recv_partial(&mut buf), Ok((0, 0)));
+        assert_eq!(receiver.try_recv_partial(&mut buf), Err(RecvError::Empty));
+        assert_eq!(sender.free_space(), sender.capacity());
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_discard_next() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 128;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 8];
+
+        assert_eq!(receiver.discard_next(), Err(RecvError::Empty));
+        // make the next message wrap around
+        sender.send(&[0; 30]).unwrap();
+        receiver.discard_next().unwrap();
+
+        sender.send(&[0xaa; 100]).unwrap();
+        sender.send(b"0123").unwrap();
+        assert_eq!(
+            receiver.try_recv(&mut buf),
+            Err(RecvError::MessageTooBig { required: 100 }),
+        );
+        assert_eq!(
+            receiver.try_recv(&mut buf),
+            Err(RecvError::MessageTooBig { required: 100 }),
+        );
+        assert_eq!(receiver.discard_next(), Ok(100));
+        assert_eq!(receiver.try_recv(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"0123");
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+        assert_eq!(sender.free_space(), sender.capacity());
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(all(not(loom), feature = "stats"))]
+    #[test]
+    fn test_stats() {
+        use super::Stats;
+
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+
+        let mut buf = [0; 8];
+        let n = 10;
+        for i in 0..n {
+            icmsg.send(&b"0123456"[..i % 8]).unwrap();
+            icmsg.try_recv(&mut buf).unwrap();
+        }
+        let sent_bytes: u32 = (0..n).map(|i| (i % 8) as u32).sum();
+        icmsg.send(b"01234567").unwrap();
+        icmsg.send(b"01234567").unwrap();
+        assert_eq!(icmsg.send(b"0123"), Err(SendError::InsufficientCapacity));
+        assert_eq!(icmsg.split_mut().1.clear(), 2);
+
+        let region = shared_region.cast::<Hdr>().as_ptr();
+        unsafe { (*region).wr_idx.value.store(2, Ordering::Release) };
+        assert_eq!(icmsg.try_recv(&mut buf), Err(RecvError::InvalidState));
+
+        let (sender, receiver) = icmsg.split();
+        let expected = Stats {
+            messages_sent: n as u32 + 2,
+            bytes_sent: sent_bytes + 16,
+            send_full_rejections: 1,
+            // two messages of 8 bytes
+            high_water_mark: 24,
+            ..Stats::default()
+        };
+        assert_eq!(sender.stats(), expected);
+        assert_eq!(sender.high_water_mark(), 24);
+        assert_eq!(sender.high_water_mark_percent(), 77);
+        let expected = Stats {
+            messages_recv: n as u32 + 2,
+            bytes_recv: sent_bytes + 16,
+            recv_invalid: 1,
+            // one message of 7 bytes, the two bigger ones were cleared without being received
+            high_water_mark: 12,
+            ..Stats::default()
+        };
+        assert_eq!(receiver.stats(), expected);
+        assert_eq!(receiver.high_water_mark_percent(), 38);
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_observer() {
+        use super::Observer;
+
+        #[derive(Default)]
+        struct Counter {
+            sent: usize,
+            recv: usize,
+            full: u32,
+            notified: u32,
+        }
+
+        impl Observer for Counter {
+            fn on_send(&mut self, len: usize) {
+                self.sent += len;
+            }
+            fn on_recv(&mut self, len: usize) {
+                self.recv += len;
+            }
+            fn on_full(&mut self) {
+                self.full += 1;
+            }
+            fn on_notify(&mut self) {
+                self.notified += 1;
+            }
+        }
+
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split();
+        let mut sender = sender.with_observer(Counter::default());
+        let mut receiver = receiver.with_observer(Counter::default());
+
+        let mut buf = [0; 8];
+        sender.send(b"012").unwrap();
+        sender.send_no_notify(b"01234567").unwrap();
+        assert_eq!(
+            sender.send(b"01234567"),
+            Err(SendError::InsufficientCapacity)
+        );
+        assert_eq!(receiver.try_recv(&mut buf), Ok(3));
+        assert_eq!(receiver.clear(), 1);
+
+        let sender = sender.observer();
+        assert_eq!((sender.sent, sender.full, sender.notified), (11, 1, 1));
+        assert_eq!(receiver.observer().recv, 11);
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_send_typed() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 8];
+
+        sender.send_typed(b"012", 0xa5).unwrap();
+        sender.send(b"0123").unwrap();
+        assert_eq!(receiver.try_recv_typed(&mut buf), Ok((3, 0xa5)));
+        assert_eq!(&buf[..3], b"012");
+        assert_eq!(receiver.try_recv_typed(&mut buf), Ok((4, 0)));
+        assert_eq!(&buf[..4], b"0123");
+
+        sender.send_typed(b"01", 1).unwrap();
+        assert_eq!(
+            receiver.try_recv_typed(&mut buf[..1]),
+            Err(RecvError::MessageTooBig { required: 2 })
+        );
+        // plain receives ignore the flags
+        assert_eq!(receiver.try_recv(&mut buf), Ok(2));
+        assert_eq!(receiver.try_recv_typed(&mut buf), Err(RecvError::Empty));
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_data_sync() {
+        use core::cell::Cell;
+
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let calls = Cell::new(0);
+        let icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let mut icmsg = icmsg.with_barrier(|| calls.set(calls.get() + 1));
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 8];
+
+        sender.send(b"0123").unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(receiver.try_recv(&mut buf), Ok(4));
+        assert_eq!(calls.get(), 2);
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_crc() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        icmsg.set_crc(true);
+        let (sender, receiver) = icmsg.split_mut();
+        let data = unsafe { shared_region.cast::<u8>().as_ptr().add(size_of::<Hdr>()) };
+        let mut buf = [0; 32];
+
+        // the trailer takes space in the ring
+        assert_eq!(sender.max_message_len(), 20);
+        sender.send(b"01234").unwrap();
+        assert_eq!(sender.free_space(), 31 - 16);
+        assert_eq!(receiver.try_recv(&mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"01234");
+
+        // corrupted payload
+        sender.send(b"0123456789").unwrap();
+        unsafe { data.add(25).write(b'x') };
+        assert_eq!(receiver.peek_len(), Err(RecvError::CrcMismatch));
+        // the packet stays in the ring
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::CrcMismatch));
+        unsafe { data.add(25).write(b'5') };
+        assert_eq!(receiver.try_recv(&mut buf), Ok(10));
+        assert_eq!(&buf[..10], b"0123456789");
+
+        // corrupted length
+        sender.send(b"0123").unwrap();
+        unsafe { data.add(receiver.recv_rd_idx as usize + 1).write(3) };
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::CrcMismatch));
+        assert_eq!(receiver.clear(), 1);
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+
+        // corrupted sequence byte, which is sent as 0 without sequence numbers
+        sender.send(b"0123").unwrap();
+        let seq = unsafe { data.add(receiver.recv_rd_idx as usize + 3) };
+        assert_eq!(unsafe { seq.read() }, 0);
+        unsafe { seq.write(1) };
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::CrcMismatch));
+        unsafe { seq.write(0) };
+        assert_eq!(receiver.try_recv(&mut buf), Ok(4));
+
+        sender.send(b"").unwrap();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(0));
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_zero_length_message() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 16;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+
+        // a zero-length message is just a header, and is not the same as an empty ring
+        assert_eq!(receiver.peek_len(), Err(RecvError::Empty));
+        sender.send(b"").unwrap();
+        assert_eq!(sender.free_space(), 15 - 4);
+        assert_eq!(receiver.peek_len(), Ok(0));
+        assert_eq!(receiver.try_recv(&mut []), Ok(0));
+        assert_eq!(receiver.try_recv(&mut []), Err(RecvError::Empty));
+
+        // the ring fills up with zero-length messages, the last header ending exactly at the end
+        // of the buffer
+        for _ in 0..3 {
+            sender.send(b"").unwrap();
+        }
+        assert_eq!(sender.send_wr_idx, 0);
+        assert_eq!(sender.send(b""), Err(SendError::InsufficientCapacity));
+        for _ in 0..3 {
+            assert_eq!(receiver.try_recv(&mut []), Ok(0));
+        }
+        assert_eq!(receiver.recv_rd_idx, 0);
+        assert_eq!(receiver.try_recv(&mut []), Err(RecvError::Empty));
+
+        // with a CRC, the header is at the end of the buffer and the trailer wraps to the start
+        icmsg.set_crc(true);
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 8];
+        sender.send(b"0123").unwrap();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(4));
+        sender.send(b"").unwrap();
+        assert_eq!(sender.send_wr_idx, 4);
+        assert_eq!(receiver.try_recv(&mut buf), Ok(0));
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_sequence() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        icmsg.set_sequence(true);
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 32];
+
+        // the first message is accepted whatever its number, and the numbers wrap around
+        sender.seq = Some(250);
+        for i in 0..10u8 {
+            sender.send(&[i]).unwrap();
+            assert_eq!(receiver.try_recv(&mut buf), Ok(1));
+            assert_eq!(buf[0], i);
+        }
+        assert_eq!(receiver.expected_seq, Some(4));
+
+        // lost messages
+        sender.seq = Some(7);
+        sender.send(b"a").unwrap();
+        sender.send(b"b").unwrap();
+        assert_eq!(
+            receiver.peek_len(),
+            Err(RecvError::SequenceGap {
+                expected: 4,
+                got: 7
+            }),
+        );
+        assert_eq!(receiver.try_recv(&mut buf), Ok(1));
+        assert_eq!(&buf[..1], b"a");
+        assert_eq!(receiver.try_recv(&mut buf), Ok(1));
+        assert_eq!(&buf[..1], b"b");
+
+        // both sides bonded again
+        sender.reset();
+        receiver.reset();
+        sender.send(b"c").unwrap();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(1));
+        assert_eq!(receiver.expected_seq, Some(1));
+        // the other side restarted numbering without bonding again
+        sender.seq = Some(0);
+        sender.send(b"d").unwrap();
+        assert_eq!(
+            receiver.try_recv(&mut buf),
+            Err(RecvError::SequenceGap {
+                expected: 1,
+                got: 0
+            }),
+        );
+        assert_eq!(receiver.try_recv(&mut buf), Ok(1));
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_send_fragmented() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 24;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+
+        // Several times the size of the ring.
+        let msg: [u8; 100] = core::array::from_fn(|i| i as u8);
+        assert_eq!(
+            sender.send(&msg),
+            Err(SendError::MessageTooLarge),
+            "message should not fit without fragmentation"
+        );
+        let mut buf = [0; 128];
+        let mut sent = 0;
+        let mut received = 0;
+        loop {
+            match sender.send_fragmented(&msg, &mut sent) {
+                Ok(()) | Err(SendError::InsufficientCapacity) => {}
+                Err(e) => panic!("send error: {e:?}"),
+            }
+            match receiver.try_recv_reassembled(&mut buf, &mut received) {
+                Ok(n) => {
+                    assert_eq!(&buf[..n], &msg);
+                    break;
+                }
+                Err(RecvError::Empty) => {}
+                Err(e) => panic!("recv error: {e:?}"),
+            }
+        }
+        assert_eq!(sent, msg.len());
+
+        // small messages go out as a single unfragmented packet
+        let mut sent = 0;
+        sender.send_fragmented(b"012", &mut sent).unwrap();
+        assert_eq!(receiver.try_recv_typed(&mut buf), Ok((3, 0)));
+
+        let mut sent = 0;
+        let mut received = 0;
+        assert_eq!(
+            sender.send_fragmented(&msg, &mut sent),
+            Err(SendError::InsufficientCapacity)
+        );
+        assert_eq!(
+            receiver.try_recv_reassembled(&mut buf[..10], &mut received),
+            Err(RecvError::MessageTooBig { required: sent })
+        );
+        assert_eq!(received, 0);
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_send_vectored() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 64;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 64];
+
+        let part1 = b"\x01";
+        let part2 = b"0123456789abcdefghijklmnopqrstu";
+        let expected = [&part1[..], &part2[..]].concat();
+
+        // start at every offset so that the wraparound lands before, inside, and between parts
+        for _ in 0..buf_size / 4 {
+            sender.send_vectored(&[part1, &[], part2]).unwrap();
+            assert_eq!(receiver.try_recv(&mut buf), Ok(expected.len()));
+            assert_eq!(&buf[..expected.len()], expected);
+            assert_eq!(sender.free_space(), sender.capacity());
+
+            sender.send(&[]).unwrap();
+            receiver.try_recv(&mut buf).unwrap();
+        }
+
+        sender.send_vectored(&[part2]).unwrap();
+        assert_eq!(
+            sender.send_vectored(&[part1, part2]),
+            Err(SendError::InsufficientCapacity),
+        );
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_recv_vectored() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 64;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+
+        let msg = b"\x01\x02\x03\x04456789abcdefghijklmnopqrstu";
+
+        // start at every offset so that the wraparound lands before, inside, and between slices
+        for _ in 0..buf_size / 4 {
+            let mut header = [0; 4];
+            let mut empty = [0; 0];
+            let mut payload = [0; 40];
+            sender.send(msg).unwrap();
+            assert_eq!(
+                receiver.try_recv_vectored(&mut [&mut header, &mut empty, &mut payload]),
+                Ok(msg.len()),
+            );
+            assert_eq!(&header, &msg[..4]);
+            assert_eq!(&payload[..msg.len() - 4], &msg[4..]);
+            assert!(receiver.is_empty());
+
+            sender.send(&[]).unwrap();
+            receiver.try_recv(&mut payload).unwrap();
+        }
+
+        sender.send(msg).unwrap();
+        let mut header = [0; 4];
+        let mut payload = [0; 26];
+        assert_eq!(
+            receiver.try_recv_vectored(&mut [&mut header, &mut payload]),
+            Err(RecvError::MessageTooBig {
+                required: msg.len(),
+            }),
+        );
+        let mut payload = [0; 27];
+        assert_eq!(
+            receiver.try_recv_vectored(&mut [&mut header, &mut payload]),
+            Ok(msg.len()),
+        );
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_recv_uninit() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [core::mem::MaybeUninit::uninit(); 8];
+
+        sender.send(b"0123456789").unwrap();
+        assert_eq!(
+            receiver.try_recv_uninit(&mut buf),
+            Err(RecvError::MessageTooBig { required: 10 }),
+        );
+        receiver.discard_next().unwrap();
+
+        sender.send(b"01234").unwrap();
+        assert_eq!(receiver.try_recv_uninit(&mut buf), Ok(5));
+        assert_eq!(unsafe { buf[..5].assume_init_ref() }, b"01234");
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_recv_many() {
+        use core::ops::ControlFlow;
+
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 64;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let region = shared_region.cast::<Hdr>().as_ptr();
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut scratch = [0; 8];
+        let mut buf = [0; 8];
+        let rd_idx = || unsafe { (*region).rd_idx.value.load(Ordering::Relaxed) };
+
+        assert_eq!(
+            receiver.try_recv_many(&mut scratch, |_| unreachable!()),
+            Err(RecvError::Empty)
+        );
+
+        // All messages are received, and rd_idx is only published at the end.
+        let messages: [&[u8]; 3] = [b"0000", b"1111", b"2222"];
+        assert_eq!(sender.send_all(messages), Ok(3));
+        let mut i = 0;
+        let r = receiver.try_recv_many(&mut scratch, |msg| {
+            assert_eq!(msg, messages[i]);
+            assert_eq!(rd_idx(), 0);
+            i += 1;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(r, Ok(3));
+        assert_eq!(rd_idx(), 24);
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+
+        // Breaking leaves the rest queued.
+        assert_eq!(sender.send_all(messages), Ok(3));
+        let r = receiver.try_recv_many(&mut scratch, |msg| {
+            assert_eq!(msg, messages[0]);
+            ControlFlow::Break(())
+        });
+        assert_eq!(r, Ok(1));
+        for msg in &messages[1..] {
+            let len = receiver.try_recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], *msg);
+        }
+
+        // A message that doesn't fit stops the batch and stays queued.
+        sender.send(b"0000").unwrap();
+        sender.send(b"0123456789").unwrap();
+        let r = receiver.try_recv_many(&mut scratch, |msg| {
+            assert_eq!(msg, b"0000");
+            ControlFlow::Continue(())
+        });
+        assert_eq!(r, Err(RecvError::MessageTooBig { required: 10 }));
+        assert_eq!(receiver.discard_next(), Ok(10));
+
+        // An invalid packet is reported after the messages before it were delivered.
+        assert_eq!(sender.send_all(messages), Ok(3));
+        let second_header = unsafe {
+            region
+                .cast::<u8>()
+                .add(size_of::<Hdr>() + (rd_idx() as usize + 8) % buf_size)
+        };
+        unsafe { second_header.write(0xff) };
+        let r = receiver.try_recv_many(&mut scratch, |msg| {
+            assert_eq!(msg, messages[0]);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(r, Err(RecvError::InvalidMessage));
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::InvalidMessage));
 
-impl core::fmt::Display for SendError {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        match self {
-            SendError::InsufficientCapacity => write!(f, "insufficient capacity"),
-            SendError::InvalidState => write!(f, "invalid state"),
-        }
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
     }
-}
 
-impl core::error::Error for SendError {}
+    #[cfg(not(loom))]
+    #[test]
+    fn test_recv_batch() {
+        use std::{panic, vec::Vec};
 
-impl embedded_io::Error for SendError {
-    fn kind(&self) -> embedded_io::ErrorKind {
-        match &self {
-            Self::InsufficientCapacity => embedded_io::ErrorKind::WriteZero,
-            Self::InvalidState => embedded_io::ErrorKind::Other,
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 64;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let region = shared_region.cast::<Hdr>().as_ptr();
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut scratch = [0; 16];
+        let mut buf = [0; 16];
+        let rd_idx = || unsafe { (*region).rd_idx.value.load(Ordering::Relaxed) };
+
+        assert_eq!(
+            receiver.try_recv_batch(&mut scratch, |_| unreachable!()),
+            Err(RecvError::Empty)
+        );
+
+        // rd_idx is only published at the end
+        let messages: [&[u8]; 3] = [b"0000", b"1111", b"2222"];
+        assert_eq!(sender.send_all(messages), Ok(3));
+        let mut received = Vec::new();
+        let r = receiver.try_recv_batch(&mut scratch, |msg| {
+            assert_eq!(rd_idx(), 0);
+            received.push(msg.to_vec());
+        });
+        assert_eq!(r, Ok(3));
+        assert_eq!(received, messages);
+        assert_eq!(rd_idx(), 24);
+
+        // messages sent during the batch are left for the next one
+        sender.send(b"0000").unwrap();
+        let r = receiver.try_recv_batch(&mut scratch, |msg| {
+            assert_eq!(msg, b"0000");
+            sender.send(b"1111").unwrap();
+        });
+        assert_eq!(r, Ok(1));
+        assert_eq!(receiver.try_recv(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"1111");
+        assert_eq!(rd_idx(), 40);
+
+        // the second message wraps around the end of the ring
+        assert_eq!(sender.send_all([&b"01234567"[..], b"abcdefghij"]), Ok(2));
+        assert_eq!(sender.send_wr_idx, 4);
+        let mut received = Vec::new();
+        let r = receiver.try_recv_batch(&mut scratch, |msg| received.push(msg.to_vec()));
+        assert_eq!(r, Ok(2));
+        assert_eq!(received, [&b"01234567"[..], b"abcdefghij"]);
+        assert_eq!(rd_idx(), 4);
+
+        // a panic in the callback leaves the message it panicked on queued
+        assert_eq!(sender.send_all(messages), Ok(3));
+        let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            receiver.try_recv_batch(&mut scratch, |msg| assert_eq!(msg, b"0000"))
+        }));
+        assert!(r.is_err());
+        assert_eq!(rd_idx(), 12);
+        assert_eq!(receiver.recv_rd_idx, rd_idx());
+        for msg in &messages[1..] {
+            let len = receiver.try_recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], *msg);
         }
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
     }
-}
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum RecvError {
-    /// The message was bigger than the provided buffer.
-    MessageTooBig,
-    /// There were no messages to receive.
-    Empty,
-    /// An invalid message was received. e.g. a packet with a length greater than the shared memory
-    /// memory region. This is a fatal error, likely caused by a bug in the channel implementation.
-    InvalidMessage,
-}
+    #[cfg(not(loom))]
+    #[tokio::main]
+    #[test]
+    async fn test_recv_async() {
+        use tokio::sync::Notify;
 
-impl core::fmt::Display for RecvError {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        match self {
-            RecvError::MessageTooBig => write!(f, "message too big"),
-            RecvError::Empty => write!(f, "empty"),
-            RecvError::InvalidMessage => write!(f, "invalid message"),
-        }
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let notify = Notify::new();
+        let mut waiter = &notify;
+        let mut buf = [0; 8];
+
+        // a message that is already there is returned without waiting
+        sender.send(b"first").unwrap();
+        assert_eq!(receiver.recv(&mut buf, &mut waiter).await, Ok(5));
+
+        let (r, ()) = tokio::join!(receiver.recv(&mut buf, &mut waiter), async {
+            tokio::task::yield_now().await;
+            sender.send(b"second").unwrap();
+            notify.notify_one();
+        });
+        assert_eq!(r, Ok(6));
+        assert_eq!(&buf[..6], b"second");
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
     }
-}
 
-impl core::error::Error for RecvError {}
+    #[cfg(not(loom))]
+    #[test]
+    fn test_send_no_notify() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 64;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let notifications = core::cell::Cell::new(0);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                CountNotifier(&notifications),
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 8];
 
-impl embedded_io::Error for RecvError {
-    fn kind(&self) -> embedded_io::ErrorKind {
-        match &self {
-            Self::MessageTooBig => embedded_io::ErrorKind::OutOfMemory,
-            Self::Empty => embedded_io::ErrorKind::Interrupted,
-            Self::InvalidMessage => embedded_io::ErrorKind::Other,
+        let messages: [&[u8]; 4] = [b"0", b"12", b"345", b"6789"];
+        for msg in messages {
+            sender.send_no_notify(msg).unwrap();
         }
-    }
-}
-
-#[repr(C)]
-pub struct SharedMemoryRegionHeader<const ALIGN: usize>
-where
-    elain::Align<ALIGN>: elain::Alignment,
-{
-    rd_idx: Index<ALIGN>,
-    wr_idx: Index<ALIGN>,
-}
+        assert_eq!(notifications.get(), 0);
+        sender.notify();
+        assert_eq!(notifications.get(), 1);
 
-#[repr(C)]
-struct Index<const ALIGN: usize>
-where
-    elain::Align<ALIGN>: elain::Alignment,
-{
-    _align: elain::Align<ALIGN>,
-    value: LeAtomicU32,
-}
+        for msg in messages {
+            let len = receiver.try_recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], msg);
+        }
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
 
-#[repr(C)]
-struct PacketHeader {
-    len: BeU16,
-    _reserved: [MaybeUninit<u8>; 2],
-}
+        sender.send(b"0").unwrap();
+        assert_eq!(notifications.get(), 2);
 
-impl PacketHeader {
-    fn new(len: u16) -> Self {
-        Self {
-            len: len.into(),
-            _reserved: [MaybeUninit::uninit(); 2],
-        }
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
     }
-}
 
-pub trait Notifier {
-    fn notify(&mut self);
-}
+    #[cfg(not(loom))]
+    #[test]
+    fn test_send_all() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 64;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let notifications = core::cell::Cell::new(0);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                CountNotifier(&notifications),
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 16];
+
+        // 5 packets of 8 bytes and one of 20 exactly fill the 60 usable bytes of the ring.
+        let messages: [&[u8]; 7] = [
+            b"0000",
+            b"1111",
+            b"2222",
+            b"3333",
+            b"4444",
+            b"5555555555555555",
+            b"6",
+        ];
+        assert_eq!(sender.send_all(messages), Ok(6));
+        assert_eq!(notifications.get(), 1);
+        assert_eq!(sender.free_space(), 3);
+
+        assert_eq!(
+            sender.send_all([&b"6"[..]]),
+            Err(SendError::InsufficientCapacity)
+        );
+        assert_eq!(notifications.get(), 1);
+
+        for msg in &messages[..6] {
+            let len = receiver.try_recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], *msg);
+        }
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
 
-mod integer {
-    use crate::loom::sync::atomic::{AtomicU32, Ordering};
+        assert_eq!(sender.send_all([]), Ok(0));
+        assert_eq!(notifications.get(), 1);
 
-    /// A big-endian u16.
-    #[repr(transparent)]
-    pub struct BeU16(u16);
+        assert_eq!(sender.send_all(messages[6..].iter().copied()), Ok(1));
+        assert_eq!(notifications.get(), 2);
+        let len = receiver.try_recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"6");
 
-    impl BeU16 {
-        pub fn value(self) -> u16 {
-            self.into()
-        }
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
     }
 
-    impl From<u16> for BeU16 {
-        fn from(t: u16) -> Self {
-            BeU16(t.to_be())
-        }
+    #[cfg(not(loom))]
+    #[test]
+    fn test_reserve() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region_zeroed(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 32];
+
+        let mut slot = sender.reserve(5).unwrap();
+        assert_eq!(slot.len(), 5);
+        let (first, second) = slot.as_mut_slices();
+        assert_eq!((first.len(), second.len()), (5, 0));
+        first.write_copy_of_slice(b"01234");
+        slot.commit();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"01234");
+
+        // dropping the slot rolls back the reservation
+        sender
+            .reserve(8)
+            .unwrap()
+            .as_mut_slices()
+            .0
+            .fill(core::mem::MaybeUninit::new(0xff));
+        assert_eq!(sender.free_space(), sender.capacity());
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+
+        // wr_idx is at 12, so a 24 byte message wraps around after 16 bytes
+        let mut slot = sender.reserve(24).unwrap();
+        let (first, second) = slot.as_mut_slices();
+        assert_eq!((first.len(), second.len()), (16, 8));
+        first.write_copy_of_slice(b"0123456789abcdef");
+        second.write_copy_of_slice(b"ghijklmn");
+        slot.commit();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(24));
+        assert_eq!(&buf[..24], b"0123456789abcdefghijklmn");
+
+        sender.send(&[0; 12]).unwrap();
+        assert!(matches!(
+            sender.reserve(12),
+            Err(SendError::InsufficientCapacity),
+        ));
+        assert!(matches!(
+            sender.reserve(sender.max_message_len() + 1),
+            Err(SendError::MessageTooLarge),
+        ));
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
     }
 
-    impl From<BeU16> for u16 {
-        fn from(t: BeU16) -> Self {
-            u16::from_be(t.0)
-        }
-    }
+    #[cfg(not(loom))]
+    #[test]
+    fn test_clear() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let mut icmsg = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        };
+        let (sender, receiver) = icmsg.split_mut();
+        let mut buf = [0; 32];
 
-    /// An atomic little-endian u32.
-    #[repr(transparent)]
-    pub struct LeAtomicU32(AtomicU32);
+        assert_eq!(receiver.clear(), 0);
 
-    impl LeAtomicU32 {
-        pub fn new(value: u32) -> Self {
-            Self(AtomicU32::new(value.to_le()))
-        }
-        pub fn load(&self, order: Ordering) -> u32 {
-            u32::from_le(self.0.load(order))
-        }
-        pub fn store(&self, val: u32, order: Ordering) {
-            self.0.store(val.to_le(), order)
-        }
-    }
-}
+        // make the following messages wrap around
+        sender.send(&[0; 16]).unwrap();
+        receiver.try_recv(&mut buf).unwrap();
 
-#[cfg(test)]
-pub mod tests {
-    extern crate std;
+        sender.send(b"0123456").unwrap();
+        sender.send(b"").unwrap();
+        sender.send(b"01").unwrap();
+        assert_eq!(receiver.clear(), 3);
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+        assert_eq!(sender.free_space(), sender.capacity());
 
-    use super::{IcMsgTransport, Notifier, RecvError, SharedMemoryRegionHeader};
-    use core::{alloc::Layout, mem::offset_of};
-    use crate::loom::{alloc, thread};
+        sender.send(b"0123").unwrap();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"0123");
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+    }
 
+    #[cfg(not(loom))]
     #[test]
-    fn test_alignment() {
-        assert_eq!(offset_of!(SharedMemoryRegionHeader<128>, rd_idx), 0);
-        assert_eq!(offset_of!(SharedMemoryRegionHeader<128>, wr_idx), 128);
+    fn test_recv_desync() {
+        const ALIGN: usize = 4;
+        type Hdr = SharedMemoryRegionHeader<ALIGN>;
+        let buf_size = 32;
+        let shared_region_layout =
+            Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+        let shared_region = alloc_region(shared_region_layout);
+        let (mut sender, _) = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        }
+        .split();
+        let (_, mut receiver) = unsafe {
+            IcMsgTransport::<_, ALIGN>::new(
+                shared_region,
+                shared_region,
+                buf_size as u32,
+                buf_size as u32,
+                Noop,
+            )
+        }
+        .split();
+        let region = shared_region.cast::<Hdr>().as_ptr();
+        let mut buf = [0; 8];
+
+        // wr_idx moves backwards
+        sender.send(b"0123").unwrap();
+        sender.send(b"0123").unwrap();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(4));
+        unsafe { (*region).wr_idx.value.store(12, Ordering::Release) };
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Desync));
+        // the error is sticky
+        unsafe { (*region).wr_idx.value.store(16, Ordering::Release) };
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Desync));
+
+        // the other side restarts
+        receiver.reset();
+        sender.reset();
+        sender.send(b"0123").unwrap();
+        sender.send(b"0123").unwrap();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(4));
+        sender.reset();
+        sender.send(b"0").unwrap();
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Desync));
+
+        receiver.reset();
+        assert_eq!(receiver.try_recv(&mut buf), Ok(1));
+        assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+
+        unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
     }
 
     #[cfg(not(loom))]
@@ -422,6 +3782,119 @@ pub mod tests {
         loom::model(|| _test_send_recv());
     }
 
+    /// Messages that don't fit in the ring at the same time, so the sender has to wait for the
+    /// receiver to free up space, and that wrap around the end of the ring in different places.
+    #[cfg(loom)]
+    #[test]
+    fn test_send_recv_wraparound_loom() {
+        loom::model(|| {
+            const ALIGN: usize = 4;
+            type Hdr = SharedMemoryRegionHeader<ALIGN>;
+            let buf_size = 16;
+            let shared_region_layout =
+                Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+            let shared_region = alloc_region(shared_region_layout);
+            let icmsg = unsafe {
+                IcMsgTransport::<_, ALIGN>::new(
+                    shared_region,
+                    shared_region,
+                    buf_size as u32,
+                    buf_size as u32,
+                    Noop,
+                )
+            };
+            let (mut sender, mut receiver) = icmsg.split();
+            let lens = [3, 6, 1];
+
+            let recv_thread = thread::spawn({
+                let receiver = SyncThing(&mut receiver as *mut super::Receiver<ALIGN>);
+                move || {
+                    let receiver = unsafe { &mut *{ receiver }.0 };
+                    let mut buf = [0; 8];
+                    for (i, len) in lens.into_iter().enumerate() {
+                        let n = loop {
+                            match receiver.try_recv(&mut buf) {
+                                Err(RecvError::Empty) => thread::yield_now(),
+                                r => break r.unwrap(),
+                            }
+                        };
+                        assert_eq!(n, len);
+                        assert!(buf[..n].iter().all(|&b| b == i as u8));
+                    }
+                    assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+                }
+            });
+
+            for (i, len) in lens.into_iter().enumerate() {
+                let msg = [i as u8; 8];
+                while sender.send(&msg[..len]) == Err(SendError::InsufficientCapacity) {
+                    thread::yield_now();
+                }
+            }
+            recv_thread.join().unwrap();
+
+            unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+        });
+    }
+
+    /// Like the wraparound test, but with the batched send and receive, which only publish the
+    /// indices once per batch.
+    #[cfg(loom)]
+    #[test]
+    fn test_send_all_recv_many_loom() {
+        loom::model(|| {
+            const ALIGN: usize = 4;
+            type Hdr = SharedMemoryRegionHeader<ALIGN>;
+            let buf_size = 16;
+            let shared_region_layout =
+                Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
+            let shared_region = alloc_region(shared_region_layout);
+            let icmsg = unsafe {
+                IcMsgTransport::<_, ALIGN>::new(
+                    shared_region,
+                    shared_region,
+                    buf_size as u32,
+                    buf_size as u32,
+                    Noop,
+                )
+            };
+            let (mut sender, mut receiver) = icmsg.split();
+            let msgs: [&[u8]; 3] = [&[0; 3], &[1; 1], &[2; 6]];
+
+            let recv_thread = thread::spawn({
+                let receiver = SyncThing(&mut receiver as *mut super::Receiver<ALIGN>);
+                move || {
+                    let receiver = unsafe { &mut *{ receiver }.0 };
+                    let mut buf = [0; 8];
+                    let mut received = 0;
+                    while received < msgs.len() {
+                        let r = receiver.try_recv_many(&mut buf, |msg| {
+                            assert_eq!(msg, msgs[received]);
+                            received += 1;
+                            core::ops::ControlFlow::Continue(())
+                        });
+                        match r {
+                            Err(RecvError::Empty) => thread::yield_now(),
+                            r => assert!(r.unwrap() > 0),
+                        }
+                    }
+                    assert_eq!(receiver.try_recv(&mut buf), Err(RecvError::Empty));
+                }
+            });
+
+            let mut sent = 0;
+            while sent < msgs.len() {
+                match sender.send_all(msgs[sent..].iter().copied()) {
+                    Err(SendError::InsufficientCapacity) => thread::yield_now(),
+                    r => sent += r.unwrap(),
+                }
+            }
+            recv_thread.join().unwrap();
+
+            unsafe { alloc::dealloc(shared_region.as_ptr().cast(), shared_region_layout) };
+        });
+    }
+
     fn _test_send_recv() {
         #[cfg(not(loom))]
         let expected_messages: &[&[u8]] = &[
@@ -447,8 +3920,8 @@ pub mod tests {
         let buf_size = 16;
         let shared_region_layout =
             Layout::from_size_align(size_of::<Hdr>() + buf_size, align_of::<Hdr>()).unwrap();
-        let shared_region_1 = unsafe { alloc::alloc(shared_region_layout) }.cast::<()>();
-        let shared_region_2 = unsafe { alloc::alloc(shared_region_layout) }.cast::<()>();
+        let shared_region_1 = alloc_region(shared_region_layout);
+        let shared_region_2 = alloc_region(shared_region_layout);
         let shared_region_sync_1 = SyncThing(shared_region_1);
         let shared_region_sync_2 = SyncThing(shared_region_2);
 
@@ -511,8 +3984,8 @@ pub mod tests {
         recv_thread.join().unwrap();
 
         unsafe {
-            alloc::dealloc(shared_region_1.cast(), shared_region_layout);
-            alloc::dealloc(shared_region_2.cast(), shared_region_layout);
+            alloc::dealloc(shared_region_1.as_ptr().cast(), shared_region_layout);
+            alloc::dealloc(shared_region_2.as_ptr().cast(), shared_region_layout);
         }
     }
 
@@ -524,12 +3997,36 @@ pub mod tests {
         }
     }
 
+    #[cfg(not(loom))]
+    struct CountNotifier<'a>(&'a core::cell::Cell<u32>);
+
+    #[cfg(not(loom))]
+    impl Notifier for CountNotifier<'_> {
+        fn notify(&mut self) {
+            self.0.set(self.0.get() + 1)
+        }
+    }
+
     struct Noop;
 
     impl Notifier for Noop {
         fn notify(&mut self) {}
     }
 
+    /// Allocate a shared memory region with `layout`.
+    pub(crate) fn alloc_region(layout: Layout) -> NonNull<()> {
+        NonNull::new(unsafe { alloc::alloc(layout) })
+            .unwrap()
+            .cast()
+    }
+
+    /// Allocate a zeroed shared memory region with `layout`.
+    pub(crate) fn alloc_region_zeroed(layout: Layout) -> NonNull<()> {
+        NonNull::new(unsafe { alloc::alloc_zeroed(layout) })
+            .unwrap()
+            .cast()
+    }
+
     /// Make something unconditionally Send + Sync. Use with care.
     #[derive(Copy, Clone)]
     pub(crate) struct SyncThing<T>(pub T);
@@ -0,0 +1,189 @@
+//! Channels of typed messages, serialized with [postcard].
+
+use core::marker::PhantomData;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    IcMsg, Notifier, Receiver, Sender, WaitForNotify,
+    transport::{RecvError, SendError},
+};
+
+impl<M, W, const ALIGN: usize> IcMsg<M, W, ALIGN>
+where
+    M: Notifier,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Split into a sender and a receiver of messages of type `T`, each serialized into at most
+    /// `N` bytes.
+    pub fn split_typed<T, const N: usize>(
+        self,
+    ) -> (TypedSender<T, M, ALIGN, N>, TypedReceiver<T, W, ALIGN, N>)
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let (sender, receiver) = self.split();
+        (TypedSender::new(sender), TypedReceiver::new(receiver))
+    }
+}
+
+/// A [`Sender`] of values of type `T`.
+///
+/// Each value is serialized into a buffer of `N` bytes and sent as one message.
+pub struct TypedSender<T, M, const ALIGN: usize, const N: usize>
+where
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    sender: Sender<M, ALIGN>,
+    buf: [u8; N],
+    _marker: PhantomData<fn(&T)>,
+}
+
+impl<T, M, const ALIGN: usize, const N: usize> TypedSender<T, M, ALIGN, N>
+where
+    T: Serialize,
+    M: Notifier,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn new(sender: Sender<M, ALIGN>) -> Self {
+        Self {
+            sender,
+            buf: [0; N],
+            _marker: PhantomData,
+        }
+    }
+
+    /// Serialize and send a value.
+    pub fn send(&mut self, value: &T) -> Result<(), TypedSendError> {
+        let msg = postcard::to_slice(value, &mut self.buf).map_err(|e| match e {
+            postcard::Error::SerializeBufferFull => TypedSendError::TooLarge,
+            e => TypedSendError::Serialize(e),
+        })?;
+        Ok(self.sender.send(msg)?)
+    }
+
+    pub fn into_inner(self) -> Sender<M, ALIGN> {
+        self.sender
+    }
+}
+
+/// A [`Receiver`] of values of type `T`.
+///
+/// Each message is received into a buffer of `N` bytes and deserialized.
+pub struct TypedReceiver<T, W, const ALIGN: usize, const N: usize>
+where
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    receiver: Receiver<W, ALIGN>,
+    buf: [u8; N],
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, W, const ALIGN: usize, const N: usize> TypedReceiver<T, W, ALIGN, N>
+where
+    T: DeserializeOwned,
+    W: WaitForNotify,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn new(receiver: Receiver<W, ALIGN>) -> Self {
+        Self {
+            receiver,
+            buf: [0; N],
+            _marker: PhantomData,
+        }
+    }
+
+    /// Try to receive a value if one is available.
+    ///
+    /// A message that doesn't fit in the buffer is discarded and reported as
+    /// [`TypedRecvError::TooLarge`], so that the next call continues with the following message.
+    pub fn try_recv(&mut self) -> Result<T, TypedRecvError> {
+        let r = self.receiver.try_recv(&mut self.buf);
+        self.deserialize(r)
+    }
+
+    /// Wait for and receive a value. See [`try_recv`][Self::try_recv].
+    pub async fn recv(&mut self) -> Result<T, TypedRecvError> {
+        let r = self.receiver.recv(&mut self.buf).await;
+        self.deserialize(r)
+    }
+
+    pub fn into_inner(self) -> Receiver<W, ALIGN> {
+        self.receiver
+    }
+
+    fn deserialize(&mut self, r: Result<usize, RecvError>) -> Result<T, TypedRecvError> {
+        let n = match r {
+            Ok(n) => n,
+            Err(RecvError::MessageTooBig { required }) => {
+                self.receiver.discard_next()?;
+                return Err(TypedRecvError::TooLarge { required });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        postcard::from_bytes(&self.buf[..n]).map_err(TypedRecvError::Deserialize)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TypedSendError {
+    Send(SendError),
+    /// The serialized value does not fit in the sender's buffer.
+    TooLarge,
+    /// The value could not be serialized.
+    Serialize(postcard::Error),
+}
+
+impl From<SendError> for TypedSendError {
+    fn from(e: SendError) -> Self {
+        Self::Send(e)
+    }
+}
+
+impl core::fmt::Display for TypedSendError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TypedSendError::Send(e) => write!(f, "send error: {e}"),
+            TypedSendError::TooLarge => write!(f, "serialized value too large"),
+            TypedSendError::Serialize(e) => write!(f, "serialization failed: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for TypedSendError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TypedRecvError {
+    Recv(RecvError),
+    /// The message was bigger than the receiver's buffer, and has been discarded.
+    TooLarge {
+        required: usize,
+    },
+    /// The message could not be deserialized into a value.
+    Deserialize(postcard::Error),
+}
+
+impl From<RecvError> for TypedRecvError {
+    fn from(e: RecvError) -> Self {
+        Self::Recv(e)
+    }
+}
+
+impl core::fmt::Display for TypedRecvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TypedRecvError::Recv(e) => write!(f, "receive error: {e}"),
+            TypedRecvError::TooLarge { required } => {
+                write!(f, "message too large, {required} bytes required")
+            }
+            TypedRecvError::Deserialize(e) => write!(f, "deserialization failed: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for TypedRecvError {}
@@ -0,0 +1,124 @@
+//! An [`embedded_io_async::Write`] that frames bytes into messages.
+
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    Notifier, Sender,
+    transport::{MORE_FRAGMENTS, SendError},
+};
+
+/// A writer that collects bytes into a frame and sends it through a [`Sender`], for serializers
+/// that write a packet in several steps.
+///
+/// Bytes are buffered until [`flush`][embedded_io_async::Write::flush], which ends the frame. If
+/// the frame grows beyond `MAX` bytes, the buffered part is sent early as a fragment, so the
+/// other side has to receive frames with
+/// [`Receiver::recv_reassembled`][crate::Receiver::recv_reassembled]. `MAX` must be at most
+/// [`Sender::max_message_len`], or sending fails with [`SendError::MessageTooLarge`].
+///
+/// When the ring is full, the writer waits for room by polling every `retry_interval_us`
+/// microseconds instead of failing with [`SendError::InsufficientCapacity`].
+pub struct SenderWriter<M, D, const ALIGN: usize, const MAX: usize>
+where
+    M: Notifier,
+    D: DelayNs,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    sender: Sender<M, ALIGN>,
+    delay: D,
+    retry_interval_us: u32,
+    buf: [u8; MAX],
+    len: usize,
+    // whether part of the current frame has already been sent
+    fragmented: bool,
+}
+
+impl<M, D, const ALIGN: usize, const MAX: usize> SenderWriter<M, D, ALIGN, MAX>
+where
+    M: Notifier,
+    D: DelayNs,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    pub fn new(sender: Sender<M, ALIGN>, delay: D, retry_interval_us: u32) -> Self {
+        Self {
+            sender,
+            delay,
+            retry_interval_us,
+            buf: [0; MAX],
+            len: 0,
+            fragmented: false,
+        }
+    }
+
+    /// The underlying [`Sender`]. Messages sent through it directly must not be interleaved with
+    /// an unfinished frame.
+    pub fn inner(&mut self) -> &mut Sender<M, ALIGN> {
+        &mut self.sender
+    }
+
+    /// Return the underlying [`Sender`], dropping any bytes that have not been flushed.
+    pub fn into_inner(self) -> Sender<M, ALIGN> {
+        self.sender
+    }
+
+    /// Send the buffered bytes, waiting for room in the ring.
+    async fn send_buffered(&mut self, flags: u8) -> Result<(), SendError> {
+        loop {
+            match self.sender.send_typed(&self.buf[..self.len], flags) {
+                Err(SendError::InsufficientCapacity) => {
+                    self.delay.delay_us(self.retry_interval_us).await
+                }
+                r => {
+                    r?;
+                    self.len = 0;
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl<M, D, const ALIGN: usize, const MAX: usize> embedded_io::ErrorType
+    for SenderWriter<M, D, ALIGN, MAX>
+where
+    M: Notifier,
+    D: DelayNs,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    type Error = SendError;
+}
+
+impl<M, D, const ALIGN: usize, const MAX: usize> embedded_io_async::Write
+    for SenderWriter<M, D, ALIGN, MAX>
+where
+    M: Notifier,
+    D: DelayNs,
+    elain::Align<ALIGN>: elain::Alignment,
+{
+    /// Add bytes to the current frame, sending the buffered part as a fragment first if the
+    /// buffer is full.
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.len == MAX {
+            self.send_buffered(MORE_FRAGMENTS).await?;
+            self.fragmented = true;
+        }
+        let n = buf.len().min(MAX - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&buf[..n]);
+        self.len += n;
+        Ok(n)
+    }
+
+    /// End the current frame and send it. Does nothing if nothing has been written since the last
+    /// flush.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        if self.len == 0 && !self.fragmented {
+            return Ok(());
+        }
+        self.send_buffered(0).await?;
+        self.fragmented = false;
+        Ok(())
+    }
+}